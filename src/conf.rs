@@ -1,10 +1,14 @@
 //! Configuration schema and utilities for Pageshelf.
 
-use clap::crate_version;
+use std::net::IpAddr;
+
+use log::warn;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::{frontend::templates::TemplateServerContext, resolver::DefaultUrlResolver};
+#[cfg(feature = "server")]
+use crate::frontend::templates::TemplateServerContext;
+use crate::{PageLocation, resolver::DefaultUrlResolver};
 
 /* -------------------------------------------------------------------------- */
 /*                              Config structure                              */
@@ -22,11 +26,30 @@ pub enum ServerConfigUpstreamMethod {
     #[serde(rename = "direct")]
     #[default]
     Direct,
+    /// Mirror repositories into local bare clones and read assets from them,
+    /// only asking upstream for what isn't mirrored yet. Requires the `git` feature.
+    #[serde(rename = "mirror")]
+    Mirror,
+}
+
+/// What to do with requests that don't specify a host (e.g. HTTP/1.0 clients).
+#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ServerConfigMissingHost {
+    /// Resolve the request as if it was made to the home domain (`url`).
+    #[serde(rename = "root")]
+    #[default]
+    Root,
+    /// Serve the request from the default user's page.
+    #[serde(rename = "default_user")]
+    DefaultUser,
+    /// Reject the request with a 400 Bad Request.
+    #[serde(rename = "reject")]
+    Reject,
 }
 
 /// Upstream configuration for the server.
 /// This configures where to get page data from.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ServerConfigUpstream {
     /// What type of platform
     #[serde(default)]
@@ -36,12 +59,62 @@ pub struct ServerConfigUpstream {
     pub method: ServerConfigUpstreamMethod,
     #[serde(default = "default_upstream_url")]
     pub url: String,
+    /// The repository to serve if none is specified.
+    #[serde(default = "default_repo")]
+    pub default_repo: String,
     #[serde(default = "default_branch")]
     pub default_branch: String,
     #[serde(default = "default_branches_allowed")]
     pub branches: Vec<String>,
     pub token: Option<String>,
     pub poll_interval: Option<u64>,
+    /// For how many seconds pages removed upstream should respond with 410 Gone instead of 404.
+    /// If unset, removed pages aren't tracked.
+    pub gone_window: Option<u64>,
+    /// Glob pattern of branches to serve as preview pages (e.g. `preview/*`), besides `branches`.
+    pub preview_branches: Option<String>,
+    /// For how many seconds a preview branch may go without commits before it stops being served.
+    /// If unset, previews are served until the branch is deleted.
+    pub preview_idle: Option<u64>,
+    /// The most pages to index; Any more are skipped (with a warning) to bound memory use.
+    pub max_pages: Option<usize>,
+    /// Don't index branches without any files as pages, so they aren't served at all.
+    /// This fetches the file tree of every branch when it changes.
+    #[serde(default = "default_upstream_skip_empty")]
+    pub skip_empty: bool,
+    /// Start another scan right away if one runs past `poll_interval`, rather than skipping
+    /// to the next interval. Scans never overlap either way.
+    #[serde(default = "default_upstream_queue_scans")]
+    pub queue_scans: bool,
+    /// How many times a failed asset fetch should be retried (5xx and network errors only).
+    #[serde(default = "default_upstream_retries")]
+    pub retries: u32,
+    /// Delay before the first retry in milliseconds, doubled for every further attempt.
+    #[serde(default = "default_upstream_retry_delay")]
+    pub retry_delay: u64,
+    /// The most asset fetches to have in flight upstream at once; Excess fetches queue.
+    /// If unset, fetches aren't limited.
+    pub max_fetches: Option<usize>,
+    /// How long a queued fetch may wait for its turn in milliseconds, before failing with 503.
+    #[serde(default = "default_upstream_fetch_timeout")]
+    pub fetch_timeout: u64,
+    /// How long a request to upstream may take in milliseconds, before failing with 503.
+    /// Keeps a hung upstream from tying up the server's workers.
+    #[serde(default = "default_upstream_request_timeout")]
+    pub request_timeout: u64,
+    /// How long all upstream calls of a single client request (fallbacks included) may take
+    /// in milliseconds, before failing with 504. If unset, only each call's own timeout applies.
+    pub request_budget: Option<u64>,
+    /// How long to pause upstream requests in seconds after being rate limited (`429`).
+    /// Pages that need upstream get `503` with `Retry-After` meanwhile, and scans are skipped.
+    #[serde(default = "default_upstream_rate_limit_pause")]
+    pub rate_limit_pause: u64,
+    /// User-Agent to send with upstream requests, so forge admins can tell Pageshelf apart.
+    /// Defaults to `Pageshelf/<version> (+<url>)`.
+    pub user_agent: Option<String>,
+    /// Directory to keep repository mirrors in, when using the `mirror` method.
+    #[serde(default = "default_upstream_mirror_dir")]
+    pub mirror_dir: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -53,7 +126,7 @@ pub struct ServerConfigSecurity {
 }
 
 /// Cache configuration for the server
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ServerConfigCache {
     /// Should Cache be used?
     #[serde(default = "default_cache_enabled")]
@@ -67,6 +140,102 @@ pub struct ServerConfigCache {
     /// How long should cached assets live in Cache?
     #[serde(default = "default_cache_ttl")]
     pub ttl: Option<u32>,
+    /// Compress cached assets (with zstd), trading a little CPU for less memory in the cache.
+    #[serde(default = "default_cache_compress")]
+    pub compress: bool,
+    /// Assets smaller than this (in bytes) are cached uncompressed, as it wouldn't pay off.
+    #[serde(default = "default_cache_compress_min_size")]
+    pub compress_min_size: usize,
+}
+
+/// Single-page app configuration for the server.
+/// SPA pages serve their root `index.html` for unknown paths, so client-side routing works.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerConfigSpa {
+    /// Treat every page as a single-page app.
+    #[serde(default = "default_spa_always")]
+    pub always: bool,
+    /// Treat pages that contain a `/.spa` file as single-page apps.
+    #[serde(default = "default_spa_marker")]
+    pub marker: bool,
+    /// Also fall back for paths that look like files (have an extension).
+    /// Disabled by default, so missing assets still 404.
+    #[serde(default = "default_spa_fallback_files")]
+    pub fallback_files: bool,
+}
+
+/// Per-page configuration, read from a `/.pageshelf.toml` (or `.yaml`) file in the page.
+/// Pages may only change the settings allowed here, within the bounds set here.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerConfigPageConfig {
+    /// Read page configuration files. This costs an extra asset fetch per page version.
+    #[serde(default = "default_page_config_enabled")]
+    pub enabled: bool,
+    /// Let pages make themselves single-page apps. Pages can always opt out.
+    #[serde(default = "default_page_config_spa")]
+    pub spa: bool,
+    /// The longest (in seconds) pages may let clients cache their assets for.
+    #[serde(default = "default_page_config_max_age")]
+    pub max_age: u32,
+}
+
+/// `_redirects` configuration for the server.
+/// Pages can redirect or rewrite paths that don't match an asset with a `_redirects` file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerConfigRedirects {
+    /// Read `_redirects` files. This costs an extra asset fetch per page version.
+    #[serde(default = "default_redirects_enabled")]
+    pub enabled: bool,
+    /// The most `200` rewrites to follow for a request, before responding `508 Loop Detected`.
+    #[serde(default = "default_redirects_max_rewrites")]
+    pub max_rewrites: usize,
+}
+
+/// Image negotiation configuration for the server.
+/// Only takes effect when built with the `images` feature.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerConfigImages {
+    /// Serve PNG and JPEG images as WebP to clients that accept it, if that's smaller.
+    /// Converting costs CPU, so it's off by default.
+    #[serde(default = "default_images_webp")]
+    pub webp: bool,
+    /// The most converted images to keep in memory.
+    #[serde(default = "default_images_cache_size")]
+    pub cache_size: usize,
+}
+
+/// Cross-origin resource sharing (CORS) configuration for the server.
+/// Lets scripts on other origins fetch pages, such as JSON APIs hosted as a page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerConfigCors {
+    /// Send CORS headers and answer preflight (`OPTIONS`) requests.
+    #[serde(default = "default_cors_enabled")]
+    pub enabled: bool,
+    /// Origins allowed to fetch pages (e.g. `https://app.example.domain`), or `*` for any.
+    #[serde(default = "default_cors_allowed_origins")]
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in cross-origin requests.
+    #[serde(default = "default_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests, or `*` for any.
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    /// Response headers that cross-origin scripts may read, beyond the safelisted ones.
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+    /// How long (in seconds) clients may cache preflight responses.
+    pub max_age: Option<usize>,
+}
+
+/// A synthetic asset to be served on every page that doesn't provide it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerConfigInjectAsset {
+    /// The path in the page to serve the asset at (e.g. `/.well-known/security.txt`)
+    pub path: String,
+    /// Inline content of the asset. Takes priority over `file`.
+    pub content: Option<String>,
+    /// Path to a local file to load the asset's content from at startup.
+    pub file: Option<String>,
 }
 
 /// Aggregate configuration of the server (Contains all other configs)
@@ -83,8 +252,123 @@ pub struct ServerConfig {
     pub pages_urls: Option<Vec<Url>>,
     #[serde(default = "default_user")]
     pub default_user: String,
+    /// Serve the built-in landing page at the root (`/`) of the home domain, rather than the
+    /// default user's page. Either way, the default user's page stays available at its own
+    /// path and subdomain.
+    #[serde(default = "default_root_prefers_builtin")]
+    pub root_prefers_builtin: bool,
     #[serde(default = "default_domains_allowed")]
     pub allow_domains: bool,
+    /// Serve other branches of a custom domain's page at `<branch>.<domain>`, where the
+    /// domain is declared by the page's default branch. A domain declared directly by a
+    /// `.domain` file always takes precedence over being read as a branch.
+    #[serde(default = "default_domain_branches")]
+    pub domain_branches: bool,
+    /// Only respond to the home domain, page domains, built-in hosts and custom domains claimed
+    /// by a page; Any other host gets `421 Misdirected Request` instead of the built-in pages.
+    #[serde(default = "default_strict_host")]
+    pub strict_host: bool,
+    /// Page (`owner/name:branch`) to serve for custom domains that no page claims, such as a
+    /// "domain not configured" site. Only applies if `allow_domains` is enabled, and takes
+    /// priority over `strict_host` for those domains.
+    pub domain_fallback: Option<String>,
+    /// Logging level (`off`, `error`, `warn`, `info`, `debug` or `trace`).
+    /// The `--log-level` flag and `PAGESHELF_LOG_LEVEL` take priority over this.
+    pub log_level: Option<String>,
+    /// File to also write logs to (appended to, without colors).
+    pub log_file: Option<String>,
+    /// Whether to color logs on stdout. If unset, colors are only used when stdout is a terminal.
+    pub log_color: Option<bool>,
+    /// HTML to insert before `</body>` in every served HTML asset (e.g. an analytics script).
+    /// This modifies page content, so nothing is injected unless it's set.
+    pub html_snippet: Option<String>,
+    /// Show a "coming soon" page instead of a 404 for owners that exist, but have no page.
+    #[serde(default = "default_coming_soon")]
+    pub coming_soon: bool,
+    /// Serve custom 404 pages with 200 OK instead of 404 Not Found ("soft 404").
+    /// Search engines may index soft 404s as real content, so this is off by default.
+    #[serde(default = "default_soft_404")]
+    pub soft_404: bool,
+    /// How to handle requests that don't specify a host.
+    #[serde(default)]
+    pub missing_host: ServerConfigMissingHost,
+    /// Addresses of reverse proxies whose `X-Forwarded-Host`/`X-Forwarded-Proto` headers are trusted.
+    /// If empty, those headers are ignored, as any client could spoof them.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Hostnames (or IPs) that always serve the built-in pages, never a page or owner.
+    /// Useful for internal names that load balancers and health checks reach the server by.
+    #[serde(default)]
+    pub builtin_hosts: Vec<String>,
+    /// Bearer token required by the admin endpoints (`/.pageshelf/stats`, `/.pageshelf/resolve`
+    /// and `/.pageshelf/domains`).
+    /// If unset, they're disabled and respond 404.
+    pub admin_token: Option<String>,
+    /// Token that lets requests pick the branch of a page to serve with the `X-Pageshelf-Branch`
+    /// header, sent as `X-Pageshelf-Token`. If unset, the header is ignored, as it bypasses
+    /// serving branches only at their own URLs.
+    pub preview_token: Option<String>,
+    /// Request header with the client's country code (e.g. `CF-IPCountry`, set by Cloudflare),
+    /// which pages can serve variants of assets by. Requires the `geo` feature.
+    pub geo_header: Option<String>,
+    /// Scheme (`http` or `https`) to use for absolute URLs the server generates.
+    /// Defaults to the scheme of `url`, or `http` if that's unset.
+    pub public_scheme: Option<String>,
+    /// File to serve at the root of a page if it has no `index.html` (e.g. `home.html`).
+    /// Only applies to the page root, not to subdirectories.
+    pub root_asset: Option<String>,
+    /// Serve a path's index (e.g. `/docs/index.html`) over an asset at the same path (`/docs`)
+    /// if a page has both. Otherwise, the asset is served.
+    #[serde(default = "default_prefer_index")]
+    pub prefer_index: bool,
+    /// Serve a path's index (`/docs/index.html`) if there's no asset at the path (`/docs`).
+    /// Disabling it saves an upstream lookup per missing path, for pages that link to their
+    /// indexes directly; The page root always serves its index.
+    #[serde(default = "default_index_fallback")]
+    pub index_fallback: bool,
+    /// Lowercase owner and repository names (not branches) before looking up pages, so
+    /// `/Owner/Repo` serves `owner/repo`. Only enable it if the forge treats names
+    /// case-insensitively (as Forgejo does); Otherwise, pages with uppercase names become unreachable.
+    #[serde(default = "default_lowercase_names")]
+    pub lowercase_names: bool,
+    /// Pattern for the repository serving an owner's root site, with `<owner>` replaced by the
+    /// owner's name (e.g. `<owner>.pages.example.domain`). If unset, the default repository is used.
+    pub owner_repo: Option<String>,
+    /// Separator between the repository and branch in path URLs (`/owner/repo:branch`).
+    #[serde(default = "default_branch_separator")]
+    pub branch_separator: char,
+    /// Pages (`owner/name:branch`) to keep serving from memory while upstream is failing.
+    /// Their assets are kept as they're served, for as long as the page's version doesn't change.
+    #[serde(default)]
+    pub pinned: Vec<String>,
+    /// Glob patterns of asset paths to never serve, responding 404. They match within any
+    /// directory (`.env` denies `/docs/.env`), unless they start with `/` to only match from the
+    /// page root. Keeps things like repository metadata and secrets from leaking.
+    #[serde(default = "default_deny_paths")]
+    pub deny_paths: Vec<String>,
+    /// Files (relative to the page root) that configure how a page is served, such as `.domain`.
+    /// They're still read by the server, but respond 404 when requested directly.
+    #[serde(default = "default_control_files")]
+    pub control_files: Vec<String>,
+    /// Rewrite root-relative links (`/style.css`) in HTML of pages served under a path
+    /// (`/owner/repo/`), so they point within the page rather than the server root.
+    /// This modifies page content, so it's off by default.
+    #[serde(default = "default_rewrite_path_links")]
+    pub rewrite_path_links: bool,
+    /// Send `X-Content-Type-Options: nosniff` with served assets.
+    /// The server determines each asset's type itself, so browsers shouldn't second-guess it;
+    /// Sniffing could otherwise render user-uploaded content as HTML or scripts (MIME confusion).
+    #[serde(default = "default_nosniff")]
+    pub nosniff: bool,
+    /// Detect the type of extensionless assets from their content (e.g. HTML or PNG), if their
+    /// provider doesn't know it. Requires the `sniff` feature. Off by default, as it can render
+    /// content that would otherwise only be downloaded.
+    #[serde(default = "default_sniff")]
+    pub sniff: bool,
+    /// Serve precompressed variants of assets (`/app.js.zst`, `.br` or `.gz`) to clients that
+    /// accept their encoding. Off by default, as it costs a lookup per accepted encoding.
+    #[serde(default = "default_precompressed")]
+    pub precompressed: bool,
 
     // Specialized
     #[serde(default = "default_security")]
@@ -92,28 +376,130 @@ pub struct ServerConfig {
     pub upstream: ServerConfigUpstream,
     #[serde(default = "default_cache")]
     pub cache: ServerConfigCache,
+    #[serde(default = "default_spa")]
+    pub spa: ServerConfigSpa,
+    #[serde(default = "default_page_config")]
+    pub page_config: ServerConfigPageConfig,
+    #[serde(default = "default_redirects")]
+    pub redirects: ServerConfigRedirects,
+    #[serde(default = "default_images")]
+    pub images: ServerConfigImages,
+    #[serde(default = "default_cors")]
+    pub cors: ServerConfigCors,
+    #[serde(default)]
+    pub inject: Vec<ServerConfigInjectAsset>,
 }
 
 impl ServerConfig {
+    /// The scheme that absolute URLs generated by the server should use.
+    pub fn public_scheme(&self) -> &str {
+        match &self.public_scheme {
+            Some(v) => v.as_str(),
+            None => self.url.as_ref().map(|v| v.scheme()).unwrap_or("http"),
+        }
+    }
+
+    /// The home URL of the server, using the public scheme.
+    pub fn public_url(&self) -> Option<Url> {
+        self.url.as_ref().map(|v| {
+            let mut url = v.clone();
+            if url.set_scheme(self.public_scheme()).is_err() {
+                warn!(
+                    "Failed to apply public scheme \"{}\" to {}",
+                    self.public_scheme(),
+                    v
+                );
+            }
+            url
+        })
+    }
+
+    /// The page to serve for custom domains that no page claims, if one is configured.
+    ///
+    /// An entry that can't be parsed is logged, and treated as if there was none.
+    pub fn domain_fallback_page(&self) -> Option<PageLocation> {
+        let entry = self.domain_fallback.as_deref()?;
+        match PageLocation::parse_with_defaults(
+            entry,
+            &self.upstream.default_repo,
+            &self.upstream.default_branch,
+        ) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("Failed to parse domain fallback page \"{}\": {}", entry, e);
+                None
+            }
+        }
+    }
+
+    /// The settings that differ from another configuration, but that only take effect on a restart
+    /// (e.g. because the page source was built from them).
+    ///
+    /// # Returns
+    ///
+    /// - `Vec<&'static str>` - The names of the settings that differ.
+    pub fn restart_required(&self, other: &ServerConfig) -> Vec<&'static str> {
+        [
+            ("port", self.port != other.port),
+            ("log_level", self.log_level != other.log_level),
+            ("log_file", self.log_file != other.log_file),
+            ("log_color", self.log_color != other.log_color),
+            ("allow_domains", self.allow_domains != other.allow_domains),
+            ("lowercase_names", self.lowercase_names != other.lowercase_names),
+            ("pinned", self.pinned != other.pinned),
+            ("inject", self.inject != other.inject),
+            ("upstream", self.upstream != other.upstream),
+            ("cache", self.cache != other.cache),
+            ("cors", self.cors != other.cors),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    /// The User-Agent to send with upstream requests.
+    pub fn upstream_user_agent(&self) -> String {
+        if let Some(v) = &self.upstream.user_agent {
+            return v.clone();
+        }
+        match self.public_url() {
+            Some(url) => format!("Pageshelf/{} (+{})", clap::crate_version!(), url),
+            None => format!("Pageshelf/{}", clap::crate_version!()),
+        }
+    }
+
+    #[cfg(feature = "server")]
     pub fn template_server_context(&self) -> TemplateServerContext {
         TemplateServerContext {
             name: self.name.to_string(),
             about: self.description.to_string(),
-            url: self.url.as_ref().map(|v| v.as_str().to_string()),
+            url: self.public_url().map(|v| v.as_str().to_string()),
+            scheme: self.public_scheme().to_string(),
             icon_url: Some("/pages_favicon.webp".to_string()),
             default_branch: self.upstream.default_branch.clone(),
-            version: crate_version!(),
+            version: clap::crate_version!(),
         }
     }
 
     pub fn url_resolver(&self) -> DefaultUrlResolver {
-        DefaultUrlResolver::new(
+        let resolver = DefaultUrlResolver::new(
             self.url.clone(),
             self.pages_urls.clone(),
-            "pages".to_string(),
-            "pages".to_string(),
+            self.upstream.default_repo.clone(),
+            self.upstream.default_branch.clone(),
             self.allow_domains,
         )
+        .with_branch_separator(self.branch_separator)
+        .with_builtin_hosts(self.builtin_hosts.clone())
+        .with_strict_host(self.strict_host)
+        .with_default_user(self.default_user.clone())
+        .with_root_prefers_builtin(self.root_prefers_builtin)
+        .with_lowercase_names(self.lowercase_names);
+        match &self.owner_repo {
+            Some(pattern) => resolver.with_owner_repo(pattern.clone()),
+            None => resolver,
+        }
     }
 }
 
@@ -133,7 +519,37 @@ impl Default for ServerConfig {
             pages_urls: None,
             port: default_port(),
             default_user: default_user(),
+            root_prefers_builtin: default_root_prefers_builtin(),
             allow_domains: default_domains_allowed(),
+            domain_branches: default_domain_branches(),
+            strict_host: default_strict_host(),
+            domain_fallback: None,
+            log_level: None,
+            log_file: None,
+            log_color: None,
+            html_snippet: None,
+            coming_soon: default_coming_soon(),
+            soft_404: default_soft_404(),
+            missing_host: ServerConfigMissingHost::default(),
+            trusted_proxies: Vec::new(),
+            builtin_hosts: Vec::new(),
+            admin_token: None,
+            preview_token: None,
+            geo_header: None,
+            public_scheme: None,
+            root_asset: None,
+            prefer_index: default_prefer_index(),
+            index_fallback: default_index_fallback(),
+            lowercase_names: default_lowercase_names(),
+            owner_repo: None,
+            branch_separator: default_branch_separator(),
+            pinned: Vec::new(),
+            deny_paths: default_deny_paths(),
+            control_files: default_control_files(),
+            rewrite_path_links: default_rewrite_path_links(),
+            nosniff: default_nosniff(),
+            sniff: default_sniff(),
+            precompressed: default_precompressed(),
 
             // Specialized
             security: ServerConfigSecurity {
@@ -145,16 +561,74 @@ impl Default for ServerConfig {
                 r#type: ServerConfigUpstreamType::Forgejo,
                 method: ServerConfigUpstreamMethod::Direct,
                 poll_interval: None,
+                gone_window: None,
+                preview_branches: None,
+                preview_idle: None,
+                max_pages: None,
+                retries: default_upstream_retries(),
+                retry_delay: default_upstream_retry_delay(),
+                max_fetches: None,
+                fetch_timeout: default_upstream_fetch_timeout(),
+                request_timeout: default_upstream_request_timeout(),
+                request_budget: None,
+                rate_limit_pause: default_upstream_rate_limit_pause(),
+                user_agent: None,
+                mirror_dir: default_upstream_mirror_dir(),
+                skip_empty: default_upstream_skip_empty(),
+                queue_scans: default_upstream_queue_scans(),
                 url: "".to_string(),
+                default_repo: default_repo(),
                 default_branch: default_branch(),
                 branches: Vec::new(),
                 token: None,
             },
             cache: default_cache(),
+            spa: default_spa(),
+            page_config: default_page_config(),
+            redirects: default_redirects(),
+            images: default_images(),
+            cors: default_cors(),
+            inject: Vec::new(),
         }
     }
 }
 
+fn default_deny_paths() -> Vec<String> {
+    [".git", ".git/*", ".env", ".domain"]
+        .iter()
+        .map(|v| v.to_string())
+        .collect()
+}
+
+fn default_control_files() -> Vec<String> {
+    [
+        ".domain",
+        "_redirects",
+        "_headers",
+        ".pageshelf.toml",
+        ".pageshelf.yaml",
+    ]
+    .iter()
+    .map(|v| v.to_string())
+    .collect()
+}
+
+fn default_rewrite_path_links() -> bool {
+    false
+}
+
+fn default_nosniff() -> bool {
+    true
+}
+
+fn default_sniff() -> bool {
+    false
+}
+
+fn default_precompressed() -> bool {
+    false
+}
+
 fn default_port() -> u16 {
     8080
 }
@@ -171,6 +645,42 @@ fn default_upstream_url() -> String {
     "https://codeberg.org".to_string()
 }
 
+fn default_upstream_retries() -> u32 {
+    2
+}
+
+fn default_upstream_retry_delay() -> u64 {
+    100
+}
+
+fn default_upstream_fetch_timeout() -> u64 {
+    10000
+}
+
+fn default_upstream_request_timeout() -> u64 {
+    30000
+}
+
+fn default_upstream_rate_limit_pause() -> u64 {
+    60
+}
+
+fn default_upstream_mirror_dir() -> String {
+    "mirrors".to_string()
+}
+
+fn default_upstream_queue_scans() -> bool {
+    false
+}
+
+fn default_upstream_skip_empty() -> bool {
+    false
+}
+
+fn default_repo() -> String {
+    "pages".to_string()
+}
+
 fn default_branch() -> String {
     "pages".to_string()
 }
@@ -195,12 +705,18 @@ fn default_user() -> String {
     "admin".to_string()
 }
 
+fn default_root_prefers_builtin() -> bool {
+    true
+}
+
 fn default_cache() -> ServerConfigCache {
     ServerConfigCache {
         enabled: default_cache_enabled(),
         address: default_cache_address(),
         port: default_cache_port(),
         ttl: default_cache_ttl(),
+        compress: default_cache_compress(),
+        compress_min_size: default_cache_compress_min_size(),
     }
 }
 
@@ -220,6 +736,248 @@ fn default_cache_ttl() -> Option<u32> {
     None
 }
 
+fn default_cache_compress() -> bool {
+    false
+}
+
+fn default_cache_compress_min_size() -> usize {
+    1024
+}
+
+fn default_spa() -> ServerConfigSpa {
+    ServerConfigSpa {
+        always: default_spa_always(),
+        marker: default_spa_marker(),
+        fallback_files: default_spa_fallback_files(),
+    }
+}
+
+fn default_spa_always() -> bool {
+    false
+}
+
+fn default_spa_marker() -> bool {
+    false
+}
+
+fn default_spa_fallback_files() -> bool {
+    false
+}
+
+fn default_page_config() -> ServerConfigPageConfig {
+    ServerConfigPageConfig {
+        enabled: default_page_config_enabled(),
+        spa: default_page_config_spa(),
+        max_age: default_page_config_max_age(),
+    }
+}
+
+fn default_page_config_enabled() -> bool {
+    false
+}
+
+fn default_page_config_spa() -> bool {
+    true
+}
+
+fn default_page_config_max_age() -> u32 {
+    86400
+}
+
+fn default_redirects() -> ServerConfigRedirects {
+    ServerConfigRedirects {
+        enabled: default_redirects_enabled(),
+        max_rewrites: default_redirects_max_rewrites(),
+    }
+}
+
+fn default_redirects_enabled() -> bool {
+    false
+}
+
+fn default_redirects_max_rewrites() -> usize {
+    5
+}
+
+fn default_images() -> ServerConfigImages {
+    ServerConfigImages {
+        webp: default_images_webp(),
+        cache_size: default_images_cache_size(),
+    }
+}
+
+fn default_images_webp() -> bool {
+    false
+}
+
+fn default_images_cache_size() -> usize {
+    256
+}
+
+fn default_cors() -> ServerConfigCors {
+    ServerConfigCors {
+        enabled: default_cors_enabled(),
+        allowed_origins: default_cors_allowed_origins(),
+        allowed_methods: default_cors_allowed_methods(),
+        allowed_headers: Vec::new(),
+        expose_headers: Vec::new(),
+        max_age: None,
+    }
+}
+
+fn default_cors_enabled() -> bool {
+    false
+}
+
+fn default_cors_allowed_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "HEAD".to_string()]
+}
+
 fn default_domains_allowed() -> bool {
     false
 }
+
+fn default_domain_branches() -> bool {
+    false
+}
+
+fn default_strict_host() -> bool {
+    false
+}
+
+fn default_soft_404() -> bool {
+    false
+}
+
+fn default_prefer_index() -> bool {
+    false
+}
+
+fn default_index_fallback() -> bool {
+    true
+}
+
+fn default_lowercase_names() -> bool {
+    false
+}
+
+fn default_branch_separator() -> char {
+    crate::BRANCH_SEPARATOR
+}
+
+fn default_coming_soon() -> bool {
+    false
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                    Tests                                   */
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use url::Url;
+
+    use super::ServerConfig;
+    use crate::{
+        PageAssetLocation, PageLocation,
+        resolver::{UrlResolution, UrlResolver},
+    };
+
+    /// The public scheme should follow the home URL, unless overridden
+    #[test]
+    fn public_scheme() {
+        let mut config = ServerConfig::default();
+        assert_eq!(config.public_scheme(), "http");
+
+        config.url = Some(Url::from_str("https://example.domain").unwrap());
+        assert_eq!(config.public_scheme(), "https");
+
+        config.url = Some(Url::from_str("http://example.domain").unwrap());
+        config.public_scheme = Some("https".to_string());
+        assert_eq!(config.public_scheme(), "https");
+        assert_eq!(
+            config.public_url().unwrap().as_str(),
+            "https://example.domain/"
+        );
+    }
+
+    /// Only settings the running server can't apply should call for a restart
+    #[test]
+    fn restart_required() {
+        let config = ServerConfig::default();
+        let mut other = config.clone();
+        other.nosniff = !config.nosniff;
+        other.deny_paths.push("/secret".to_string());
+        other.html_snippet = Some("<script></script>".to_string());
+        assert!(config.restart_required(&other).is_empty());
+
+        other.port += 1;
+        other.upstream.url = "https://other.domain".to_string();
+        assert_eq!(config.restart_required(&other), vec!["port", "upstream"]);
+
+        // The page source keeps looking pages up as it was started with
+        other.lowercase_names = !config.lowercase_names;
+        assert_eq!(
+            config.restart_required(&other),
+            vec!["port", "lowercase_names", "upstream"]
+        );
+
+        // Pinned pages are only read when the page source is built
+        other.pinned.push("owner/pages:pages".to_string());
+        assert_eq!(
+            config.restart_required(&other),
+            vec!["port", "lowercase_names", "pinned", "upstream"]
+        );
+    }
+
+    /// Upstream requests should identify Pageshelf (and where it's hosted), unless overridden
+    #[test]
+    fn upstream_user_agent() {
+        let mut config = ServerConfig::default();
+        let version = clap::crate_version!();
+        assert_eq!(
+            config.upstream_user_agent(),
+            format!("Pageshelf/{}", version)
+        );
+
+        config.url = Some(Url::from_str("https://example.domain").unwrap());
+        assert_eq!(
+            config.upstream_user_agent(),
+            format!("Pageshelf/{} (+https://example.domain/)", version)
+        );
+
+        config.upstream.user_agent = Some("nya".to_string());
+        assert_eq!(config.upstream_user_agent(), "nya");
+    }
+
+    /// The resolver should use the configured defaults instead of "pages"
+    #[test]
+    fn url_resolver_defaults() {
+        let mut config = ServerConfig {
+            url: Some(Url::from_str("http://example.domain").unwrap()),
+            ..ServerConfig::default()
+        };
+        config.upstream.default_repo = "site".to_string();
+        config.upstream.default_branch = "main".to_string();
+
+        assert_eq!(
+            config
+                .url_resolver()
+                .resolve(Url::from_str("http://example.domain/nya").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "site".to_string(),
+                    branch: "main".to_string()
+                },
+                asset: "/".to_string()
+            })
+        );
+    }
+}
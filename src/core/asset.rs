@@ -1,6 +1,6 @@
-use std::path::Path;
+use std::{fmt::Display, path::Path};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AssetError {
     /// The desired asset could not be found.
     NotFound,
@@ -10,8 +10,25 @@ pub enum AssetError {
     ProviderError,
     /// Unable to interpret the data of an asset in the desired manner
     CannotInterpret,
+    /// The asset exists, but is larger than what may be served (or cached).
+    TooLarge,
 }
 
+/// Allows displaying Asset Errors in a human readable format
+impl Display for AssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("Not found"),
+            Self::Corrupted => f.write_str("Corrupted"),
+            Self::ProviderError => f.write_str("Provider error"),
+            Self::CannotInterpret => f.write_str("Cannot interpret"),
+            Self::TooLarge => f.write_str("Too large"),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
 /// Represents a file that can be found in a page.
 pub trait Asset {
     /// Attempts to get the MIME type of this asset.
@@ -117,6 +134,62 @@ pub trait AssetSource {
     fn total_bytes(&self) -> Option<u32> {
         None
     }
+    /// Checks whether or not an asset exists, without necessarily fetching its content.
+    ///
+    /// By default, the asset is fetched; Sources that can tell more cheaply
+    /// (e.g. from a listing of their files) should override this.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<bool, AssetError>` - Whether or not the asset exists, or the error
+    ///   that kept it from being checked (other than it being missing).
+    #[allow(async_fn_in_trait)]
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        match self.get_asset(path).await {
+            Ok(_) => Ok(true),
+            Err(AssetError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+    /// Returns a fingerprint of an asset's content, for content-addressed URLs.
+    ///
+    /// The fingerprint changes whenever the content does, so assets referenced by it
+    /// can be cached indefinitely. By default, it's a hash of the asset's bytes;
+    /// Sources that already know a content hash (e.g. a git blob SHA) should override this.
+    ///
+    /// # Returns
+    ///
+    /// - `Option<String>` - The fingerprint, or None if the asset couldn't be found.
+    #[allow(async_fn_in_trait)]
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        match self.get_asset(path).await {
+            Ok(v) => Some(content_hash(v.bytes())),
+            Err(_) => None,
+        }
+    }
+    /// Returns the type this source gives an asset, if it's known without fetching the asset.
+    ///
+    /// By default, it isn't; Sources that keep types alongside their assets should override this.
+    ///
+    /// # Returns
+    ///
+    /// - `Option<String>` - The MIME type, or None if it isn't known (or the asset is missing).
+    #[allow(async_fn_in_trait)]
+    async fn asset_mime_type(&self, _path: &Path) -> Option<String> {
+        None
+    }
+}
+
+/// Hashes bytes into a stable hex fingerprint (64-bit FNV-1a).
+///
+/// Not cryptographic; It only has to change when the content does.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
 }
 
 /// A trait that enables manipulation of assets on its implementors.
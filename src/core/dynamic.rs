@@ -0,0 +1,298 @@
+//! Object-safe counterparts of the Page Source traits, for dynamic dispatch.
+//!
+//! [`PageSource`] and [`Page`] return `impl Trait`, so they can't be made into trait objects.
+//! Every Page Source is also a [`DynPageSource`] (with boxed futures, pages and assets),
+//! and a boxed [`DynPageSource`] is a [`PageSource`] again, so one can be picked at runtime
+//! and still be served like any other.
+
+use std::{collections::HashMap, future::Future, path::Path, pin::Pin, time::Duration};
+
+use tokio::sync::broadcast;
+
+use crate::provider::memory::MemoryAsset;
+
+use super::{
+    Asset, AssetError, AssetSource, DomainClaims, Page, PageError, PageLocation, PageSource,
+    ScanStats,
+};
+
+/// A heap-allocated future, as returned by the dynamic traits.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// A Page Source chosen at runtime.
+pub type BoxedPageSource = Box<dyn DynPageSource + Send + Sync>;
+
+/// An object-safe [`Page`].
+pub trait DynPage {
+    fn name(&self) -> &str;
+    fn branch(&self) -> &str;
+    fn owner(&self) -> &str;
+    fn version(&self) -> &str;
+    fn total_bytes(&self) -> Option<u32>;
+
+    /// Gets an asset, loaded into memory.
+    fn get_asset_dyn<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxFuture<'a, Result<MemoryAsset, AssetError>>;
+
+    /// Gets the content fingerprint of an asset.
+    fn asset_hash_dyn<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Option<String>>;
+
+    /// Checks whether or not an asset exists.
+    fn asset_exists_dyn<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool, AssetError>>;
+
+    /// Gets the type of an asset, if it's known without fetching it.
+    fn asset_mime_type_dyn<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Option<String>>;
+}
+
+impl<P: Page> DynPage for P {
+    fn name(&self) -> &str {
+        Page::name(self)
+    }
+
+    fn branch(&self) -> &str {
+        Page::branch(self)
+    }
+
+    fn owner(&self) -> &str {
+        Page::owner(self)
+    }
+
+    fn version(&self) -> &str {
+        Page::version(self)
+    }
+
+    fn total_bytes(&self) -> Option<u32> {
+        AssetSource::total_bytes(self)
+    }
+
+    fn get_asset_dyn<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxFuture<'a, Result<MemoryAsset, AssetError>> {
+        Box::pin(async move { self.get_asset(path).await.map(MemoryAsset::from_asset) })
+    }
+
+    fn asset_hash_dyn<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Option<String>> {
+        Box::pin(self.asset_hash(path))
+    }
+
+    fn asset_exists_dyn<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool, AssetError>> {
+        Box::pin(self.asset_exists(path))
+    }
+
+    fn asset_mime_type_dyn<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Option<String>> {
+        Box::pin(self.asset_mime_type(path))
+    }
+}
+
+impl<'p> Page for Box<dyn DynPage + 'p> {
+    fn name(&self) -> &str {
+        DynPage::name(self.as_ref())
+    }
+
+    fn branch(&self) -> &str {
+        DynPage::branch(self.as_ref())
+    }
+
+    fn owner(&self) -> &str {
+        DynPage::owner(self.as_ref())
+    }
+
+    fn version(&self) -> &str {
+        DynPage::version(self.as_ref())
+    }
+}
+
+impl<'p> AssetSource for Box<dyn DynPage + 'p> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+        self.as_ref().get_asset_dyn(path).await
+    }
+
+    fn total_bytes(&self) -> Option<u32> {
+        DynPage::total_bytes(self.as_ref())
+    }
+
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        self.as_ref().asset_hash_dyn(path).await
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        self.as_ref().asset_exists_dyn(path).await
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        self.as_ref().asset_mime_type_dyn(path).await
+    }
+}
+
+/// An object-safe [`PageSource`].
+pub trait DynPageSource {
+    fn page_at_dyn(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> BoxFuture<'_, Result<Box<dyn DynPage + '_>, PageError>>;
+
+    fn pages_dyn(&self) -> BoxFuture<'_, Result<Vec<Box<dyn DynPage + '_>>, PageError>>;
+
+    fn default_branch_dyn(&self) -> &str;
+
+    fn subscribe_removed_dyn(&self) -> Option<broadcast::Receiver<PageLocation>>;
+
+    fn retry_after_dyn(&self) -> Option<Duration>;
+
+    fn scan_stats_dyn(&self) -> BoxFuture<'_, Option<ScanStats>>;
+
+    fn domain_index_dyn(&self) -> BoxFuture<'_, Option<HashMap<String, DomainClaims>>>;
+
+    fn owner_exists_dyn<'a>(&'a self, owner: &'a str) -> BoxFuture<'a, Result<bool, PageError>>;
+
+    fn find_by_domains_dyn(
+        &self,
+        domains: Vec<String>,
+    ) -> BoxFuture<'_, Result<Box<dyn DynPage + '_>, PageError>>;
+}
+
+impl<PS: PageSource> DynPageSource for PS {
+    fn page_at_dyn(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> BoxFuture<'_, Result<Box<dyn DynPage + '_>, PageError>> {
+        Box::pin(async move {
+            match self.page_at(owner, name, branch).await {
+                Ok(page) => Ok(Box::new(page) as Box<dyn DynPage>),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn pages_dyn(&self) -> BoxFuture<'_, Result<Vec<Box<dyn DynPage + '_>>, PageError>> {
+        Box::pin(async move {
+            match self.pages().await {
+                Ok(pages) => Ok(pages
+                    .map(|page| Box::new(page) as Box<dyn DynPage>)
+                    .collect()),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn default_branch_dyn(&self) -> &str {
+        self.default_branch()
+    }
+
+    fn subscribe_removed_dyn(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.subscribe_removed()
+    }
+
+    fn retry_after_dyn(&self) -> Option<Duration> {
+        self.retry_after()
+    }
+
+    fn scan_stats_dyn(&self) -> BoxFuture<'_, Option<ScanStats>> {
+        Box::pin(self.scan_stats())
+    }
+
+    fn domain_index_dyn(&self) -> BoxFuture<'_, Option<HashMap<String, DomainClaims>>> {
+        Box::pin(self.domain_index())
+    }
+
+    fn owner_exists_dyn<'a>(&'a self, owner: &'a str) -> BoxFuture<'a, Result<bool, PageError>> {
+        Box::pin(self.owner_exists(owner))
+    }
+
+    fn find_by_domains_dyn(
+        &self,
+        domains: Vec<String>,
+    ) -> BoxFuture<'_, Result<Box<dyn DynPage + '_>, PageError>> {
+        Box::pin(async move {
+            match self.find_by_domains(domains).await {
+                Ok(page) => Ok(Box::new(page) as Box<dyn DynPage>),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+impl<'s> PageSource for Box<dyn DynPageSource + Send + Sync + 's> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        self.as_ref().page_at_dyn(owner, name, branch).await
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        Ok(self.as_ref().pages_dyn().await?.into_iter())
+    }
+
+    fn default_branch(&self) -> &str {
+        self.as_ref().default_branch_dyn()
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.as_ref().subscribe_removed_dyn()
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.as_ref().retry_after_dyn()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.as_ref().scan_stats_dyn().await
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        self.as_ref().domain_index_dyn().await
+    }
+
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        self.as_ref().owner_exists_dyn(owner).await
+    }
+
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+        self.as_ref().find_by_domains_dyn(domains).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        Asset, AssetSource, BoxedPageSource, Page, PageSource, PageSourceFactory,
+        provider::testing::create_example_provider_factory,
+    };
+
+    /// A boxed source should behave like the source it wraps
+    #[tokio::test]
+    async fn boxed_source() {
+        let source: BoxedPageSource = Box::new(create_example_provider_factory().build());
+
+        let page = source
+            .page_at(
+                "owner_1".to_string(),
+                "name_1".to_string(),
+                "pages".to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.owner(), "owner_1");
+        let asset = page.get_asset(Path::new("asset_1")).await.unwrap();
+        assert_eq!(asset.body().unwrap(), "data_1");
+        assert!(page.get_asset(Path::new("missing")).await.is_err());
+        assert_eq!(page.asset_exists(Path::new("asset_1")).await, Ok(true));
+        assert_eq!(page.asset_exists(Path::new("missing")).await, Ok(false));
+
+        assert!(source.owner_exists("owner_2").await.unwrap());
+        assert!(!source.owner_exists("nobody").await.unwrap());
+        assert_eq!(source.pages().await.unwrap().count(), 2);
+    }
+}
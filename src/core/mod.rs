@@ -11,4 +11,7 @@ mod asset;
 pub use asset::*;
 mod cache;
 pub use cache::*;
+mod dynamic;
+pub use dynamic::*;
 mod util;
+pub use util::{UrlAnalysis, analyze_url, asset_path, decode_path, glob_matches, path_segments};
@@ -2,10 +2,11 @@
 //! Generally, to access something a page, you go through these steps:
 //! PageSource -> Page -> Asset -> [your data]
 
-#[cfg(feature = "forgejo")]
-use crate::{Asset, AssetSource};
+use crate::{Asset, AssetError, AssetSource};
 use log::{error, info};
-use std::{fmt::Display, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Display, path::Path, str::FromStr, time::Duration};
+use tokio::sync::broadcast;
 
 /* -------------------------------- Constants ------------------------------- */
 
@@ -14,12 +15,23 @@ use std::{fmt::Display, path::Path};
 /// The relative location in which to find page domain configuration.
 pub const DOMAIN_FILE_PATH: &str = "/.domain";
 
+/// The page name to infer if none is specified.
+pub const DEFAULT_PAGE_NAME: &str = "pages";
+
+/// The page branch to infer if none is specified.
+pub const DEFAULT_PAGE_BRANCH: &str = "pages";
+
+/// The separator between a page's name and its branch (`name:branch`).
+pub const BRANCH_SEPARATOR: char = ':';
+
 /* -------------------------------- Utilities ------------------------------- */
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum PageError {
     /// The desired page wasn't found.
     NotFound,
+    /// The desired page used to exist, but was recently removed.
+    Gone,
     /// Something went wrong in the Page Provider.
     ProviderError,
 }
@@ -29,11 +41,52 @@ impl Display for PageError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::NotFound => f.write_str("Not found"),
+            Self::Gone => f.write_str("Gone"),
             Self::ProviderError => f.write_str("Provider error"),
         }
     }
 }
 
+impl std::error::Error for PageError {}
+
+/// A missing asset means a missing page; Anything else is the provider's fault.
+impl From<AssetError> for PageError {
+    fn from(value: AssetError) -> Self {
+        match value {
+            AssetError::NotFound => Self::NotFound,
+            _ => Self::ProviderError,
+        }
+    }
+}
+
+/// Statistics about the latest completed scan, for capacity planning.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanStats {
+    /// When the scan finished, as a unix timestamp.
+    pub finished_at: i64,
+    /// How long the scan took, in seconds.
+    pub duration: f64,
+    /// How many repositories were looked at.
+    pub repos_scanned: usize,
+    /// How many branches were indexed as pages.
+    pub branches_matched: usize,
+    /// How many pages were skipped because of the page limit.
+    pub pages_skipped: usize,
+    /// How many branches were skipped for having no files.
+    pub pages_empty: usize,
+    /// How many custom domains were declared by more than one page.
+    pub domain_conflicts: usize,
+}
+
+/// A custom domain in a domain index, and the pages declaring it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DomainClaims {
+    /// The page requests for the domain are served from.
+    pub serving: PageLocation,
+    /// Every page (branch) declaring the domain; More than one page is a conflict.
+    pub declared_by: Vec<PageLocation>,
+}
+
 /* -------------------------------------------------------------------------- */
 /*                               Page Accessing                               */
 /* -------------------------------------------------------------------------- */
@@ -45,6 +98,114 @@ pub struct PageLocation {
     pub branch: String,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum PageLocationParseError {
+    /// There was nothing to parse.
+    Empty,
+    /// A part of the location (owner, name, or branch) was present, but empty.
+    EmptySegment,
+    /// There were more segments than `owner/name:branch` allows.
+    TooManySegments,
+}
+
+impl Display for PageLocationParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Empty page location"),
+            Self::EmptySegment => f.write_str("Page location has an empty segment"),
+            Self::TooManySegments => f.write_str("Page location has too many segments"),
+        }
+    }
+}
+
+/// Splits a `name:branch` segment into its name and (optional) branch.
+pub fn split_name_branch(segment: &str) -> (&str, Option<&str>) {
+    split_name_branch_with(segment, BRANCH_SEPARATOR)
+}
+
+/// Splits a segment into its name and (optional) branch, using a custom separator.
+pub fn split_name_branch_with(segment: &str, separator: char) -> (&str, Option<&str>) {
+    match segment.split_once(separator) {
+        Some((name, branch)) => (name, Some(branch)),
+        None => (segment, None),
+    }
+}
+
+impl PageLocation {
+    /// Parses a location in the `owner`, `owner/name`, or `owner/name:branch` form,
+    /// filling in whatever isn't specified with the provided defaults.
+    ///
+    /// # Arguments
+    ///
+    /// - `s` (`&str`) - The location to parse.
+    /// - `default_name` (`&str`) - The name to use if none is specified.
+    /// - `default_branch` (`&str`) - The branch to use if none is specified.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Self, PageLocationParseError>` - The location, if it could be parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pageshelf::PageLocation;
+    ///
+    /// let loc = PageLocation::parse_with_defaults("nya/site", "pages", "main").unwrap();
+    /// assert_eq!(loc.to_string(), "nya/site:main");
+    /// ```
+    pub fn parse_with_defaults(
+        s: &str,
+        default_name: &str,
+        default_branch: &str,
+    ) -> Result<Self, PageLocationParseError> {
+        if s.is_empty() {
+            return Err(PageLocationParseError::Empty);
+        }
+
+        let mut segments = s.split('/');
+        let owner = segments.next().unwrap_or_default();
+        let (name, branch) = match segments.next() {
+            Some(v) => split_name_branch(v),
+            None => (default_name, None),
+        };
+        if segments.next().is_some() {
+            return Err(PageLocationParseError::TooManySegments);
+        }
+        let branch = branch.unwrap_or(default_branch);
+
+        if owner.is_empty() || name.is_empty() || branch.is_empty() {
+            return Err(PageLocationParseError::EmptySegment);
+        }
+
+        Ok(Self {
+            owner: owner.to_string(),
+            name: name.to_string(),
+            branch: branch.to_string(),
+        })
+    }
+}
+
+impl FromStr for PageLocation {
+    type Err = PageLocationParseError;
+
+    /// Parses a location in the `owner/name:branch` form, using
+    /// [`DEFAULT_PAGE_NAME`] and [`DEFAULT_PAGE_BRANCH`] for anything unspecified.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_defaults(s, DEFAULT_PAGE_NAME, DEFAULT_PAGE_BRANCH)
+    }
+}
+
+/// Displays a Page Location in the `owner/name:branch` form
+impl Display for PageLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}{}{}",
+            self.owner, self.name, BRANCH_SEPARATOR, self.branch
+        )
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PageAssetLocation {
     pub page: PageLocation,
@@ -144,11 +305,59 @@ pub trait PageSource {
 
     /// What branch should be inferred when there is no specified branch?
     fn default_branch(&self) -> &str {
-        "pages"
+        DEFAULT_PAGE_BRANCH
+    }
+
+    /// Subscribes to pages being removed upstream, if this source can tell.
+    ///
+    /// Layers keeping state per page (like caches) can use this to drop it.
+    /// Layers should forward this to the source they wrap.
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        None
+    }
+
+    /// How long until upstream can be reached again, if this source knows it's holding off
+    /// (e.g. while rate limited). Sent to clients as `Retry-After` with `503` responses.
+    ///
+    /// Layers should forward this to the source they wrap.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Statistics about the latest completed scan of upstream, if this source scans it.
+    ///
+    /// Layers should forward this to the source they wrap.
+    #[allow(async_fn_in_trait)]
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        None
+    }
+
+    /// The custom domains declared by pages, if this source keeps an index of them.
+    /// Without one, they can only be found by reading the domain file of every page.
+    ///
+    /// Layers should forward this to the source they wrap.
+    #[allow(async_fn_in_trait)]
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        None
     }
 
     /* ------------------------- Automatic Abstractions ------------------------- */
 
+    /// Checks whether an owner exists, even if they don't have the page being looked for.
+    ///
+    /// By default, only owners with at least one page are considered to exist;
+    /// Sources with cheaper (or more complete) knowledge of owners should override this.
+    #[allow(async_fn_in_trait)]
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        match self.pages().await {
+            Ok(mut pages) => Ok(pages.any(|page| page.owner() == owner)),
+            Err(e) => {
+                error!("Error checking if owner {} exists: {}", owner, e);
+                Err(e)
+            }
+        }
+    }
+
     /// Find all Pages that meet conditions set by the query
     #[allow(async_fn_in_trait)]
     async fn search_pages<'a>(
@@ -202,62 +411,173 @@ pub trait PageSource {
         }
     }
 
+    /// Finds the page that declares one of the domains in its domain file.
+    ///
+    /// If several branches of pages declare it, the default branch takes precedence.
+    /// The domains are owned, so the found page doesn't borrow them.
     #[allow(async_fn_in_trait)]
-    async fn find_by_domains(&self, domains: &[&str]) -> Result<impl Page, PageError> {
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
         let pages = self.pages().await;
         if let Err(e) = pages {
             error!("Error getting pages to find: {}", e);
             return Err(e);
         }
         let pages = pages.unwrap();
+        let mut found = None;
         for page in pages {
-            let mut applies = false;
-            {
-                // TODO: Magic string, fix.
-                info!(
-                    "Checking repo {}/{}:{} for domain file. Matching against domains {:?}...",
-                    page.owner(),
-                    page.name(),
-                    page.branch(),
-                    domains
-                );
-                let asset = page.get_asset(Path::new(DOMAIN_FILE_PATH)).await;
-
-                if let Ok(asset) = asset {
-                    info!(
-                        "Found domain file at {}/{}:{}",
-                        page.owner(),
-                        page.name(),
-                        page.branch()
-                    );
-                    let bytes = asset.bytes();
-                    if let Ok(body) = std::str::from_utf8(bytes) {
-                        // Trim lines in the body to avoid whitespace issues
-                        let trimmed_body_lines: Vec<String> = body
-                            .split('\n')
-                            .map(|line| line.trim().to_string())
-                            .collect();
-
-                        // Check if any domain is in the trimmed body lines
-                        if trimmed_body_lines
-                            .iter()
-                            .any(|line| domains.contains(&line.as_str()))
-                        {
-                            applies = true;
-                        }
-                    }
-                }
+            if !declares_domain(&page, &domains).await? {
+                continue;
             }
-            if applies {
+            if page.branch() == self.default_branch() {
                 info!("Resolved page");
                 return Ok(page);
             }
+            if found.is_none() {
+                found = Some(page);
+            }
         }
 
-        Err(PageError::NotFound)
+        match found {
+            Some(page) => {
+                info!("Resolved page (not on the default branch)");
+                Ok(page)
+            }
+            None => Err(PageError::NotFound),
+        }
+    }
+}
+
+/// Whether or not a page's domain file lists any of the domains.
+///
+/// Pages without a domain file declare none; Failing to read one fails the search instead,
+/// as the page may well declare the domain.
+async fn declares_domain(page: &impl Page, domains: &[String]) -> Result<bool, PageError> {
+    // TODO: Magic string, fix.
+    info!(
+        "Checking repo {}/{}:{} for domain file. Matching against domains {:?}...",
+        page.owner(),
+        page.name(),
+        page.branch(),
+        domains
+    );
+    let asset = match page.get_asset(Path::new(DOMAIN_FILE_PATH)).await {
+        Err(AssetError::NotFound) => return Ok(false),
+        asset => asset?,
+    };
+    info!(
+        "Found domain file at {}/{}:{}",
+        page.owner(),
+        page.name(),
+        page.branch()
+    );
+    match std::str::from_utf8(asset.bytes()) {
+        Ok(body) => Ok(domain_file_entries(body).any(|line| domains.iter().any(|v| v == line))),
+        Err(_) => Ok(false),
     }
 }
 
+/// The domains listed in the body of a domain file, one per line.
+pub fn domain_file_entries(body: &str) -> impl Iterator<Item = &str> {
+    // Trim lines in the body to avoid whitespace issues
+    body.split('\n').map(str::trim).filter(|v| !v.is_empty())
+}
+
 /* -------------------------------------------------------------------------- */
 /*                                    Tests                                   */
 /* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{PageError, PageLocation, PageLocationParseError};
+    use crate::AssetError;
+
+    fn loc(owner: &str, name: &str, branch: &str) -> PageLocation {
+        PageLocation {
+            owner: owner.to_string(),
+            name: name.to_string(),
+            branch: branch.to_string(),
+        }
+    }
+
+    /// Ensure each of the short forms gets the defaults filled in
+    #[test]
+    fn page_location_parse() {
+        let params: Vec<(&str, Result<PageLocation, PageLocationParseError>)> = vec![
+            ("person", Ok(loc("person", "pages", "pages"))),
+            ("person/page", Ok(loc("person", "page", "pages"))),
+            (
+                "person/page:unstable",
+                Ok(loc("person", "page", "unstable")),
+            ),
+            ("", Err(PageLocationParseError::Empty)),
+            ("/page", Err(PageLocationParseError::EmptySegment)),
+            ("person/", Err(PageLocationParseError::EmptySegment)),
+            ("person/page:", Err(PageLocationParseError::EmptySegment)),
+            (
+                "person/page/other",
+                Err(PageLocationParseError::TooManySegments),
+            ),
+        ];
+
+        for param in params {
+            assert_eq!(
+                PageLocation::from_str(param.0),
+                param.1,
+                "Parsing {}",
+                param.0
+            );
+        }
+
+        assert_eq!(
+            PageLocation::parse_with_defaults("person", "site", "main"),
+            Ok(loc("person", "site", "main"))
+        );
+    }
+
+    /// Ensure a displayed location parses back into the same location
+    #[test]
+    fn page_location_round_trip() {
+        let locations = [
+            loc("person", "pages", "pages"),
+            loc("person", "page", "unstable"),
+            loc("person", "page.example.domain", "pages-testing"),
+        ];
+
+        for location in locations {
+            let s = location.to_string();
+            assert_eq!(PageLocation::from_str(&s), Ok(location), "Parsing {}", s);
+        }
+    }
+
+    /// Errors should work with `?` into boxed errors
+    #[test]
+    fn errors_are_std_errors() {
+        fn boxed(e: impl std::error::Error + 'static) -> Box<dyn std::error::Error> {
+            Box::new(e)
+        }
+
+        assert_eq!(boxed(PageError::Gone).to_string(), "Gone");
+        assert_eq!(boxed(AssetError::NotFound).to_string(), "Not found");
+        assert_eq!(
+            boxed(AssetError::ProviderError).to_string(),
+            "Provider error"
+        );
+    }
+
+    /// Document how asset errors carry over to pages
+    #[test]
+    fn error_conversions() {
+        let params = [
+            (AssetError::NotFound, PageError::NotFound),
+            (AssetError::Corrupted, PageError::ProviderError),
+            (AssetError::ProviderError, PageError::ProviderError),
+            (AssetError::CannotInterpret, PageError::ProviderError),
+            (AssetError::TooLarge, PageError::ProviderError),
+        ];
+        for (asset, page) in params {
+            assert_eq!(PageError::from(asset), page);
+        }
+    }
+}
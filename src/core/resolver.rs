@@ -1,9 +1,9 @@
-use log::warn;
-use url::Url;
+use log::{info, warn};
+use url::{Host, Url};
 
-use crate::{PageAssetLocation, PageLocation};
+use crate::{BRANCH_SEPARATOR, PageAssetLocation, PageLocation};
 
-use super::util::analyze_url;
+use super::util::{analyze_url, path_segments};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum UrlResolution {
@@ -16,12 +16,53 @@ pub enum UrlResolution {
     External(Url),
     /// The URL is invalid.
     Malformed(String),
+    /// The URL's host isn't one the server is configured to serve.
+    Misdirected(String),
 }
 
 pub trait UrlResolver {
     fn resolve(&self, url: Url) -> UrlResolution;
+
+    /// The host of the home domain, if there is one.
+    fn home_domain(&self) -> Option<&str> {
+        None
+    }
+
+    /// The hosts of the page domains (which pages are served under).
+    fn page_domains(&self) -> &[String] {
+        &[]
+    }
+
+    /// Whether or not arbitrary (custom) domains are resolved, as [`UrlResolution::External`].
+    fn external_enabled(&self) -> bool {
+        false
+    }
+}
+
+/// A [`UrlResolver`] of any type, e.g. to pick one at runtime.
+pub type BoxedUrlResolver = Box<dyn UrlResolver + Send + Sync>;
+
+impl<R: UrlResolver + ?Sized> UrlResolver for Box<R> {
+    fn resolve(&self, url: Url) -> UrlResolution {
+        self.as_ref().resolve(url)
+    }
+
+    fn home_domain(&self) -> Option<&str> {
+        self.as_ref().home_domain()
+    }
+
+    fn page_domains(&self) -> &[String] {
+        self.as_ref().page_domains()
+    }
+
+    fn external_enabled(&self) -> bool {
+        self.as_ref().external_enabled()
+    }
 }
 
+/// Placeholder in an owner repo pattern that is replaced with the owner's name.
+pub const OWNER_PLACEHOLDER: &str = "<owner>";
+
 #[derive(Clone)]
 pub struct DefaultUrlResolver {
     home_domain: Option<String>,
@@ -29,6 +70,13 @@ pub struct DefaultUrlResolver {
     external_enabled: bool,
     default_repo: String,
     default_branch: String,
+    owner_repo: Option<String>,
+    branch_separator: char,
+    builtin_hosts: Vec<String>,
+    strict_host: bool,
+    default_user: Option<String>,
+    root_prefers_builtin: bool,
+    lowercase_names: bool,
 }
 
 impl DefaultUrlResolver {
@@ -80,15 +128,135 @@ impl DefaultUrlResolver {
             default_repo,
             default_branch,
             external_enabled,
+            owner_repo: None,
+            branch_separator: BRANCH_SEPARATOR,
+            builtin_hosts: Vec::new(),
+            strict_host: false,
+            default_user: None,
+            root_prefers_builtin: true,
+            lowercase_names: false,
         }
     }
-}
 
-impl UrlResolver for DefaultUrlResolver {
-    fn resolve(&self, url: Url) -> UrlResolution {
+    /// Factory function to derive the default repository from the owner's name.
+    ///
+    /// `<owner>` in the pattern is replaced with the owner, so `<owner>.example.domain`
+    /// makes `person.example.domain` the root site of `person`, much like GitHub's `owner.github.io`.
+    pub fn with_owner_repo(mut self, pattern: String) -> Self {
+        self.owner_repo = Some(pattern);
+        self
+    }
+
+    /// Factory function to change the separator between the repository and branch
+    /// in the path form (e.g. `@` for `/owner/repo@branch`).
+    pub fn with_branch_separator(mut self, separator: char) -> Self {
+        self.branch_separator = separator;
+        self
+    }
+
+    /// Factory function to add hosts that always resolve to built-in pages, no matter the path
+    /// or page domains (e.g. internal hostnames or the bind address used by health checks).
+    pub fn with_builtin_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.builtin_hosts = hosts.iter().map(|f| normalize_host(f)).collect();
+        self
+    }
+
+    /// Factory function to only resolve hosts that are the home domain, page domains, or built-in.
+    ///
+    /// Other hosts resolve as [`UrlResolution::Misdirected`], or as external domains (if enabled)
+    /// that must then be claimed by a page.
+    pub fn with_strict_host(mut self, strict_host: bool) -> Self {
+        self.strict_host = strict_host;
+        self
+    }
+
+    /// Factory function to set the owner whose page may be served at the root (`/`) of the home domain.
+    ///
+    /// Only used if the root doesn't prefer the built-in page (see [`Self::with_root_prefers_builtin`]).
+    pub fn with_default_user(mut self, default_user: String) -> Self {
+        self.default_user = Some(default_user);
+        self
+    }
+
+    /// Factory function to pick what the root (`/`) of the home domain resolves to.
+    ///
+    /// If enabled (the default), the root is the built-in landing page, and the default user's
+    /// page is only served at their own path or subdomain. Otherwise, the root resolves to the
+    /// default user's page, if one is set. Paths with an owner are unaffected either way.
+    pub fn with_root_prefers_builtin(mut self, root_prefers_builtin: bool) -> Self {
+        self.root_prefers_builtin = root_prefers_builtin;
+        self
+    }
+
+    /// Factory function to lowercase the owner and repository names pages resolve to, so
+    /// `/Owner/Repo` and `/owner/repo` are the same page. Branch names are kept as they are.
+    pub fn with_lowercase_names(mut self, lowercase_names: bool) -> Self {
+        self.lowercase_names = lowercase_names;
+        self
+    }
+
+    /// The repository defaulted to if none is specified (unless derived with an owner repo pattern).
+    pub fn default_repo(&self) -> &str {
+        &self.default_repo
+    }
+
+    /// The branch defaulted to if none is specified.
+    pub fn default_branch(&self) -> &str {
+        &self.default_branch
+    }
+
+    /// The page domain a host is (under), if any.
+    pub fn page_domain_of(&self, host: &str) -> Option<&str> {
+        self.page_domains()
+            .iter()
+            .find(|pd| *pd == host || is_in_url(pd, host))
+            .map(String::as_str)
+    }
+
+    /// Whether or not a host is the home domain or (under) one of the page domains.
+    fn is_known_host(&self, host: &str) -> bool {
+        self.home_domain.as_deref() == Some(host) || self.page_domain_of(host).is_some()
+    }
+
+    /// The repository to default to for an owner, if none is specified.
+    fn default_repo_for(&self, owner: &str) -> String {
+        match &self.owner_repo {
+            Some(pattern) => pattern.replace(OWNER_PLACEHOLDER, owner),
+            None => self.default_repo.clone(),
+        }
+    }
+
+    fn resolve_url(&self, url: Url) -> UrlResolution {
+        if path_segments(&url).is_none() {
+            warn!("Refusing to resolve unsafe path {}", url.path());
+            return UrlResolution::Malformed(url.path().to_string());
+        }
+
         let host = url.host_str();
 
-        let is_root = (self.page_domains.iter().count() == 0 && !self.external_enabled)
+        // Hosts like internal health check addresses are never pages
+        if host.is_some_and(|host| self.builtin_hosts.contains(&normalize_host(host))) {
+            return UrlResolution::BuiltIn;
+        }
+
+        if self.strict_host
+            && let Some(host) = host
+            && !self.is_known_host(host)
+        {
+            return match self.external_enabled {
+                true => UrlResolution::External(url),
+                false => {
+                    info!("Refusing to resolve unknown host {}", host);
+                    UrlResolution::Misdirected(host.to_string())
+                }
+            };
+        }
+
+        // IP literals can't carry owner subdomains, so they can only refer to the root
+        let is_ip = matches!(url.host(), Some(Host::Ipv4(_) | Host::Ipv6(_)));
+
+        let is_root = is_ip
+            || (self.page_domains.iter().count() == 0 && !self.external_enabled)
             || match host {
                 Some(host) => match &self.page_domains {
                     Some(pd) => match &self.home_domain {
@@ -111,17 +279,27 @@ impl UrlResolver for DefaultUrlResolver {
             };
 
         match is_root {
-            true => match analyze_url(&url, None) {
+            true => match analyze_url(&url, None, self.branch_separator) {
                 Some(a) => match a.owner {
                     Some(owner) => UrlResolution::Page(PageAssetLocation {
                         page: PageLocation {
+                            name: a.repo.unwrap_or_else(|| self.default_repo_for(&owner)),
                             owner,
-                            name: a.repo.unwrap_or(self.default_repo.clone()),
                             branch: a.branch.unwrap_or(self.default_branch.clone()),
                         },
                         asset: a.asset,
                     }),
-                    None => UrlResolution::BuiltIn,
+                    None => match (&self.default_user, self.root_prefers_builtin) {
+                        (Some(owner), false) => UrlResolution::Page(PageAssetLocation {
+                            page: PageLocation {
+                                name: a.repo.unwrap_or_else(|| self.default_repo_for(owner)),
+                                owner: owner.clone(),
+                                branch: a.branch.unwrap_or(self.default_branch.clone()),
+                            },
+                            asset: a.asset,
+                        }),
+                        _ => UrlResolution::BuiltIn,
+                    },
                 },
                 None => UrlResolution::BuiltIn,
             },
@@ -131,15 +309,15 @@ impl UrlResolver for DefaultUrlResolver {
                     Some(pds) => {
                         for pd in pds {
                             if is_in_url(pd, host) {
-                                match analyze_url(&url, Some(pd)) {
+                                match analyze_url(&url, Some(pd), self.branch_separator) {
                                     Some(a) => match a.owner {
                                         Some(owner) => {
                                             return UrlResolution::Page(PageAssetLocation {
                                                 page: PageLocation {
+                                                    name: a.repo.unwrap_or_else(|| {
+                                                        self.default_repo_for(&owner)
+                                                    }),
                                                     owner,
-                                                    name: a
-                                                        .repo
-                                                        .unwrap_or(self.default_repo.clone()),
                                                     branch: a
                                                         .branch
                                                         .unwrap_or(self.default_branch.clone()),
@@ -180,10 +358,98 @@ impl UrlResolver for DefaultUrlResolver {
     }
 }
 
+impl UrlResolver for DefaultUrlResolver {
+    fn resolve(&self, url: Url) -> UrlResolution {
+        match self.resolve_url(url) {
+            UrlResolution::Page(mut loc) if self.lowercase_names => {
+                loc.page.owner = loc.page.owner.to_lowercase();
+                loc.page.name = loc.page.name.to_lowercase();
+                UrlResolution::Page(loc)
+            }
+            resolution => resolution,
+        }
+    }
+
+    fn home_domain(&self) -> Option<&str> {
+        self.home_domain.as_deref()
+    }
+
+    fn page_domains(&self) -> &[String] {
+        self.page_domains.as_deref().unwrap_or_default()
+    }
+
+    fn external_enabled(&self) -> bool {
+        self.external_enabled
+    }
+}
+
+/// Resolves URLs with several resolvers, in order.
+///
+/// The first resolution that isn't [`UrlResolution::BuiltIn`] or [`UrlResolution::Malformed`]
+/// wins, so earlier resolvers can defer to later ones (e.g. an alias resolver before the default
+/// one). If every resolver defers, the last one's resolution is used.
+#[derive(Default)]
+pub struct ChainResolver {
+    resolvers: Vec<BoxedUrlResolver>,
+}
+
+impl ChainResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Factory function to add a resolver to the end of the chain.
+    pub fn with(mut self, resolver: impl UrlResolver + Send + Sync + 'static) -> Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+}
+
+impl UrlResolver for ChainResolver {
+    fn resolve(&self, url: Url) -> UrlResolution {
+        let mut resolution = UrlResolution::BuiltIn;
+        for resolver in &self.resolvers {
+            resolution = resolver.resolve(url.clone());
+            if !matches!(
+                resolution,
+                UrlResolution::BuiltIn | UrlResolution::Malformed(_)
+            ) {
+                break;
+            }
+        }
+        resolution
+    }
+
+    /// The first home domain in the chain.
+    fn home_domain(&self) -> Option<&str> {
+        self.resolvers.iter().find_map(|v| v.home_domain())
+    }
+
+    /// The page domains of the first resolver in the chain that has any.
+    fn page_domains(&self) -> &[String] {
+        self.resolvers
+            .iter()
+            .map(|v| v.page_domains())
+            .find(|v| !v.is_empty())
+            .unwrap_or_default()
+    }
+
+    fn external_enabled(&self) -> bool {
+        self.resolvers.iter().any(|v| v.external_enabled())
+    }
+}
+
 /* -------------------------------------------------------------------------- */
 /*                                URL Utilities                               */
 /* -------------------------------------------------------------------------- */
 
+/// Lowercases a host and strips the brackets of IPv6 literals, so they compare as configured.
+fn normalize_host(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase()
+}
+
 fn is_in_url(url_base: &str, url: &str) -> bool {
     log::debug!("Checking if {} ends in {}...", url, url_base);
     let s = format!(".{}", url_base);
@@ -205,7 +471,7 @@ pub mod tests {
         resolver::{DefaultUrlResolver, UrlResolution},
     };
 
-    use super::UrlResolver;
+    use super::{ChainResolver, UrlResolver};
 
     #[test]
     fn root_builtin() {
@@ -348,6 +614,58 @@ pub mod tests {
         );
     }
 
+    /// Unsafe paths must be rejected, rather than leaking into the owner or asset
+    #[test]
+    fn unsafe_paths() {
+        let r =
+            DefaultUrlResolver::new(None, None, "pages".to_string(), "pages".to_string(), false);
+
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain/nya//pages/./index.html").unwrap()),
+            r.resolve(Url::from_str("http://home.domain/nya/pages/index.html").unwrap())
+        );
+
+        assert!(matches!(
+            r.resolve(Url::from_str("http://home.domain/nya/pages/..%2Fsecret").unwrap()),
+            UrlResolution::Malformed(_)
+        ));
+    }
+
+    /// IP literal hosts should be treated as the home domain
+    #[test]
+    fn ip_literals() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+            "pages".to_string(),
+            "pages".to_string(),
+            true,
+        );
+
+        for host in ["127.0.0.1", "127.0.0.1:8080", "[::1]", "[::1]:8080"] {
+            assert_eq!(
+                r.resolve(Url::from_str(&format!("http://{}", host)).unwrap()),
+                UrlResolution::BuiltIn,
+                "Resolving {}",
+                host
+            );
+
+            assert_eq!(
+                r.resolve(Url::from_str(&format!("http://{}/nya/site/index.html", host)).unwrap()),
+                UrlResolution::Page(PageAssetLocation {
+                    page: PageLocation {
+                        owner: "nya".to_string(),
+                        name: "site".to_string(),
+                        branch: "pages".to_string()
+                    },
+                    asset: "/index.html".to_string()
+                }),
+                "Resolving {}",
+                host
+            );
+        }
+    }
+
     #[test]
     fn domains() {
         let r = DefaultUrlResolver::new(
@@ -368,4 +686,396 @@ pub mod tests {
             UrlResolution::External(Url::from_str("http://other.domain").unwrap())
         );
     }
+
+    /// Getters should describe the resolver as it was configured, with hosts of URLs
+    #[test]
+    fn getters() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("https://home.domain/path").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain:8080").unwrap()]),
+            "site".to_string(),
+            "main".to_string(),
+            true,
+        );
+        assert_eq!(r.home_domain(), Some("home.domain"));
+        assert_eq!(r.page_domains(), ["pages.domain"]);
+        assert!(r.external_enabled());
+        assert_eq!(r.default_repo(), "site");
+        assert_eq!(r.default_branch(), "main");
+        assert_eq!(r.page_domain_of("pages.domain"), Some("pages.domain"));
+        assert_eq!(r.page_domain_of("owner.pages.domain"), Some("pages.domain"));
+        assert_eq!(r.page_domain_of("otherpages.domain"), None);
+
+        let r =
+            DefaultUrlResolver::new(None, None, "pages".to_string(), "pages".to_string(), false);
+        assert_eq!(r.home_domain(), None);
+        assert!(r.page_domains().is_empty());
+        assert!(!r.external_enabled());
+    }
+
+    /// The default repository can be derived from the owner
+    #[test]
+    fn owner_repo() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+            "pages".to_string(),
+            "pages".to_string(),
+            false,
+        )
+        .with_owner_repo("<owner>.pages.domain".to_string());
+
+        assert_eq!(
+            r.resolve(Url::from_str("http://nya.pages.domain/index.html").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "nya.pages.domain".to_string(),
+                    branch: "pages".to_string()
+                },
+                asset: "/index.html".to_string()
+            })
+        );
+
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain/nya").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "nya.pages.domain".to_string(),
+                    branch: "pages".to_string()
+                },
+                asset: "/".to_string()
+            })
+        );
+
+        // Explicit repositories are unaffected
+        assert_eq!(
+            r.resolve(Url::from_str("http://blog.nya.pages.domain/").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "blog".to_string(),
+                    branch: "pages".to_string()
+                },
+                asset: "/".to_string()
+            })
+        );
+    }
+
+    /// Branches should be selectable without a branch subdomain
+    #[test]
+    fn branch_selector() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+            "pages".to_string(),
+            "pages".to_string(),
+            false,
+        );
+
+        let expected = UrlResolution::Page(PageAssetLocation {
+            page: PageLocation {
+                owner: "nya".to_string(),
+                name: "pages".to_string(),
+                branch: "preview".to_string(),
+            },
+            asset: "/index.html".to_string(),
+        });
+
+        assert_eq!(
+            r.resolve(Url::from_str("http://nya.pages.domain/~preview/index.html").unwrap()),
+            expected
+        );
+        assert_eq!(
+            r.resolve(Url::from_str("http://nya.pages.domain/index.html?branch=preview").unwrap()),
+            expected
+        );
+    }
+
+    /// The path form branch separator should be configurable
+    #[test]
+    fn branch_separator() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            None,
+            "pages".to_string(),
+            "pages".to_string(),
+            false,
+        )
+        .with_branch_separator('@');
+
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain/nya/site@preview/index.html").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "site".to_string(),
+                    branch: "preview".to_string()
+                },
+                asset: "/index.html".to_string()
+            })
+        );
+    }
+
+    /// Owner and repository names can be lowercased, but never branches
+    #[test]
+    fn lowercase_names() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            None,
+            "pages".to_string(),
+            "pages".to_string(),
+            false,
+        );
+        let url = Url::from_str("http://home.domain/Nya/Site:Preview/index.html").unwrap();
+        assert!(matches!(
+            r.resolve(url.clone()),
+            UrlResolution::Page(loc) if loc.page.owner == "Nya"
+        ));
+
+        let r = r.with_lowercase_names(true);
+        assert_eq!(
+            r.resolve(url),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "site".to_string(),
+                    branch: "Preview".to_string()
+                },
+                asset: "/index.html".to_string()
+            })
+        );
+    }
+
+    /// Built-in hosts should never be parsed as owners or external domains
+    #[test]
+    fn builtin_hosts() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+            "pages".to_string(),
+            "pages".to_string(),
+            true,
+        )
+        .with_builtin_hosts(vec![
+            "Internal.Pages.Domain".to_string(),
+            "10.0.0.1".to_string(),
+            "[::1]".to_string(),
+        ]);
+
+        for url in [
+            "http://internal.pages.domain/health",
+            "http://10.0.0.1:8080/nya/site",
+            "http://[::1]/nya",
+        ] {
+            assert_eq!(
+                r.resolve(Url::from_str(url).unwrap()),
+                UrlResolution::BuiltIn,
+                "Resolving {}",
+                url
+            );
+        }
+
+        // Other hosts are unaffected
+        assert_eq!(
+            r.resolve(Url::from_str("http://nya.pages.domain").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "pages".to_string(),
+                    branch: "pages".to_string()
+                },
+                asset: "/".to_string()
+            })
+        );
+    }
+
+    /// The root should only resolve to the default user's page if it doesn't prefer the built-in page
+    #[test]
+    fn root_prefers_builtin() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+            "pages".to_string(),
+            "pages".to_string(),
+            false,
+        )
+        .with_default_user("nya".to_string());
+
+        let page = |asset: &str| {
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "nya".to_string(),
+                    name: "pages".to_string(),
+                    branch: "pages".to_string(),
+                },
+                asset: asset.to_string(),
+            })
+        };
+
+        // Preferred by default
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain/").unwrap()),
+            UrlResolution::BuiltIn
+        );
+        assert_eq!(
+            r.resolve(Url::from_str("http://nya.pages.domain/").unwrap()),
+            page("/")
+        );
+
+        let r = r.with_root_prefers_builtin(false);
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain/").unwrap()),
+            page("/")
+        );
+        assert_eq!(
+            r.resolve(Url::from_str("http://nya.pages.domain/").unwrap()),
+            page("/")
+        );
+        // Other owners and hosts are unaffected
+        assert!(matches!(
+            r.resolve(Url::from_str("http://home.domain/mrrp").unwrap()),
+            UrlResolution::Page(v) if v.page.owner == "mrrp"
+        ));
+        assert_eq!(
+            r.resolve(Url::from_str("http://pages.domain/").unwrap()),
+            UrlResolution::BuiltIn
+        );
+
+        // Without a default user, there's nothing else to serve
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            None,
+            "pages".to_string(),
+            "pages".to_string(),
+            false,
+        )
+        .with_root_prefers_builtin(false);
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain/").unwrap()),
+            UrlResolution::BuiltIn
+        );
+    }
+
+    /// Strict hosts should only resolve configured domains
+    #[test]
+    fn strict_host() {
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+            "pages".to_string(),
+            "pages".to_string(),
+            false,
+        )
+        .with_builtin_hosts(vec!["10.0.0.1".to_string()])
+        .with_strict_host(true);
+
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain").unwrap()),
+            UrlResolution::BuiltIn
+        );
+        assert_eq!(
+            r.resolve(Url::from_str("http://10.0.0.1/nya").unwrap()),
+            UrlResolution::BuiltIn
+        );
+        assert!(matches!(
+            r.resolve(Url::from_str("http://nya.pages.domain").unwrap()),
+            UrlResolution::Page(_)
+        ));
+        assert_eq!(
+            r.resolve(Url::from_str("http://other.domain/nya").unwrap()),
+            UrlResolution::Misdirected("other.domain".to_string())
+        );
+        assert_eq!(
+            r.resolve(Url::from_str("http://10.0.0.2/nya").unwrap()),
+            UrlResolution::Misdirected("10.0.0.2".to_string())
+        );
+
+        // Unknown hosts may still be custom domains, but never built-in
+        let r = DefaultUrlResolver::new(
+            Some(Url::from_str("http://home.domain").unwrap()),
+            Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+            "pages".to_string(),
+            "pages".to_string(),
+            true,
+        )
+        .with_strict_host(true);
+        assert_eq!(
+            r.resolve(Url::from_str("http://other.domain/nya").unwrap()),
+            UrlResolution::External(Url::from_str("http://other.domain/nya").unwrap())
+        );
+    }
+
+    /// Resolves `alias.domain` to a fixed page, deferring anything else.
+    struct AliasResolver;
+
+    impl UrlResolver for AliasResolver {
+        fn resolve(&self, url: Url) -> UrlResolution {
+            match url.host_str() {
+                Some("alias.domain") => UrlResolution::Page(PageAssetLocation {
+                    page: PageLocation {
+                        owner: "alias".to_string(),
+                        name: "pages".to_string(),
+                        branch: "pages".to_string(),
+                    },
+                    asset: url.path().to_string(),
+                }),
+                Some("broken.domain") => UrlResolution::Malformed("Broken".to_string()),
+                _ => UrlResolution::BuiltIn,
+            }
+        }
+    }
+
+    /// Chains should use the first resolution that isn't deferred, or the last one
+    #[test]
+    fn chain() {
+        let r = ChainResolver::new()
+            .with(AliasResolver)
+            .with(DefaultUrlResolver::new(
+                Some(Url::from_str("http://home.domain").unwrap()),
+                None,
+                "pages".to_string(),
+                "pages".to_string(),
+                false,
+            ));
+
+        assert_eq!(
+            r.resolve(Url::from_str("http://alias.domain/nya").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "alias".to_string(),
+                    name: "pages".to_string(),
+                    branch: "pages".to_string()
+                },
+                asset: "/nya".to_string()
+            })
+        );
+        // Deferred to the default resolver
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain/owner").unwrap()),
+            UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "owner".to_string(),
+                    name: "pages".to_string(),
+                    branch: "pages".to_string()
+                },
+                asset: "/".to_string()
+            })
+        );
+        assert_eq!(
+            r.resolve(Url::from_str("http://home.domain").unwrap()),
+            UrlResolution::BuiltIn
+        );
+        // Malformed resolutions defer too
+        assert_eq!(
+            r.resolve(Url::from_str("http://broken.domain").unwrap()),
+            UrlResolution::BuiltIn
+        );
+
+        assert_eq!(
+            ChainResolver::new().resolve(Url::from_str("http://home.domain").unwrap()),
+            UrlResolution::BuiltIn
+        );
+    }
 }
@@ -1,5 +1,14 @@
+use percent_encoding::percent_decode_str;
 use url::Url;
 
+use crate::split_name_branch_with;
+
+/// Prefix of a leading path segment that selects a branch in subdomain form (`/~branch/asset`).
+pub const BRANCH_PATH_PREFIX: char = '~';
+/// Query parameter that selects a branch (`?branch=name`).
+pub const BRANCH_QUERY: &str = "branch";
+
+/// What a URL says about the page and asset it refers to.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UrlAnalysis {
     pub owner: Option<String>,
@@ -8,6 +17,45 @@ pub struct UrlAnalysis {
     pub asset: String,
 }
 
+/// Percent-decodes a URL path (or path segment) exactly once.
+///
+/// Decoding only once matters: `%2520` must become `%20`, not a space,
+/// otherwise doubly-encoded input could be used to smuggle characters past checks.
+pub fn decode_path(path: &str) -> String {
+    percent_decode_str(path).decode_utf8_lossy().to_string()
+}
+
+/// Splits a URL's path into percent-decoded segments, dropping empty (`//`) and `.` segments.
+///
+/// # Arguments
+///
+/// - `url` (`&Url`) - The URL whose path should be split.
+///
+/// # Returns
+///
+/// - `Option<Vec<String>>` - The segments, or None if any of them are unsafe;
+///   That is, they would go up a level (`..`) or smuggle in a separator (`%2F`).
+pub fn path_segments(url: &Url) -> Option<Vec<String>> {
+    let mut segments = vec![];
+    for segment in url.path_segments()? {
+        let segment = decode_path(segment);
+        match segment.as_str() {
+            "" | "." => continue,
+            ".." => return None,
+            s if s.contains(['/', '\\', '\0']) => return None,
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments)
+}
+
+/// The asset a URL's path refers to, built from its [path_segments].
+///
+/// None if the path is unsafe.
+pub fn asset_path(url: &Url) -> Option<String> {
+    path_segments(url).map(|segments| format!("/{}", segments.join("/")))
+}
+
 // TODO: Consider removing native subdomain resolution in favor of using an external proxy?
 // ? Would be simple enough to have a redirect on proxy --> server transit:
 // ? mypage.pages.server --> pages.server/page/mypage
@@ -16,15 +64,23 @@ pub struct UrlAnalysis {
 /// Analyze a URL to determine what page it refers to,
 /// assuming it's either a subdirectory or subdomain.
 ///
+/// If the URL doesn't otherwise specify a branch, it can be selected with a leading
+/// `/~branch` segment (subdomain form only) or a `?branch=` query.
+///
 /// # Arguments
 ///
 /// - `url` (`&Url`) - The URL to analyze.
 /// - `base_domain` (`Option<&str>`) - The base domain to determine subdirectory requests.
+/// - `branch_separator` (`char`) - Separates the repository and branch in the path form (`repo:branch`).
 ///
 /// # Returns
 ///
 /// - `Option<UrlAnalysis>` - The URL analysis if successful, otherwise nothing.
-pub fn analyze_url(url: &Url, base_domain: Option<&str>) -> Option<UrlAnalysis> {
+pub fn analyze_url(
+    url: &Url,
+    base_domain: Option<&str>,
+    branch_separator: char,
+) -> Option<UrlAnalysis> {
     // Assume pages_url is example.domain;
     // (If none is returned, assume it's not valid)
     // (url "other.domain") -> None
@@ -89,29 +145,35 @@ pub fn analyze_url(url: &Url, base_domain: Option<&str>) -> Option<UrlAnalysis>
             branch = Some(b.to_string());
         }
 
+        let mut segments = path_segments(url)?;
+
+        // Branches can't always be subdomains (they need DNS), so also accept `/~branch/...`
+        if branch.is_none()
+            && let Some(b) = segments
+                .first()
+                .and_then(|v| v.strip_prefix(BRANCH_PATH_PREFIX))
+            && !b.is_empty()
+        {
+            branch = Some(b.to_string());
+            segments.remove(0);
+        }
+
         // Collect path segments into asset if present
-        let path: String = url
-            .path_segments()?
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<_>>()
-            .join("/");
+        let path: String = segments.join("/");
         if !path.is_empty() {
             asset.push_str(&path);
         }
     } else {
         // --- Path-based form ---
-        let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+        let mut segments = path_segments(url)?.into_iter();
 
         if let Some(o) = segments.next() {
-            owner = Some(o.to_string());
+            owner = Some(o);
         }
         if let Some(r) = segments.next() {
-            if let Some((repo_part, branch_part)) = r.split_once(':') {
-                repo = Some(repo_part.to_string());
-                branch = Some(branch_part.to_string());
-            } else {
-                repo = Some(r.to_string());
-            }
+            let (repo_part, branch_part) = split_name_branch_with(&r, branch_separator);
+            repo = Some(repo_part.to_string());
+            branch = branch_part.map(|v| v.to_string());
         }
         let path: String = segments.collect::<Vec<_>>().join("/");
         if !path.is_empty() {
@@ -119,6 +181,14 @@ pub fn analyze_url(url: &Url, base_domain: Option<&str>) -> Option<UrlAnalysis>
         }
     }
 
+    // The query is the fallback, if the branch wasn't selected any other way
+    if branch.is_none() {
+        branch = url
+            .query_pairs()
+            .find(|(k, v)| k == BRANCH_QUERY && !v.is_empty())
+            .map(|(_, v)| v.to_string());
+    }
+
     Some(UrlAnalysis {
         owner,
         repo,
@@ -127,13 +197,49 @@ pub fn analyze_url(url: &Url, base_domain: Option<&str>) -> Option<UrlAnalysis>
     })
 }
 
+/// Matches text against a glob pattern, where `*` matches any run of characters
+/// (including `/`) and `?` matches exactly one.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it's currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character, and try again
+                Some((sp, st)) => {
+                    star = Some((sp, st + 1));
+                    p = sp + 1;
+                    t = st + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
     use url::Url;
 
-    use super::{UrlAnalysis, analyze_url};
+    use super::{UrlAnalysis, analyze_url, glob_matches};
+    use crate::BRANCH_SEPARATOR;
 
     /// Ensure subdirectory queries are correctly recognized by the default URL analyzer
     #[test]
@@ -184,7 +290,7 @@ mod tests {
         for param in params {
             let url_str = format!("http://{}", param.0);
             let url = Url::from_str(url_str.as_str()).unwrap();
-            let a = analyze_url(&url, Some(domain));
+            let a = analyze_url(&url, Some(domain), BRANCH_SEPARATOR);
             assert_eq!(a, param.1, "Analyzing {}", param.0)
         }
     }
@@ -237,7 +343,67 @@ mod tests {
         for param in params {
             let url_str = format!("http://{}", param.0);
             let url = Url::from_str(url_str.as_str()).unwrap();
-            let a = analyze_url(&url, Some(domain));
+            let a = analyze_url(&url, Some(domain), BRANCH_SEPARATOR);
+            assert_eq!(a, param.1, "Analyzing {}", param.0)
+        }
+    }
+
+    /// Ensure percent-encoded paths are decoded, but only once
+    #[test]
+    fn test_analyze_url_percent_decoding() {
+        let domain = "example.domain";
+
+        let params: Vec<(&str, &str)> = vec![
+            ("example.domain/person/page/My%20File.html", "/My File.html"),
+            ("example.domain/person/page/%E2%9C%A8.html", "/✨.html"),
+            (
+                "example.domain/person/page/My%2520File.html",
+                "/My%20File.html",
+            ),
+            ("person.example.domain/My%20File.html", "/My File.html"),
+        ];
+
+        for param in params {
+            let url_str = format!("http://{}", param.0);
+            let url = Url::from_str(url_str.as_str()).unwrap();
+            let a = analyze_url(&url, Some(domain), BRANCH_SEPARATOR).unwrap();
+            assert_eq!(a.asset, param.1, "Analyzing {}", param.0)
+        }
+    }
+
+    /// Ensure empty and dot segments can't shift the owner/repo, or escape the page
+    #[test]
+    fn test_analyze_url_normalization() {
+        let domain = "example.domain";
+        let expected = Some(UrlAnalysis {
+            owner: Some("person".to_string()),
+            repo: Some("page".to_string()),
+            branch: None,
+            asset: "/my_asset".to_string(),
+        });
+
+        let params: Vec<(&str, Option<UrlAnalysis>)> = vec![
+            ("example.domain/person/page/my_asset", expected.clone()),
+            ("example.domain//person//page//my_asset", expected.clone()),
+            ("example.domain/person/./page/./my_asset", expected.clone()),
+            ("example.domain/person/%2E/page/my_asset", expected.clone()),
+            (
+                "example.domain/person/other/../page/my_asset",
+                expected.clone(),
+            ),
+            ("example.domain/person/page/..%2Fmy_asset", None),
+            (
+                "example.domain/person/page/%2E%2E%2F%2E%2E%2Fmy_asset",
+                None,
+            ),
+            ("example.domain/person/page%2Fother/my_asset", None),
+            ("example.domain/person/page/..%5Cmy_asset", None),
+        ];
+
+        for param in params {
+            let url_str = format!("http://{}", param.0);
+            let url = Url::from_str(url_str.as_str()).unwrap();
+            let a = analyze_url(&url, Some(domain), BRANCH_SEPARATOR);
             assert_eq!(a, param.1, "Analyzing {}", param.0)
         }
     }
@@ -303,8 +469,142 @@ mod tests {
         for param in params {
             let url_str = format!("http://{}", param.0);
             let url = Url::from_str(url_str.as_str()).unwrap();
-            let a = analyze_url(&url, Some(domain));
+            let a = analyze_url(&url, Some(domain), BRANCH_SEPARATOR);
             assert_eq!(a, param.1, "Analyzing {}", param.0)
         }
     }
+
+    /// Ensure branches can be selected with `/~branch` in subdomain form, or `?branch=` anywhere
+    #[test]
+    fn test_analyze_url_branch_selector() {
+        let domain = "example.domain";
+
+        let params: Vec<(&str, Option<UrlAnalysis>)> = vec![
+            (
+                "person.example.domain/~preview/my_asset",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: None,
+                    branch: Some("preview".to_string()),
+                    asset: "/my_asset".to_string(),
+                }),
+            ),
+            (
+                "page.person.example.domain/~preview",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: Some("page".to_string()),
+                    branch: Some("preview".to_string()),
+                    asset: "/".to_string(),
+                }),
+            ),
+            // The subdomain takes priority, so the segment is just an asset
+            (
+                "unstable.page.person.example.domain/~preview",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: Some("page".to_string()),
+                    branch: Some("unstable".to_string()),
+                    asset: "/~preview".to_string(),
+                }),
+            ),
+            // Only a leading segment selects a branch
+            (
+                "person.example.domain/dir/~preview",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: None,
+                    branch: None,
+                    asset: "/dir/~preview".to_string(),
+                }),
+            ),
+            (
+                "person.example.domain/my_asset?branch=preview",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: None,
+                    branch: Some("preview".to_string()),
+                    asset: "/my_asset".to_string(),
+                }),
+            ),
+            (
+                "example.domain/person/page?branch=preview",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: Some("page".to_string()),
+                    branch: Some("preview".to_string()),
+                    asset: "/".to_string(),
+                }),
+            ),
+            // Explicit path branches take priority over the query
+            (
+                "example.domain/person/page:unstable?branch=preview",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: Some("page".to_string()),
+                    branch: Some("unstable".to_string()),
+                    asset: "/".to_string(),
+                }),
+            ),
+        ];
+
+        for param in params {
+            let url_str = format!("http://{}", param.0);
+            let url = Url::from_str(url_str.as_str()).unwrap();
+            let a = analyze_url(&url, Some(domain), BRANCH_SEPARATOR);
+            assert_eq!(a, param.1, "Analyzing {}", param.0)
+        }
+    }
+
+    /// Ensure the path form branch separator can be changed
+    #[test]
+    fn test_analyze_url_branch_separator() {
+        let domain = "example.domain";
+
+        let params: Vec<(&str, Option<UrlAnalysis>)> = vec![
+            (
+                "example.domain/person/page@unstable/my_asset",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: Some("page".to_string()),
+                    branch: Some("unstable".to_string()),
+                    asset: "/my_asset".to_string(),
+                }),
+            ),
+            // The default separator is no longer special
+            (
+                "example.domain/person/page:unstable",
+                Some(UrlAnalysis {
+                    owner: Some("person".to_string()),
+                    repo: Some("page:unstable".to_string()),
+                    branch: None,
+                    asset: "/".to_string(),
+                }),
+            ),
+        ];
+
+        for param in params {
+            let url_str = format!("http://{}", param.0);
+            let url = Url::from_str(url_str.as_str()).unwrap();
+            let a = analyze_url(&url, Some(domain), '@');
+            assert_eq!(a, param.1, "Analyzing {}", param.0)
+        }
+    }
+
+    /// `*` should match any run of characters, and `?` exactly one
+    #[test]
+    fn glob() {
+        assert!(glob_matches("pages", "pages"));
+        assert!(!glob_matches("pages", "pages-2"));
+        assert!(glob_matches("preview/*", "preview/my-feature"));
+        assert!(glob_matches("preview/*", "preview/"));
+        assert!(!glob_matches("preview/*", "main"));
+        assert!(glob_matches("pages-*", "pages-dark"));
+        assert!(glob_matches("*-preview", "a-b-preview"));
+        assert!(glob_matches("v?", "v1"));
+        assert!(!glob_matches("v?", "v10"));
+        assert!(glob_matches("*", "anything/at/all"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+    }
 }
@@ -0,0 +1,36 @@
+/// Cross-origin resource sharing (CORS), so pages can be fetched by scripts on other origins.
+///
+/// Preflight (`OPTIONS`) requests are answered by the middleware, before reaching the routes.
+/// Requests from origins that aren't allowed are still served, just without CORS headers.
+use actix_cors::Cors;
+use actix_web::middleware::Condition;
+
+use crate::conf::ServerConfigCors;
+
+/// Creates the CORS middleware for a configuration; It does nothing unless CORS is enabled.
+pub fn middleware(config: &ServerConfigCors) -> Condition<Cors> {
+    let cors = Cors::default()
+        .block_on_origin_mismatch(false)
+        .allowed_methods(config.allowed_methods.iter().map(String::as_str))
+        .max_age(config.max_age);
+
+    let cors = match config.allowed_origins.iter().any(|f| f == "*") {
+        true => cors.allow_any_origin(),
+        false => config
+            .allowed_origins
+            .iter()
+            .fold(cors, |cors, origin| cors.allowed_origin(origin)),
+    };
+
+    let cors = match config.allowed_headers.iter().any(|f| f == "*") {
+        true => cors.allow_any_header(),
+        false => cors.allowed_headers(config.allowed_headers.iter().map(String::as_str)),
+    };
+
+    let cors = match config.expose_headers.is_empty() {
+        true => cors,
+        false => cors.expose_headers(config.expose_headers.iter().map(String::as_str)),
+    };
+
+    Condition::new(config.enabled, cors)
+}
@@ -0,0 +1,149 @@
+/// On-the-fly conversion of PNG and JPEG images to WebP, for clients that accept it.
+///
+/// Conversions are cached in memory by the SHA-256 of the content, so each image is only
+/// converted once (until the cache fills up). Images that don't get smaller are served as they are.
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use image::{DynamicImage, ImageFormat};
+use log::{debug, warn};
+use mime_guess::Mime;
+
+use sha2::{Digest, Sha256};
+
+use crate::{conf::ServerConfigImages, provider::layers::transform::AssetTransform};
+
+/// SHA-256 of the content -> WebP content, or None if converting didn't pay off
+///
+/// A cryptographic hash is used (rather than the quicker content hash used for ETags), as
+/// pages could otherwise craft images that collide with another page's.
+type ConversionMap = HashMap<[u8; 32], Option<Arc<Vec<u8>>>>;
+
+/// Converts images to WebP, remembering the results.
+pub struct WebpConverter {
+    cache: Mutex<ConversionMap>,
+    cache_size: usize,
+}
+
+impl WebpConverter {
+    pub fn new(cache_size: usize) -> Self {
+        Self {
+            cache: Mutex::new(HashMap::new()),
+            cache_size,
+        }
+    }
+
+    /// Creates the converter from the server configuration, if WebP conversion is enabled.
+    pub fn from_config(config: &ServerConfigImages) -> Option<Self> {
+        match config.webp {
+            true => Some(Self::new(config.cache_size)),
+            false => None,
+        }
+    }
+
+    /// Converts an image to WebP.
+    ///
+    /// # Returns
+    ///
+    /// - `Option<Arc<Vec<u8>>>` - The WebP content, or None if the image couldn't be
+    ///   converted or wouldn't get smaller.
+    pub fn convert(&self, bytes: &[u8]) -> Option<Arc<Vec<u8>>> {
+        let hash: [u8; 32] = Sha256::digest(bytes).into();
+        if let Some(v) = self.cache.lock().unwrap().get(&hash) {
+            return v.clone();
+        }
+
+        let converted = encode_webp(bytes).filter(|v| v.len() < bytes.len());
+        debug!(
+            "Converted image to WebP: {:?} -> {:?} bytes",
+            bytes.len(),
+            converted.as_ref().map(|v| v.len())
+        );
+        let converted = converted.map(Arc::new);
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.cache_size {
+            cache.clear();
+        }
+        cache.insert(hash, converted.clone());
+        converted
+    }
+}
+
+/// Converts supported images to WebP unconditionally, for use with a
+/// [`TransformLayer`](crate::provider::layers::transform::TransformLayer).
+impl AssetTransform for WebpConverter {
+    fn transform(&self, _path: &Path, mime: &Mime, bytes: Vec<u8>) -> Vec<u8> {
+        if !is_convertible(mime) {
+            return bytes;
+        }
+        match self.convert(&bytes) {
+            Some(v) => v.to_vec(),
+            None => bytes,
+        }
+    }
+}
+
+/// Whether or not images of a type are converted; Formats that are already modern aren't.
+pub fn is_convertible(mime: &Mime) -> bool {
+    matches!(mime.essence_str(), "image/png" | "image/jpeg")
+}
+
+/// Whether or not an `Accept` header value accepts WebP images.
+///
+/// Only an explicit `image/webp` counts; Wildcards are sent by browsers that can't decode it.
+pub fn accepts_webp(accept: &str) -> bool {
+    accept.split(',').any(|v| {
+        let mut params = v.split(';').map(str::trim);
+        params
+            .next()
+            .is_some_and(|v| v.eq_ignore_ascii_case("image/webp"))
+            && params.all(|v| match v.strip_prefix("q=") {
+                Some(q) => q.parse::<f32>().is_ok_and(|q| q > 0.0),
+                None => true,
+            })
+    })
+}
+
+/// Decodes an image and encodes it as (lossless) WebP.
+fn encode_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    let image = match image::load_from_memory(bytes) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Failed to decode image for WebP conversion: {}", e);
+            return None;
+        }
+    };
+    // The WebP encoder only takes 8-bit RGB(A)
+    let image = match image.color().has_alpha() {
+        true => DynamicImage::ImageRgba8(image.to_rgba8()),
+        false => DynamicImage::ImageRgb8(image.to_rgb8()),
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    match image.write_to(&mut out, ImageFormat::WebP) {
+        Ok(()) => Some(out.into_inner()),
+        Err(e) => {
+            warn!("Failed to encode image as WebP: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::accepts_webp;
+
+    #[test]
+    fn accept_header() {
+        assert!(accepts_webp("image/avif,image/webp,*/*"));
+        assert!(accepts_webp("text/html, image/webp;q=0.8"));
+        assert!(!accepts_webp("image/webp;q=0"));
+        assert!(!accepts_webp("image/*,*/*;q=0.8"));
+        assert!(!accepts_webp("image/webpx"));
+    }
+}
@@ -1,15 +1,27 @@
 use std::sync::Arc;
 
 use actix_web::web::{self, ServiceConfig};
+use arc_swap::ArcSwap;
 use minijinja::Environment;
-use routes::{RoutingState, register_routes_to_config};
-use templates::templates_from_builtin;
+use routes::{RoutingState, SharedRoutingState, register_routes_to_config};
 
 use crate::{PageSource, conf::ServerConfig, resolver::UrlResolver};
 
+pub mod cors;
+#[cfg(feature = "images")]
+pub mod images;
+pub mod page_config;
+pub mod precompressed;
+pub mod redirects;
+pub mod request_id;
 pub mod routes;
 pub mod templates;
 
+/// Sets up the service to serve pages with a configuration.
+///
+/// # Panics
+///
+/// If the routing state can't be created (see [`RoutingState::new`]).
 pub fn setup_service_config<
     'a,
     PS: PageSource + Sync + Send + 'static,
@@ -21,17 +33,24 @@ pub fn setup_service_config<
     resolver: UR,
     templates: Option<Environment<'static>>,
 ) -> &'a mut ServiceConfig {
-    let _pages = server_config.upstream.branches.clone();
-    let config = server_config.clone();
-    web_config.app_data(web::Data::new(RoutingState {
-        provider: page_source,
-        jinja: match templates {
-            Some(v) => v.clone(),
-            None => templates_from_builtin(),
-        },
-        config,
-        resolver,
-    }));
+    let state = RoutingState::new(server_config, page_source, resolver, templates)
+        .unwrap_or_else(|e| panic!("{}", e));
+    setup_shared_service_config(web_config, web::Data::new(ArcSwap::from_pointee(state)))
+}
+
+/// Sets up the service with routing state that's shared with the caller, so it can be swapped
+/// out while the server is running.
+///
+/// The same state should be passed to every worker, so they all serve with the same one.
+pub fn setup_shared_service_config<
+    'a,
+    PS: PageSource + Sync + Send + 'static,
+    UR: UrlResolver + 'static,
+>(
+    web_config: &'a mut ServiceConfig,
+    state: web::Data<SharedRoutingState<'static, PS, UR>>,
+) -> &'a mut ServiceConfig {
+    web_config.app_data(state);
     //.wrap(middleware::NormalizePath::trim())
     web_config.configure(|f| {
         register_routes_to_config::<PS, UR>(f);
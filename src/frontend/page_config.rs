@@ -0,0 +1,310 @@
+/// Per-page configuration, read from a `/.pageshelf.toml` (or `/.pageshelf.yaml`) file in the page.
+///
+/// Site authors can change how their page is served with it, but only through a whitelisted
+/// set of settings, each bounded by the server's `[page_config]` section. Files are parsed once
+/// per page version; Settings that aren't part of the schema are ignored.
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use config::{Config, File, FileFormat};
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::{Asset, AssetError, Page, conf::ServerConfigPageConfig, glob_matches};
+
+/// Files a page's configuration is read from, in order of priority.
+pub const PAGE_CONFIG_FILES: [(&str, FileFormat); 2] = [
+    ("/.pageshelf.toml", FileFormat::Toml),
+    ("/.pageshelf.yaml", FileFormat::Yaml),
+];
+
+/// The most index files a page may list; Each one costs a lookup on requests for directories.
+pub const MAX_INDEX_FILES: usize = 4;
+
+/// The longest name of a variant by country, which becomes part of asset file names.
+pub const MAX_VARIANT_LENGTH: usize = 16;
+
+/// Index file used for pages that don't list any.
+const DEFAULT_INDEX_FILE: &str = "index.html";
+
+/// The settings a page may override.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PageConfig {
+    /// Files to serve for directories, tried in order (e.g. `["index.html", "index.htm"]`).
+    /// Must be plain file names, without any directories.
+    #[serde(default)]
+    pub index: Vec<String>,
+    /// Serve the page as a single-page app, or never do so.
+    pub spa: Option<bool>,
+    /// How long (in seconds) clients may cache the page's assets, sent as `Cache-Control`.
+    pub max_age: Option<u32>,
+    /// Variants of assets to serve by the client's country code, when the server knows it
+    /// (e.g. `{ DE = "de" }` serves `index.de.html` in place of `index.html`, if there is one).
+    #[serde(default)]
+    pub countries: HashMap<String, String>,
+    /// Branches that may be served besides the default one, as glob patterns (e.g. `["preview/*"]`).
+    /// Only read from the default branch; If empty, any branch the server serves may be.
+    #[serde(default)]
+    pub branches: Vec<String>,
+}
+
+impl PageConfig {
+    /// Parses a page configuration file.
+    pub fn parse(content: &[u8], format: FileFormat) -> Result<Self, String> {
+        let text = std::str::from_utf8(content).map_err(|e| e.to_string())?;
+        Config::builder()
+            .add_source(File::from_str(text, format))
+            .build()
+            .and_then(|v| v.try_deserialize())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Limits the settings to what the server allows, dropping anything that's out of bounds.
+    pub fn bounded(mut self, limits: &ServerConfigPageConfig) -> Self {
+        self.index.retain(|v| {
+            let safe = !v.is_empty() && !v.starts_with('.') && !v.contains(['/', '\\', '\0']);
+            if !safe {
+                warn!("Ignoring index file \"{}\" of page config", v);
+            }
+            safe
+        });
+        self.index.truncate(MAX_INDEX_FILES);
+        if !limits.spa && self.spa == Some(true) {
+            self.spa = None;
+        }
+        self.max_age = self.max_age.map(|v| v.min(limits.max_age));
+        self.countries = self
+            .countries
+            .into_iter()
+            .filter(|(country, variant)| {
+                let safe = !variant.is_empty()
+                    && variant.len() <= MAX_VARIANT_LENGTH
+                    && variant
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+                if !safe {
+                    warn!(
+                        "Ignoring variant \"{}\" for {} of page config",
+                        variant, country
+                    );
+                }
+                safe
+            })
+            .map(|(country, variant)| (country.to_uppercase(), variant))
+            .collect();
+        self
+    }
+
+    /// Whether or not a (non-default) branch of the page may be served.
+    pub fn allows_branch(&self, branch: &str) -> bool {
+        self.branches.is_empty() || self.branches.iter().any(|v| glob_matches(v, branch))
+    }
+
+    /// The files to serve for directories, in order.
+    pub fn index_files(&self) -> impl Iterator<Item = &str> {
+        let default = self.index.is_empty().then_some(DEFAULT_INDEX_FILE);
+        self.index.iter().map(String::as_str).chain(default)
+    }
+}
+
+/// Page (owner, name, branch) -> The version the config was read at, and the config.
+type PageConfigMap = HashMap<(String, String, String), (String, Arc<PageConfig>)>;
+
+/// Configurations of pages, parsed once per page version.
+#[derive(Default)]
+pub struct PageConfigs {
+    parsed: RwLock<PageConfigMap>,
+}
+
+impl PageConfigs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the configuration of a page, reading it if the page's version wasn't read yet.
+    ///
+    /// Pages without a (valid) configuration file get the default one.
+    pub async fn get(&self, page: &impl Page, limits: &ServerConfigPageConfig) -> Arc<PageConfig> {
+        let key = (
+            page.owner().to_string(),
+            page.name().to_string(),
+            page.branch().to_string(),
+        );
+        if let Some((version, config)) = self.parsed.read().unwrap().get(&key)
+            && version == page.version()
+        {
+            return config.clone();
+        }
+
+        let config = match read(page).await {
+            Ok(v) => Arc::new(v.bounded(limits)),
+            // Not remembered, so it's read again once upstream recovers
+            Err(_) => return Arc::default(),
+        };
+        self.parsed
+            .write()
+            .unwrap()
+            .insert(key, (page.version().to_string(), config.clone()));
+        config
+    }
+
+    /// The configuration last read for a page, whichever version it was read at.
+    ///
+    /// Saves looking the page up just for its configuration, which may then be outdated until
+    /// the page is served again.
+    pub fn last(&self, owner: &str, name: &str, branch: &str) -> Option<Arc<PageConfig>> {
+        let key = (owner.to_string(), name.to_string(), branch.to_string());
+        self.parsed
+            .read()
+            .unwrap()
+            .get(&key)
+            .map(|(_, config)| config.clone())
+    }
+}
+
+/// Reads the configuration file of a page, if it has one.
+async fn read(page: &impl Page) -> Result<PageConfig, AssetError> {
+    for (file, format) in PAGE_CONFIG_FILES {
+        let asset = match page.get_asset(Path::new(file)).await {
+            Ok(v) => v,
+            Err(AssetError::ProviderError) => return Err(AssetError::ProviderError),
+            Err(_) => continue,
+        };
+        debug!(
+            "Reading {} of {}/{}:{}",
+            file,
+            page.owner(),
+            page.name(),
+            page.branch()
+        );
+        return match PageConfig::parse(&asset.into_bytes(), format) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                warn!(
+                    "Ignoring invalid {} of {}/{}:{}: {}",
+                    file,
+                    page.owner(),
+                    page.name(),
+                    page.branch(),
+                    e
+                );
+                Ok(PageConfig::default())
+            }
+        };
+    }
+    Ok(PageConfig::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use config::FileFormat;
+
+    use super::{PageConfig, PageConfigs};
+    use crate::{
+        PageSource, PageSourceFactory,
+        conf::ServerConfig,
+        provider::memory::{MemoryAsset, MemoryPageProviderFactory},
+    };
+
+    /// Both formats should parse to the same settings, ignoring ones outside the schema
+    #[test]
+    fn parse_formats() {
+        let toml = PageConfig::parse(
+            b"index = [\"home.html\"]\nspa = true\nmax_age = 60\nport = 1",
+            FileFormat::Toml,
+        )
+        .unwrap();
+        let yaml = PageConfig::parse(
+            b"index:\n  - home.html\nspa: true\nmax_age: 60\nport: 1",
+            FileFormat::Yaml,
+        )
+        .unwrap();
+        assert_eq!(toml, yaml);
+        assert_eq!(toml.index_files().collect::<Vec<_>>(), vec!["home.html"]);
+
+        assert!(PageConfig::parse(b"spa = \"nya\"", FileFormat::Toml).is_err());
+
+        let config = PageConfig::parse(b"branches = [\"preview/*\"]", FileFormat::Toml).unwrap();
+        assert!(config.allows_branch("preview/new"));
+        assert!(!config.allows_branch("secret"));
+        assert!(PageConfig::default().allows_branch("secret"));
+        assert_eq!(
+            PageConfig::default().index_files().collect::<Vec<_>>(),
+            vec!["index.html"]
+        );
+    }
+
+    /// Settings should be kept within what the server allows
+    #[test]
+    fn bounded() {
+        let mut limits = ServerConfig::default().page_config;
+        limits.spa = false;
+        limits.max_age = 60;
+
+        let config = PageConfig {
+            index: vec![
+                "../secret".to_string(),
+                ".env".to_string(),
+                "a/index.html".to_string(),
+                "home.html".to_string(),
+            ],
+            spa: Some(true),
+            max_age: Some(3600),
+            countries: [("de", "de"), ("FR", "../fr")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+            ..PageConfig::default()
+        }
+        .bounded(&limits);
+        assert_eq!(config.index, vec!["home.html"]);
+        assert_eq!(
+            config.countries,
+            [("DE".to_string(), "de".to_string())].into()
+        );
+        assert_eq!(config.spa, None);
+        assert_eq!(config.max_age, Some(60));
+
+        // Opting out is always allowed
+        let config = PageConfig {
+            spa: Some(false),
+            ..PageConfig::default()
+        }
+        .bounded(&limits);
+        assert_eq!(config.spa, Some(false));
+    }
+
+    /// The last read configuration should be remembered, until it's read again
+    #[tokio::test]
+    async fn last() {
+        let provider = MemoryPageProviderFactory::new()
+            .with_asset(
+                "owner",
+                "pages",
+                "pages",
+                Path::new("/.pageshelf.toml"),
+                MemoryAsset::from("branches = [\"preview/*\"]"),
+            )
+            .build();
+        let page = provider
+            .page_at(
+                "owner".to_string(),
+                "pages".to_string(),
+                "pages".to_string(),
+            )
+            .await
+            .unwrap();
+        let configs = PageConfigs::new();
+        let limits = ServerConfig::default().page_config;
+
+        assert!(configs.last("owner", "pages", "pages").is_none());
+        let config = configs.get(&page, &limits).await;
+        assert!(!config.allows_branch("secret"));
+        assert_eq!(configs.last("owner", "pages", "pages"), Some(config));
+        assert!(configs.last("owner", "other", "pages").is_none());
+    }
+}
@@ -0,0 +1,91 @@
+/// Serving precompressed variants of assets (e.g. `/app.js.zst` for `/app.js`), which site
+/// generators can emit ahead of time, to clients that accept their encoding.
+///
+/// Encodings are negotiated by the `Accept-Encoding` header's q-values; Ties go to the
+/// encoding listed first here, as it compresses best.
+use std::path::{Path, PathBuf};
+
+/// Content encodings with a precompressed variant, and the extension their variants have.
+pub const PRECOMPRESSED: [(&str, &str); 3] = [("zstd", "zst"), ("br", "br"), ("gzip", "gz")];
+
+/// The encodings (of [`PRECOMPRESSED`]) an `Accept-Encoding` header value accepts,
+/// most preferred first.
+pub fn accepted_encodings(accept_encoding: &str) -> Vec<&'static str> {
+    let mut wildcard = None;
+    let mut qualities = Vec::new();
+    for value in accept_encoding.split(',') {
+        let mut params = value.split(';').map(str::trim);
+        let Some(coding) = params.next().filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        let q = params
+            .find_map(|v| v.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        match coding {
+            "*" => wildcard = Some(q),
+            coding => qualities.push((coding.to_ascii_lowercase(), q)),
+        }
+    }
+
+    let mut encodings: Vec<(&str, f32)> = PRECOMPRESSED
+        .iter()
+        .filter_map(|(encoding, _)| {
+            let q = qualities
+                .iter()
+                .find(|(v, _)| v == encoding)
+                .map(|(_, q)| *q)
+                .or(wildcard)?;
+            (q > 0.0).then_some((*encoding, q))
+        })
+        .collect();
+    // Stable, so equally preferred encodings keep their order
+    encodings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    encodings.into_iter().map(|(v, _)| v).collect()
+}
+
+/// The path of an asset's variant in an encoding (e.g. `/app.js.zst` for `/app.js` and `zstd`).
+pub fn precompressed_path(file: &Path, encoding: &str) -> Option<PathBuf> {
+    let (_, extension) = PRECOMPRESSED.iter().find(|(v, _)| *v == encoding)?;
+    let name = file.file_name()?.to_str()?;
+    Some(file.with_file_name(format!("{}.{}", name, extension)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{accepted_encodings, precompressed_path};
+
+    /// Encodings should be ordered by q-value, then by how well they compress
+    #[test]
+    fn accepted() {
+        assert_eq!(
+            accepted_encodings("gzip, deflate, br, zstd"),
+            vec!["zstd", "br", "gzip"]
+        );
+        assert_eq!(
+            accepted_encodings("gzip;q=1.0, zstd;q=0.5, br;q=0"),
+            vec!["gzip", "zstd"]
+        );
+        assert_eq!(
+            accepted_encodings("*;q=0.1, gzip"),
+            vec!["gzip", "zstd", "br"]
+        );
+        assert_eq!(accepted_encodings("identity"), Vec::<&str>::new());
+        assert_eq!(accepted_encodings("ZSTD;q=nya"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            precompressed_path(Path::new("/app.js"), "zstd").unwrap(),
+            Path::new("/app.js.zst")
+        );
+        assert_eq!(
+            precompressed_path(Path::new("/docs/LICENSE"), "gzip").unwrap(),
+            Path::new("/docs/LICENSE.gz")
+        );
+        assert!(precompressed_path(Path::new("/app.js"), "deflate").is_none());
+    }
+}
@@ -0,0 +1,288 @@
+/// Netlify-style `_redirects` files, read from the root of a page.
+///
+/// Every line is a rule of `<from> <to> [status]`; Lines starting with `#` are comments.
+/// Paths may capture a segment with a placeholder (`/blog/:slug`) or the rest of the path with
+/// a trailing splat (`/old/*`), which the target can use as `:slug` and `:splat`.
+/// Rules only apply to paths that don't match an asset; The first matching rule wins.
+/// A `200` status rewrites the path (serving another asset under the same URL),
+/// and `3xx` statuses redirect to the target. Files are parsed once per page version.
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use log::{debug, warn};
+
+use crate::{Asset, AssetError, Page};
+
+/// The file a page's rules are read from.
+pub const REDIRECTS_FILE: &str = "/_redirects";
+
+/// A rule of a `_redirects` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectRule {
+    /// The path the rule applies to.
+    pub from: String,
+    /// The path (or URL, for redirects) to send requests to.
+    pub to: String,
+    /// `200` for rewrites, otherwise the redirect status.
+    pub status: u16,
+}
+
+impl RedirectRule {
+    /// Matches a path against the rule, capturing its placeholders and splat (as `splat`).
+    fn captures(&self, path: &str) -> Option<Vec<(&str, String)>> {
+        let patterns: Vec<&str> = segments(&self.from).collect();
+        let parts: Vec<&str> = segments(path).collect();
+        let mut captures = Vec::new();
+        for (i, pattern) in patterns.iter().enumerate() {
+            if *pattern == "*" && i == patterns.len() - 1 {
+                let rest = parts.get(i..).unwrap_or_default();
+                captures.push(("splat", rest.join("/")));
+                return Some(captures);
+            }
+            let part = parts.get(i)?;
+            match pattern.strip_prefix(':') {
+                Some(name) => captures.push((name, part.to_string())),
+                None if pattern == part => {}
+                None => return None,
+            }
+        }
+        (patterns.len() == parts.len()).then_some(captures)
+    }
+
+    /// The target of the rule, with captured segments substituted for their placeholders.
+    fn target(&self, captures: &[(&str, String)]) -> String {
+        self.to
+            .split('/')
+            .map(|segment| {
+                let value = segment
+                    .strip_prefix(':')
+                    .and_then(|name| captures.iter().find(|(v, _)| *v == name));
+                match value {
+                    Some((_, v)) => v.as_str(),
+                    None => segment,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// A rule that applies to a path, with its placeholders filled in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectMatch {
+    /// The path (or URL, for redirects) to send the request to.
+    pub to: String,
+    /// `200` for rewrites, otherwise the redirect status.
+    pub status: u16,
+}
+
+impl RedirectMatch {
+    /// Whether or not the request is served from another path, rather than redirected to it.
+    pub fn is_rewrite(&self) -> bool {
+        self.status == 200
+    }
+}
+
+/// The rules of a page, in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redirects {
+    rules: Vec<RedirectRule>,
+}
+
+impl Redirects {
+    /// Parses a `_redirects` file, skipping (and logging) lines that aren't valid rules.
+    pub fn parse(content: &str) -> Self {
+        let mut rules = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_rule(line) {
+                Some(rule) => rules.push(rule),
+                None => warn!("Ignoring invalid redirect rule on line {}", number + 1),
+            }
+        }
+        Self { rules }
+    }
+
+    /// Finds the first rule that applies to a path, and where it sends the path.
+    pub fn find(&self, path: &str) -> Option<RedirectMatch> {
+        self.rules.iter().find_map(|rule| {
+            Some(RedirectMatch {
+                to: rule.target(&rule.captures(path)?),
+                status: rule.status,
+            })
+        })
+    }
+}
+
+/// Parses a single (non-empty, non-comment) rule line.
+fn parse_rule(line: &str) -> Option<RedirectRule> {
+    let mut parts = line.split_whitespace();
+    let from = parts.next()?;
+    let to = parts.next()?;
+    // Forced rules (`301!`) are accepted, but apply like any other
+    let status = match parts.next() {
+        Some(v) => v.trim_end_matches('!').parse().ok()?,
+        None => 301,
+    };
+    if !from.starts_with('/') || !(status == 200 || (300..400).contains(&status)) {
+        return None;
+    }
+    // Only paths within the page can be rewritten to, as nothing is proxied
+    if status == 200 && !to.starts_with('/') {
+        return None;
+    }
+    // Nor can they go up (or stay on) a level, to reach past the page root
+    if status == 200 && segments(to).any(|v| v == "." || v == "..") {
+        return None;
+    }
+    Some(RedirectRule {
+        from: from.to_string(),
+        to: to.to_string(),
+        status,
+    })
+}
+
+/// The segments of a path, ignoring empty ones (so trailing slashes don't matter).
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|v| !v.is_empty())
+}
+
+/// Page (owner, name, branch) -> The version the rules were read at, and the rules.
+type RedirectsMap = HashMap<(String, String, String), (String, Arc<Redirects>)>;
+
+/// Redirect rules of pages, parsed once per page version.
+#[derive(Default)]
+pub struct PageRedirects {
+    parsed: RwLock<RedirectsMap>,
+}
+
+impl PageRedirects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the rules of a page, reading them if the page's version wasn't read yet.
+    pub async fn get(&self, page: &impl Page) -> Arc<Redirects> {
+        let key = (
+            page.owner().to_string(),
+            page.name().to_string(),
+            page.branch().to_string(),
+        );
+        if let Some((version, redirects)) = self.parsed.read().unwrap().get(&key)
+            && version == page.version()
+        {
+            return redirects.clone();
+        }
+
+        let redirects = match page.get_asset(Path::new(REDIRECTS_FILE)).await {
+            Ok(v) => {
+                debug!(
+                    "Reading redirects of {}/{}:{}",
+                    page.owner(),
+                    page.name(),
+                    page.branch()
+                );
+                Arc::new(Redirects::parse(&String::from_utf8_lossy(&v.into_bytes())))
+            }
+            // Not remembered, so it's read again once upstream recovers
+            Err(AssetError::ProviderError) => return Arc::default(),
+            Err(_) => Arc::default(),
+        };
+        self.parsed
+            .write()
+            .unwrap()
+            .insert(key, (page.version().to_string(), redirects.clone()));
+        redirects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RedirectRule, Redirects};
+
+    /// Valid rules should be kept in order, and anything else skipped
+    #[test]
+    fn parse_rules() {
+        let redirects = Redirects::parse(
+            "# Moved pages\n\
+             /old /new\n\
+             /blog/ https://blog.example.domain 302\n\
+             /app /index.html 200\n\
+             /forced /new 301!\n\
+             \n\
+             /missing-target\n\
+             /proxy https://example.domain 200\n\
+             /gone /new 410\n\
+             /escape /../../secret 200\n\
+             /here /docs/./index.html 200\n\
+             relative /new",
+        );
+        let rule = |from: &str, to: &str, status| RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+        };
+        assert_eq!(
+            redirects.rules,
+            vec![
+                rule("/old", "/new", 301),
+                rule("/blog/", "https://blog.example.domain", 302),
+                rule("/app", "/index.html", 200),
+                rule("/forced", "/new", 301),
+            ]
+        );
+    }
+
+    /// Paths should match regardless of trailing slashes, and the first rule should win
+    #[test]
+    fn find_rule() {
+        let redirects =
+            Redirects::parse("/blog /posts 301\n/blog/ /other 302\n/app /index.html 200");
+        assert_eq!(redirects.find("/blog/").unwrap().to, "/posts");
+        assert_eq!(redirects.find("blog").unwrap().to, "/posts");
+        assert!(redirects.find("/app").unwrap().is_rewrite());
+        assert!(redirects.find("/blog/post").is_none());
+    }
+
+    /// Splats should capture the rest of the path, and be substituted into the target
+    #[test]
+    fn find_splat() {
+        let redirects = Redirects::parse(
+            "/old/* /new/:splat 301\n/docs/*/edit /editor 302\n/* https://example.domain/:splat",
+        );
+        let to = |path: &str| redirects.find(path).map(|v| v.to);
+        assert_eq!(to("/old/a/b.html").unwrap(), "/new/a/b.html");
+        assert_eq!(to("/old").unwrap(), "/new/");
+        // Splats only capture at the end of a path
+        assert_eq!(
+            to("/docs/page/edit").unwrap(),
+            "https://example.domain/docs/page/edit"
+        );
+        assert_eq!(to("/").unwrap(), "https://example.domain/");
+    }
+
+    /// Placeholders should capture one segment each, and the first matching rule should win
+    #[test]
+    fn find_placeholders() {
+        let redirects = Redirects::parse(
+            "/blog/featured /highlights 302\n\
+             /blog/:year/:slug /posts/:slug/:year 301\n\
+             /blog/:slug /posts/:slug 301\n\
+             /tag/:name /tags/:unknown/:name 200",
+        );
+        let to = |path: &str| redirects.find(path).map(|v| v.to);
+        assert_eq!(to("/blog/featured").unwrap(), "/highlights");
+        assert_eq!(to("/blog/hello/").unwrap(), "/posts/hello");
+        assert_eq!(to("/blog/2025/hello").unwrap(), "/posts/hello/2025");
+        assert!(to("/blog/2025/hello/more").is_none());
+        assert!(to("/blog").is_none());
+        // Placeholders that weren't captured are left as they are
+        assert_eq!(to("/tag/rust").unwrap(), "/tags/:unknown/rust");
+    }
+}
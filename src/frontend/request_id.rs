@@ -0,0 +1,87 @@
+/// Per-request correlation IDs, so log lines belonging to one request can be told apart.
+///
+/// The ID is taken from an incoming `X-Request-Id` header (if it's sane) or generated,
+/// made available to logging via [`current`], and echoed back in the response.
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    Error,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+};
+
+/// Header that carries the request ID, both in requests and responses.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest incoming request ID that will be reused, rather than replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The ID of the request currently being handled, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Generates a request ID that is unique within this process.
+fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let start = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or(0);
+    format!("{:x}-{:x}", start, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Whether or not an incoming request ID is safe to reuse (and put in logs).
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Middleware that assigns every request an ID, for use with [`actix_web::middleware::from_fn`].
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| is_valid(v))
+        .map(|v| v.to_string())
+        .unwrap_or_else(generate);
+
+    let mut res = REQUEST_ID.scope(id.clone(), next.call(req)).await?;
+    if let Ok(v) = HeaderValue::from_str(&id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), v);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, is_valid};
+
+    #[test]
+    fn request_id_validation() {
+        assert!(is_valid("abc-123"));
+        assert!(is_valid(&generate()));
+        assert!(!is_valid(""));
+        assert!(!is_valid("has space"));
+        assert!(!is_valid("new\nline"));
+        assert!(!is_valid(&"a".repeat(129)));
+        assert_ne!(generate(), generate());
+    }
+}
@@ -0,0 +1,165 @@
+/// Admin endpoint listing the domains the server serves, and which pages custom domains lead to.
+use std::{collections::BTreeMap, path::Path};
+
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    http::{
+        StatusCode,
+        header::{CacheControl, CacheDirective},
+    },
+    web,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Asset, AssetSource, DOMAIN_FILE_PATH, Page, PageError, PageLocation, PageSource,
+    domain_file_entries,
+    frontend::routes::{
+        SharedRoutingState,
+        errors::{json_error_response, unauthorized_response},
+        stats::is_authorized,
+    },
+    resolver::UrlResolver,
+};
+
+/// Path of the domains endpoint.
+pub const DOMAINS_PATH: &str = "/.pageshelf/domains";
+
+/// A page (branch) a domain is associated with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DomainPage {
+    pub owner: String,
+    pub repo: String,
+    pub branch: String,
+}
+
+impl From<&PageLocation> for DomainPage {
+    fn from(value: &PageLocation) -> Self {
+        Self {
+            owner: value.owner.clone(),
+            repo: value.name.clone(),
+            branch: value.branch.clone(),
+        }
+    }
+}
+
+/// A custom domain, declared by pages in their domain file.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CustomDomain {
+    /// The page requests for the domain are served from; None if upstream failed to tell.
+    pub serving: Option<DomainPage>,
+    /// Every page declaring the domain; More than one is a conflict.
+    pub declared_by: Vec<DomainPage>,
+}
+
+/// The domains the server serves, as configured.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Domains {
+    /// Host of the home domain (`url`), if there is one.
+    pub home_domain: Option<String>,
+    /// Hosts of the page domains (`pages_urls`).
+    pub page_domains: Vec<String>,
+    /// Whether or not custom domains are served (`allow_domains`).
+    pub custom_domains_enabled: bool,
+    /// Custom domains declared by pages, by domain.
+    pub custom_domains: BTreeMap<String, CustomDomain>,
+}
+
+/// Lists the configured domains, and the custom domains pages declare.
+///
+/// Custom domains come from the provider's domain index if it keeps one; Otherwise, the domain
+/// file of every page is read.
+pub async fn get_domains<'a, PS: PageSource, UR: UrlResolver>(
+    state: web::Data<SharedRoutingState<'a, PS, UR>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let data = state.load_full();
+    match is_authorized(&req, &data.config) {
+        None => return json_error_response(StatusCode::NOT_FOUND, "Not found"),
+        Some(false) => {
+            info!("Refusing unauthorized request to list domains");
+            return unauthorized_response();
+        }
+        Some(true) => {}
+    }
+
+    let custom_domains = match data.provider.domain_index().await {
+        Some(index) => index
+            .into_iter()
+            .map(|(domain, claims)| {
+                let mut declared_by: Vec<DomainPage> =
+                    claims.declared_by.iter().map(DomainPage::from).collect();
+                declared_by.sort_by(|a, b| {
+                    (&a.owner, &a.repo, &a.branch).cmp(&(&b.owner, &b.repo, &b.branch))
+                });
+                let serving = Some(DomainPage::from(&claims.serving));
+                (
+                    domain,
+                    CustomDomain {
+                        serving,
+                        declared_by,
+                    },
+                )
+            })
+            .collect(),
+        None => match read_domain_files(data.provider.as_ref()).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to list pages for domains: {}", e);
+                return json_error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to list pages",
+                );
+            }
+        },
+    };
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(Domains {
+            home_domain: data.resolver.home_domain().map(str::to_string),
+            page_domains: data.resolver.page_domains().to_vec(),
+            custom_domains_enabled: data.resolver.external_enabled(),
+            custom_domains,
+        })
+}
+
+/// Finds the custom domains pages declare by reading the domain file of every page.
+async fn read_domain_files(
+    provider: &impl PageSource,
+) -> Result<BTreeMap<String, CustomDomain>, PageError> {
+    let mut declared: BTreeMap<String, Vec<DomainPage>> = BTreeMap::new();
+    for page in provider.pages().await? {
+        let Ok(asset) = page.get_asset(Path::new(DOMAIN_FILE_PATH)).await else {
+            continue;
+        };
+        let Ok(body) = asset.body() else {
+            continue;
+        };
+        for domain in domain_file_entries(body) {
+            declared
+                .entry(domain.to_string())
+                .or_default()
+                .push(DomainPage::from(&page.location()));
+        }
+    }
+
+    // Looked up the same way as when serving, so conflicts show which page wins
+    let mut custom_domains = BTreeMap::new();
+    for (domain, declared_by) in declared {
+        let serving = provider
+            .find_by_domains(vec![domain.clone()])
+            .await
+            .ok()
+            .map(|v| DomainPage::from(&v.location()));
+        custom_domains.insert(
+            domain,
+            CustomDomain {
+                serving,
+                declared_by,
+            },
+        );
+    }
+    Ok(custom_domains)
+}
@@ -0,0 +1,39 @@
+/// Errors of the machine-consumed endpoints (`/.pageshelf/*`), as JSON rather than the HTML
+/// error pages shown to visitors of pages.
+use actix_web::{
+    HttpResponse,
+    http::{
+        StatusCode,
+        header::{self, CacheControl, CacheDirective},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+/// The body of an error response, e.g. `{"error": "Invalid URL: ...", "code": 400}`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct JsonError {
+    /// What went wrong, for humans.
+    pub error: String,
+    /// The HTTP status of the response.
+    pub code: u16,
+}
+
+/// Responds with an error as JSON.
+pub fn json_error_response(status: StatusCode, error: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(JsonError {
+            error: error.into(),
+            code: status.as_u16(),
+        })
+}
+
+/// Responds that the admin token is missing or wrong.
+pub fn unauthorized_response() -> HttpResponse {
+    let mut response = json_error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Bearer"),
+    );
+    response
+}
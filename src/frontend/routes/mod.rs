@@ -1,12 +1,35 @@
 use std::sync::Arc;
 
 use actix_web::web::{self, ServiceConfig};
+use arc_swap::ArcSwap;
 use minijinja::Environment;
+use subtle::ConstantTimeEq;
 
-use crate::{PageSource, conf::ServerConfig, resolver::UrlResolver};
+use crate::{
+    PageSource,
+    conf::ServerConfig,
+    frontend::{
+        page_config::PageConfigs,
+        redirects::PageRedirects,
+        templates::{ensure_builtin_templates, templates_from_builtin},
+    },
+    resolver::UrlResolver,
+};
 
+pub mod domains;
+pub mod errors;
 pub mod pages;
+pub mod resolve;
 pub mod server;
+pub mod stats;
+
+/// Whether or not a token given by a client is the configured one.
+///
+/// Compares in constant time, so the configured token can't be guessed from how long a
+/// mismatch takes to refuse.
+pub(crate) fn token_matches(given: &str, token: &str) -> bool {
+    given.trim().as_bytes().ct_eq(token.as_bytes()).into()
+}
 
 /// This serves as state for the Actix server.
 pub struct RoutingState<'a, PS: PageSource, UR: UrlResolver> {
@@ -14,6 +37,54 @@ pub struct RoutingState<'a, PS: PageSource, UR: UrlResolver> {
     pub config: ServerConfig,
     pub jinja: Environment<'a>,
     pub resolver: UR,
+    /// Configurations read from pages, if enabled.
+    pub page_configs: PageConfigs,
+    /// `_redirects` rules read from pages, if enabled.
+    pub page_redirects: PageRedirects,
+    /// Converts images to WebP, if enabled.
+    #[cfg(feature = "images")]
+    pub webp: Option<Arc<crate::frontend::images::WebpConverter>>,
+}
+
+/// Routing state that can be replaced while the server is running (e.g. to reload the config).
+///
+/// Requests use the state that was current when they arrived.
+pub type SharedRoutingState<'a, PS, UR> = ArcSwap<RoutingState<'a, PS, UR>>;
+
+impl<PS: PageSource, UR: UrlResolver> RoutingState<'static, PS, UR> {
+    /// Creates the state for serving pages with a configuration.
+    ///
+    /// # Arguments
+    ///
+    /// - `config` (`&ServerConfig`) - The configuration to serve with.
+    /// - `provider` (`Arc<PS>`) - Where to get pages from.
+    /// - `resolver` (`UR`) - How to tell which page a URL is for.
+    /// - `templates` (`Option<Environment<'static>>`) - Templates to use instead of the built-in ones.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Self, String>` - An error if the templates lack one that routes need, and
+    ///   there's no built-in one to fall back to.
+    pub fn new(
+        config: &ServerConfig,
+        provider: Arc<PS>,
+        resolver: UR,
+        templates: Option<Environment<'static>>,
+    ) -> Result<Self, String> {
+        let mut jinja = templates.unwrap_or_else(templates_from_builtin);
+        // Routes expect these to exist, so fail now rather than panicking mid-request
+        ensure_builtin_templates(&mut jinja)?;
+        Ok(Self {
+            provider,
+            jinja,
+            #[cfg(feature = "images")]
+            webp: crate::frontend::images::WebpConverter::from_config(&config.images).map(Arc::new),
+            config: config.clone(),
+            resolver,
+            page_configs: PageConfigs::new(),
+            page_redirects: PageRedirects::new(),
+        })
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -21,10 +92,28 @@ pub struct RoutingState<'a, PS: PageSource, UR: UrlResolver> {
 /* -------------------------------------------------------------------------- */
 
 /// Register default routes for the server to an Actix configuration.
+///
+/// Pages only support `GET` and `HEAD`; Any other method responds `405 Method Not Allowed`.
 pub fn register_routes_to_config<PS: PageSource + 'static, UR: UrlResolver + 'static>(
     config: &mut ServiceConfig,
 ) -> &mut ServiceConfig {
+    // Uptime counts from when the server is set up, not from the first request for statistics
+    std::sync::LazyLock::force(&stats::STARTED);
     config
         .service(server::get_favicon_webp)
-        .route("/{tail:.*}", web::get().to(server::get_index::<PS, UR>))
+        .route(stats::STATS_PATH, web::get().to(stats::get_stats::<PS, UR>))
+        .route(
+            domains::DOMAINS_PATH,
+            web::get().to(domains::get_domains::<PS, UR>),
+        )
+        .route(
+            resolve::RESOLVE_PATH,
+            web::get().to(resolve::get_resolve::<PS, UR>),
+        )
+        .service(
+            web::resource("/{tail:.*}")
+                .route(web::get().to(server::get_index::<PS, UR>))
+                .route(web::head().to(server::get_index::<PS, UR>))
+                .default_service(web::to(server::method_not_allowed)),
+        )
 }
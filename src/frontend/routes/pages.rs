@@ -1,14 +1,35 @@
 /// A set of utilities for querying pages and getting an HTTP output.
-use std::{path::Path, str::FromStr};
+use std::{
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
-use actix_web::{HttpResponse, http::StatusCode, web};
+use actix_web::{
+    HttpResponse,
+    http::{
+        StatusCode,
+        header::{self, HeaderValue},
+    },
+    web,
+};
 use log::{debug, error, info};
 use mime_guess::Mime;
 use minijinja::context;
 
+#[cfg(feature = "images")]
+use crate::frontend::images;
 use crate::{
-    Asset, AssetSource, PageSource, RoutingState,
-    frontend::templates::{TEMPLATE_ERROR, TemplateErrorContext, TemplatePageContext},
+    Asset, AssetError, Page, PageError, PageSource, RoutingState, content_hash,
+    frontend::{
+        page_config::PageConfig,
+        precompressed,
+        redirects::RedirectMatch,
+        templates::{
+            TEMPLATE_COMING_SOON, TEMPLATE_ERROR, TemplateErrorContext, TemplatePageContext,
+        },
+    },
+    glob_matches,
     resolver::UrlResolver,
 };
 
@@ -16,6 +37,33 @@ use crate::{
 /*                                Data Querying                               */
 /* -------------------------------------------------------------------------- */
 
+/// Details of the request an asset is served for, that affect how it's served.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct AssetRequest<'r> {
+    /// The path the page is served under (e.g. `/owner/repo`), if any;
+    /// Root-relative links in its HTML are rewritten to it if `rewrite_path_links` is enabled.
+    pub link_base: Option<&'r str>,
+    /// The request's `Accept` header, used to negotiate image formats.
+    pub accept: Option<&'r str>,
+    /// The request's `Accept-Encoding` header, used to negotiate precompressed variants.
+    pub accept_encoding: Option<&'r str>,
+    /// The request's `If-None-Match` header, compared against the ETag of the served asset.
+    pub if_none_match: Option<&'r str>,
+    /// How long (in seconds) clients may cache the asset, as set by the page's config.
+    pub max_age: Option<u32>,
+    /// Branch to serve instead of the one the URL resolved to, from an authorized
+    /// `X-Pageshelf-Branch` header.
+    pub branch: Option<&'r str>,
+    /// The client's country code, from the configured `geo_header` (with the `geo` feature).
+    pub country: Option<&'r str>,
+    /// Variant of assets to prefer (e.g. `de` serves `index.de.html` for `index.html`),
+    /// as the page's config picks for the client's country.
+    pub variant: Option<&'r str>,
+    /// Whether or not the asset is served in place of a missing one (a soft 404), so it isn't
+    /// validated or cached as if it were the requested path.
+    pub soft_404: bool,
+}
+
 /// Attempts to get a Page, given parameters.
 ///
 /// Will result in a 200 OK response if successful, otherwise will check for index or 404.
@@ -25,39 +73,328 @@ pub async fn get_page_response<'a, PS: PageSource, UR: UrlResolver>(
     repo: Option<&str>,
     channel: Option<&str>,
     file: &Path,
+    request: AssetRequest<'_>,
 ) -> HttpResponse {
     let owner = owner.unwrap_or(data.config.default_user.as_str());
-    let repo = repo.unwrap_or("pages");
+    let repo = repo.unwrap_or(data.config.upstream.default_repo.as_str());
+    if let Some(v) = request.branch {
+        info!("Previewing branch \"{}\" as requested by header", v);
+    }
+    let channel = request.branch.or(channel);
 
     match channel {
         Some(v) => info!("Accessing page {}/{} (Branch \"{}\")...", owner, repo, v),
         None => info!("Accessing page {}/{} (No specified branch)...", owner, repo),
     }
 
-    let primary = match file.is_dir() {
-        false => {
-            let buf = file;
-            get_page_response_raw(data, owner, repo, channel, buf, 200).await
+    let branch = match channel {
+        Some(v) => v,
+        None => &data.config.upstream.default_branch,
+    };
+
+    // Resolve the page once, and reuse it for every fallback
+    let page = match resolve_page(data, owner, repo, branch).await {
+        Ok(v) => v,
+        Err(e) => return e.0,
+    };
+
+    let overrides = match data.config.page_config.enabled {
+        true => data.page_configs.get(&page, &data.config.page_config).await,
+        false => Arc::default(),
+    };
+    let request = AssetRequest {
+        max_age: overrides.max_age,
+        variant: request
+            .country
+            .and_then(|v| overrides.countries.get(&v.to_uppercase()))
+            .map(String::as_str),
+        ..request
+    };
+
+    // A rewrite may lead to another, so the lookup is repeated for every one of them
+    let mut file = file.to_path_buf();
+    let mut rewrites = Vec::new();
+    loop {
+        for path in served_paths(data, &file, &overrides) {
+            if let Some(v) = try_asset_response(data, &page, owner, repo, &path, request).await {
+                return v;
+            }
         }
-        true => {
-            let file = file.join("index.html");
-            get_page_response_raw(data, owner, repo, channel, &file, 200).await
+
+        if !data.config.redirects.enabled {
+            break;
+        }
+        let redirects = data.page_redirects.get(&page).await;
+        let path = file.to_string_lossy();
+        let Some(rule) = redirects.find(&path) else {
+            break;
+        };
+        if !rule.is_rewrite() {
+            info!("Redirecting {} to {} ({})", path, rule.to, rule.status);
+            return redirect_response(&rule, request.link_base);
+        }
+
+        rewrites.push(path.to_string());
+        if rewrites.len() > data.config.redirects.max_rewrites {
+            error!(
+                "Too many rewrites in {}/{} (a rewrite loop?): {} -> {}",
+                owner,
+                repo,
+                rewrites.join(" -> "),
+                rule.to
+            );
+            return loop_detected_response(data, owner, repo);
         }
+        debug!("404'd, rewriting {} to {}...", path, rule.to);
+        file = PathBuf::from(&rule.to);
+    }
+    let file = file.as_path();
+
+    if let Some(root_asset) = &data.config.root_asset
+        && is_page_root(file)
+    {
+        debug!("404'd, trying the root asset {}...", root_asset);
+        let root = Path::new(root_asset);
+        if let Some(v) = try_asset_response(data, &page, owner, repo, root, request).await {
+            return v;
+        }
+    }
+
+    if is_spa_route(data, &page, &overrides, file).await {
+        debug!("404'd, falling back to the single-page app index...");
+        let root = Path::new("/");
+        if let Some(v) =
+            try_index_response(data, &page, owner, repo, root, &overrides, request).await
+        {
+            return v;
+        }
+    }
+
+    debug!("404'd, trying to see if there's a custom 404 here...");
+    let code = match data.config.soft_404 {
+        true => 200,
+        false => 404,
     };
-    if primary.1 == 404 {
-        let p = file.join("./index.html");
-        debug!("404'd, trying to see if there's an index here...");
-        let secondary = get_page_response_raw(data, owner, repo, channel, &p, 200).await;
+    get_asset_response(
+        data,
+        &page,
+        owner,
+        repo,
+        Path::new("./404.html"),
+        code,
+        AssetRequest {
+            soft_404: data.config.soft_404,
+            ..request
+        },
+    )
+    .await
+    .0
+}
 
-        if secondary.1 == 404 {
-            debug!("404'd, trying to see if there's a custom 404 here...");
-            return get_page_response_raw(data, owner, repo, channel, Path::new("./404.html"), 404)
-                .await
-                .0;
+/// Gets an asset as a response, unless it's missing (or responds 404 anyway, e.g. as it's denied).
+///
+/// Whether the asset exists is checked first, so missing ones aren't fetched.
+async fn try_asset_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    page: &impl Page,
+    owner: &str,
+    repo: &str,
+    file: &Path,
+    request: AssetRequest<'_>,
+) -> Option<HttpResponse> {
+    // Denied paths (e.g. going up a level) are never looked up, even to check they exist
+    if is_refused(data, file) {
+        debug!(
+            "Not looking up denied asset {:?} in {}/{}",
+            file, owner, repo
+        );
+        return None;
+    }
+    if let Some(variant) = request.variant.and_then(|v| variant_path(file, v))
+        && page.asset_exists(&variant).await == Ok(true)
+    {
+        debug!("Serving variant {:?} of {:?}", variant, file);
+        let (response, code) =
+            get_asset_response(data, page, owner, repo, &variant, 200, request).await;
+        if code != 404 {
+            return Some(response);
         }
-        return secondary.0;
     }
-    primary.0
+
+    // Failing to check is left for fetching the asset to report
+    if page.asset_exists(file).await == Ok(false) {
+        debug!("No asset {:?} in {}/{}", file, owner, repo);
+        return None;
+    }
+    if data.config.precompressed
+        && let Some(v) = try_precompressed_response(data, page, owner, repo, file, request).await
+    {
+        return Some(v);
+    }
+    let (mut response, code) =
+        get_asset_response(data, page, owner, repo, file, 200, request).await;
+    if data.config.precompressed {
+        // Other clients may get a precompressed variant, so shared caches mustn't mix them up
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+    (code != 404).then_some(response)
+}
+
+/// Gets the precompressed variant of an asset (e.g. `/app.js.zst`) in the encoding the client
+/// prefers as a response, if there is one.
+///
+/// HTML that would be modified (by `html_snippet` or `rewrite_path_links`) is always served
+/// uncompressed, as it can't be modified while compressed.
+async fn try_precompressed_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    page: &impl Page,
+    owner: &str,
+    repo: &str,
+    file: &Path,
+    request: AssetRequest<'_>,
+) -> Option<HttpResponse> {
+    // Denied assets are left for the asset's own response to refuse
+    if is_refused(data, file) {
+        return None;
+    }
+    let modified = data.config.html_snippet.is_some()
+        || (data.config.rewrite_path_links && request.link_base.is_some());
+
+    // The type is of the uncompressed asset, which isn't fetched for it; Assets whose type
+    // can't be told without their content are served uncompressed
+    let provided = page.asset_mime_type(file).await;
+    let mime = known_mime(file, provided.as_deref())?;
+    if modified && mime.essence_str() == "text/html" {
+        return None;
+    }
+
+    let mut original_exists = false;
+    for encoding in precompressed::accepted_encodings(request.accept_encoding?) {
+        let Some(variant) = precompressed::precompressed_path(file, encoding) else {
+            continue;
+        };
+        if page.asset_exists(&variant).await != Ok(true) {
+            continue;
+        }
+        // Variants are only served for assets that exist themselves
+        if !original_exists {
+            if page.asset_exists(file).await != Ok(true) {
+                return None;
+            }
+            original_exists = true;
+        }
+        debug!(
+            "Serving {} precompressed {:?} of {:?}",
+            encoding, variant, file
+        );
+        let (mut response, code) =
+            get_asset_response(data, page, owner, repo, &variant, 200, request).await;
+        if code != 200 && code != 304 {
+            continue;
+        }
+        let headers = response.headers_mut();
+        if code == 200 {
+            // The compressed content is still of the asset's type, not an archive
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_str(mime.as_ref()).unwrap(),
+            );
+        }
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        return Some(response);
+    }
+    None
+}
+
+/// The path of a variant of an asset (e.g. `/index.de.html` for `/index.html` and `de`).
+fn variant_path(file: &Path, variant: &str) -> Option<PathBuf> {
+    let stem = file.file_stem()?.to_str()?;
+    let name = match file.extension().and_then(|v| v.to_str()) {
+        Some(extension) => format!("{}.{}.{}", stem, variant, extension),
+        None => format!("{}.{}", stem, variant),
+    };
+    Some(file.with_file_name(name))
+}
+
+/// The paths a requested path may be served from, in the order they're tried: The asset
+/// itself, and the directory's index files if it may be one (or only those, if it must be).
+///
+/// Fallbacks are only fetched once they're known to exist, rather than to discover a 404.
+/// Whether a path is a directory is up to the page (whether it has an index there),
+/// never the local filesystem.
+pub(crate) fn served_paths<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    file: &Path,
+    overrides: &PageConfig,
+) -> Vec<PathBuf> {
+    let is_dir = is_dir_path(file);
+    let index_fallback = data.config.index_fallback;
+    let indexes = overrides.index_files().map(|v| file.join(v));
+    let mut paths = Vec::new();
+    if is_dir || (index_fallback && data.config.prefer_index) {
+        paths.extend(indexes);
+        if !is_dir {
+            paths.push(file.to_path_buf());
+        }
+    } else {
+        paths.push(file.to_path_buf());
+        if index_fallback {
+            paths.extend(indexes);
+        }
+    }
+    paths
+}
+
+/// Gets the first index file of a directory that exists as a response, if any.
+async fn try_index_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    page: &impl Page,
+    owner: &str,
+    repo: &str,
+    dir: &Path,
+    overrides: &PageConfig,
+    request: AssetRequest<'_>,
+) -> Option<HttpResponse> {
+    for index in overrides.index_files() {
+        let file = dir.join(index);
+        if let Some(v) = try_asset_response(data, page, owner, repo, &file, request).await {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Whether or not a path refers to the root of a page (e.g. `/`, `.` or an empty path).
+fn is_page_root(file: &Path) -> bool {
+    file.components()
+        .all(|c| matches!(c, Component::RootDir | Component::CurDir))
+}
+
+/// Whether or not a requested path can only be a directory (the page root, or ending with `/`).
+fn is_dir_path(file: &Path) -> bool {
+    is_page_root(file) || file.as_os_str().to_string_lossy().ends_with('/')
+}
+
+/// Whether or not a missing path should fall back to the page's index, as a single-page app.
+///
+/// A page's own config takes priority over the server's SPA settings.
+async fn is_spa_route<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    page: &impl Page,
+    overrides: &PageConfig,
+    file: &Path,
+) -> bool {
+    let spa = &data.config.spa;
+    if !spa.fallback_files && file.extension().is_some() {
+        return false;
+    }
+    if let Some(v) = overrides.spa {
+        return v;
+    }
+    spa.always || (spa.marker && page.asset_exists(Path::new("/.spa")).await == Ok(true))
 }
 
 /// Get a page directly as a response, without checking for fallbacks.
@@ -78,56 +415,229 @@ pub async fn get_page_response_raw<'a, PS: PageSource, UR: UrlResolver>(
         None => &data.config.upstream.default_branch,
     };
 
+    let page = match resolve_page(data, owner, repo, branch).await {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    get_asset_response(
+        data,
+        &page,
+        owner,
+        repo,
+        file,
+        ok_code,
+        AssetRequest::default(),
+    )
+    .await
+}
+
+/// Finds a page, or the response to send if it couldn't be found.
+///
+/// Also returns the status of the error response as a u16.
+pub async fn resolve_page<'a, PS: PageSource, UR: UrlResolver>(
+    data: &'a web::Data<RoutingState<'a, PS, UR>>,
+    owner: &str,
+    repo: &str,
+    branch: &str,
+) -> Result<impl Page + 'a, (HttpResponse, u16)> {
     /* ------------------------------- Page Query ------------------------------- */
 
-    let page = match data
+    if data.config.page_config.enabled && !is_branch_allowed(data, owner, repo, branch).await {
+        info!(
+            "Page {}/{} doesn't allow serving branch \"{}\"",
+            owner, repo, branch
+        );
+        return Err((
+            page_not_found_response(data, owner, repo, "Page not found".to_string()),
+            404,
+        ));
+    }
+
+    match data
         .provider
         .page_at(owner.to_string(), repo.to_string(), branch.to_string())
         .await
     {
-        Ok(v) => v,
+        Ok(v) => Ok(v),
         Err(e) => {
-            let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
+            if e == PageError::NotFound
+                && data.config.coming_soon
+                && data.provider.owner_exists(owner).await == Ok(true)
+            {
+                info!(
+                    "Owner {} exists, but has no page {}; Coming soon",
+                    owner, repo
+                );
+                let tp = data.jinja.get_template(TEMPLATE_COMING_SOON).unwrap();
+                return Err((
+                    HttpResponse::NotFound().content_type("text/html").body(
+                        tp.render(context! {
+                            server => data.config.template_server_context(),
+                            page => TemplatePageContext {
+                                owner: owner.to_string(),
+                                repo: repo.to_string()
+                            },
+                        })
+                        .unwrap(),
+                    ),
+                    404,
+                ));
+            }
+
+            if e == PageError::Gone {
+                info!("Page {}/{}:{} was removed", owner, repo, branch);
+                let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
+                return Err((
+                    HttpResponse::Gone().content_type("text/html").body(
+                        tp.render(context! {
+                            server => data.config.template_server_context(),
+                            page => TemplatePageContext {
+                                owner: owner.to_string(),
+                                repo: repo.to_string()
+                            },
+                            error => TemplateErrorContext {
+                                code: 410,
+                                message: "Page removed".to_string(),
+                                about: "The page you were looking for has been removed.".to_string()
+                            }
+                        })
+                        .unwrap(),
+                    ),
+                    410,
+                ));
+            }
+
+            if e == PageError::ProviderError {
+                error!(
+                    "Upstream failed while getting page {}/{}:{}",
+                    owner, repo, branch
+                );
+                return Err((unavailable_response(data, owner, repo), 503));
+            }
+
             error!(
                 "Failed to find page (owner: {}, name: {}, branch: {}): {}",
                 owner, repo, branch, e
             );
-            return (
-                HttpResponse::NotFound().content_type("text/html").body(
-                    tp.render(context! {
-                        server => data.config.template_server_context(),
-                        page => TemplatePageContext {
-                            owner: repo.to_string(),
-                            repo: owner.to_string()
-                        },
-                        error => TemplateErrorContext {
-                            code: 404,
-                            message: format!("Page not found - {:?}", e),
-                            about: "Failed to find the page you were looking for.".to_string()
-                        }
-                    })
-                    .unwrap(),
-                ),
+            Err((
+                page_not_found_response(data, owner, repo, format!("Page not found - {:?}", e)),
                 404,
-            );
+            ))
         }
-    };
+    }
+}
+
+/// Whether or not a page allows serving a branch, according to the config of its default branch.
+///
+/// The default branch is always allowed, as is every branch of pages that don't list any.
+/// The default branch's config is reused as last read, rather than looked up for every request.
+pub(crate) async fn is_branch_allowed<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    owner: &str,
+    repo: &str,
+    branch: &str,
+) -> bool {
+    let default_branch = &data.config.upstream.default_branch;
+    if branch == default_branch {
+        return true;
+    }
+    if let Some(config) = data.page_configs.last(owner, repo, default_branch) {
+        return config.allows_branch(branch);
+    }
+    match data
+        .provider
+        .page_at(owner.to_string(), repo.to_string(), default_branch.clone())
+        .await
+    {
+        Ok(page) => data
+            .page_configs
+            .get(&page, &data.config.page_config)
+            .await
+            .allows_branch(branch),
+        // Without a default branch, there's no config to restrict the branch
+        Err(_) => true,
+    }
+}
+
+/// Renders the error page shown when a page doesn't exist.
+fn page_not_found_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    owner: &str,
+    repo: &str,
+    message: String,
+) -> HttpResponse {
+    let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
+    HttpResponse::NotFound().content_type("text/html").body(
+        tp.render(context! {
+            server => data.config.template_server_context(),
+            page => TemplatePageContext {
+                owner: owner.to_string(),
+                repo: repo.to_string()
+            },
+            error => TemplateErrorContext {
+                code: 404,
+                message,
+                about: "Failed to find the page you were looking for.".to_string()
+            }
+        })
+        .unwrap(),
+    )
+}
 
+/// Gets an asset of an already resolved page as a response.
+///
+/// Also returns the status as a u16.
+pub async fn get_asset_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    page: &impl Page,
+    owner: &str,
+    repo: &str,
+    file: &Path,
+    ok_code: u16,
+    request: AssetRequest<'_>,
+) -> (HttpResponse, u16) {
     /* ------------------------------- Query Asset ------------------------------ */
 
     let path = file;
 
+    if is_denied(&data.config.deny_paths, path, true) {
+        info!(
+            "Refusing to serve denied asset {:?} from {}/{}",
+            file, owner, repo
+        );
+        return (not_found_response(data, page), 404);
+    }
+    if is_denied(&data.config.control_files, path, false) {
+        info!(
+            "Refusing to serve control file {:?} from {}/{}",
+            file, owner, repo
+        );
+        return (not_found_response(data, page), 404);
+    }
+
     let asset = match page.get_asset(path).await {
         Ok(v) => v,
+        Err(AssetError::ProviderError) => {
+            error!(
+                "Upstream failed while getting asset {:?} from {}/{}",
+                file, owner, repo
+            );
+            return (unavailable_response(data, owner, repo), 503);
+        }
+        Err(AssetError::TooLarge) => {
+            info!(
+                "Refusing to serve asset {:?} from {}/{}, as it's too large",
+                file, owner, repo
+            );
+            return (too_large_response(data, owner, repo), 413);
+        }
         Err(e) => {
             error!(
                 "Error getting asset {:?} from {}/{}: {:?}",
                 file, owner, repo, e
             );
-            return (
-                HttpResponse::NotFound().body(format!("Error getting asset: {:?}", e)),
-                404,
-            );
+            return (not_found_response(data, page), 404);
         }
     };
 
@@ -138,12 +648,365 @@ pub async fn get_page_response_raw<'a, PS: PageSource, UR: UrlResolver>(
         owner, repo, file
     );
 
-    // TODO: Move mime type determination to the Asset trait
-    let guesses = mime_guess::from_path(file.file_name().unwrap());
-    (
-        HttpResponse::build(StatusCode::from_u16(ok_code).unwrap())
-            .content_type(guesses.first_or(Mime::from_str("application/octet-stream").unwrap()))
-            .body(asset.into_bytes()),
-        ok_code,
+    let mime = resolve_mime(data, file, asset.mime_type(), asset.bytes());
+
+    let mut body = asset.into_bytes();
+    if data.config.rewrite_path_links
+        && let Some(base) = request.link_base
+        && mime.essence_str() == "text/html"
+    {
+        body = rewrite_root_links(body, base);
+    }
+    if let Some(snippet) = &data.config.html_snippet
+        && mime.essence_str() == "text/html"
+    {
+        body = inject_html_snippet(body, snippet);
+    }
+    let (mime, body, vary_accept) = negotiate_image(data, mime, body, request.accept).await;
+
+    // The validator is of what's actually served, so fallbacks (e.g. a directory's index.html)
+    // and transformations get their own, rather than sharing one with the requested path
+    let etag = format!("\"{}\"", content_hash(&body));
+    let validated = ok_code == 200 && !request.soft_404;
+    let not_modified = validated
+        && request
+            .if_none_match
+            .is_some_and(|v| etag_matches(v, &etag));
+
+    let mut response = match not_modified {
+        true => HttpResponse::NotModified(),
+        false => HttpResponse::build(StatusCode::from_u16(ok_code).unwrap()),
+    };
+    if validated {
+        response.insert_header((header::ETAG, etag));
+        if let Some(max_age) = request.max_age {
+            response.insert_header((
+                header::CACHE_CONTROL,
+                format!("public, max-age={}", max_age),
+            ));
+        }
+    }
+    if vary_accept {
+        response.insert_header((header::VARY, "Accept"));
+    }
+    if not_modified {
+        debug!("Asset {:?} wasn't modified", file);
+        return (response.finish(), 304);
+    }
+    response.content_type(mime);
+    if data.config.nosniff {
+        // The type is decided here, so browsers shouldn't sniff a different one from the content
+        response.insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"));
+    }
+
+    (response.body(body), ok_code)
+}
+
+/// Whether or not an `If-None-Match` header value matches an ETag.
+///
+/// Uses weak comparison, as `If-None-Match` calls for; `W/"x"` matches `"x"`.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|v| v == "*" || v.trim_start_matches("W/") == etag)
+}
+
+/// Serves PNG and JPEG images as WebP to clients that accept it, if enabled.
+///
+/// Returns the type and content to serve, and whether they depend on the `Accept` header.
+#[cfg(feature = "images")]
+async fn negotiate_image<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    mime: Mime,
+    body: Vec<u8>,
+    accept: Option<&str>,
+) -> (Mime, Vec<u8>, bool) {
+    let Some(converter) = &data.webp else {
+        return (mime, body, false);
+    };
+    if !images::is_convertible(&mime) {
+        return (mime, body, false);
+    }
+    if !accept.is_some_and(images::accepts_webp) {
+        return (mime, body, true);
+    }
+
+    // Converting is CPU heavy, so keep it off the async workers
+    let converter = converter.clone();
+    let body = std::sync::Arc::new(body);
+    let original = body.clone();
+    let converted = match web::block(move || converter.convert(&original)).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to run WebP conversion: {}", e);
+            None
+        }
+    };
+    match converted {
+        Some(v) => (Mime::from_str("image/webp").unwrap(), v.to_vec(), true),
+        None => (
+            mime,
+            std::sync::Arc::try_unwrap(body).unwrap_or_else(|v| v.to_vec()),
+            true,
+        ),
+    }
+}
+
+#[cfg(not(feature = "images"))]
+async fn negotiate_image<'a, PS: PageSource, UR: UrlResolver>(
+    _data: &web::Data<RoutingState<'a, PS, UR>>,
+    mime: Mime,
+    body: Vec<u8>,
+    _accept: Option<&str>,
+) -> (Mime, Vec<u8>, bool) {
+    (mime, body, false)
+}
+
+/// Resolves the type an asset is served as.
+///
+/// The provider knows best, so the file name is only guessed from if it doesn't say,
+/// and the content only sniffed if neither can tell.
+fn resolve_mime<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    file: &Path,
+    provided: Option<&str>,
+    body: &[u8],
+) -> Mime {
+    known_mime(file, provided)
+        .or_else(|| sniff_mime(data, file, body))
+        .unwrap_or(Mime::from_str("application/octet-stream").unwrap())
+}
+
+/// The type of an asset as the provider gives it, or as guessed from its file name,
+/// if either can tell without its content.
+fn known_mime(file: &Path, provided: Option<&str>) -> Option<Mime> {
+    provided
+        .and_then(|v| Mime::from_str(v).ok())
+        .or_else(|| mime_guess::from_path(file.file_name()?).first())
+}
+
+/// Detects the type of an extensionless asset from its content, if enabled.
+#[cfg(feature = "sniff")]
+fn sniff_mime<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    file: &Path,
+    body: &[u8],
+) -> Option<Mime> {
+    if !data.config.sniff || file.extension().is_some() {
+        return None;
+    }
+    let mime = Mime::from_str(infer::get(body)?.mime_type()).ok()?;
+    debug!("Sniffed {:?} as {}", file, mime);
+    Some(mime)
+}
+
+#[cfg(not(feature = "sniff"))]
+fn sniff_mime<'a, PS: PageSource, UR: UrlResolver>(
+    _data: &web::Data<RoutingState<'a, PS, UR>>,
+    _file: &Path,
+    _body: &[u8],
+) -> Option<Mime> {
+    None
+}
+
+/// Whether or not an asset is denied or a control file, so it's never served.
+pub(crate) fn is_refused<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    file: &Path,
+) -> bool {
+    is_denied(&data.config.deny_paths, file, true)
+        || is_denied(&data.config.control_files, file, false)
+}
+
+/// Whether or not an asset path matches any of the denied path patterns.
+///
+/// If `nested`, patterns also match within any directory (`.env` denies `/docs/.env`), unless
+/// they start with `/`, which anchors them to the page root. Paths that go up a level (`..`)
+/// are always denied.
+fn is_denied(patterns: &[String], file: &Path, nested: bool) -> bool {
+    let mut segments = Vec::new();
+    for component in file.components() {
+        match component {
+            Component::Normal(v) => segments.push(v.to_string_lossy()),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return true,
+        }
+    }
+    let path = segments.join("/");
+    patterns.iter().any(|v| match v.strip_prefix('/') {
+        Some(v) => glob_matches(v, &path),
+        None if nested => (0..segments.len()).any(|i| glob_matches(v, &segments[i..].join("/"))),
+        None => glob_matches(v, &path),
+    })
+}
+
+/// Attributes whose root-relative URLs are rewritten by [`rewrite_root_links`].
+const LINK_ATTRIBUTES: &[&[u8]] = &[b"href", b"src", b"action", b"poster"];
+
+/// Prefixes root-relative links (`href="/style.css"`) in an HTML document with a base path,
+/// so they resolve within a page served under that path rather than the server root.
+///
+/// Protocol-relative links (`//host/...`) are left alone.
+fn rewrite_root_links(body: Vec<u8>, base: &str) -> Vec<u8> {
+    let base = base.trim_end_matches('/').as_bytes();
+    let mut out = Vec::with_capacity(body.len());
+    let mut last = 0;
+    for i in 0..body.len().saturating_sub(2) {
+        // Looking for `attribute="/x` (or single quotes), where x isn't another slash
+        if body[i] != b'='
+            || !matches!(body[i + 1], b'"' | b'\'')
+            || body[i + 2] != b'/'
+            || body.get(i + 3) == Some(&b'/')
+        {
+            continue;
+        }
+        let before = &body[..i];
+        let is_link = LINK_ATTRIBUTES.iter().any(|attribute| {
+            before.len() > attribute.len()
+                && before[before.len() - attribute.len()..].eq_ignore_ascii_case(attribute)
+                && before[before.len() - attribute.len() - 1].is_ascii_whitespace()
+        });
+        if is_link {
+            out.extend_from_slice(&body[last..i + 2]);
+            out.extend_from_slice(base);
+            last = i + 2;
+        }
+    }
+    out.extend_from_slice(&body[last..]);
+    out
+}
+
+/// Inserts a snippet of HTML before the last `</body>` tag of a document.
+///
+/// If the document has no closing body tag, the snippet is appended to the end instead.
+fn inject_html_snippet(mut body: Vec<u8>, snippet: &str) -> Vec<u8> {
+    const TAG: &[u8] = b"</body>";
+
+    let position = body
+        .windows(TAG.len())
+        .rposition(|w| w.eq_ignore_ascii_case(TAG))
+        .unwrap_or(body.len());
+    body.splice(position..position, snippet.bytes());
+    body
+}
+
+/// Renders the error page shown when a page has no such asset (nor a custom 404 page).
+fn not_found_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    page: &impl Page,
+) -> HttpResponse {
+    let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
+    HttpResponse::NotFound().content_type("text/html").body(
+        tp.render(context! {
+            server => data.config.template_server_context(),
+            page => TemplatePageContext {
+                owner: page.owner().to_string(),
+                repo: page.name().to_string()
+            },
+            error => TemplateErrorContext {
+                code: 404,
+                message: "Not found".to_string(),
+                about: "This site doesn't have the page you were looking for.".to_string()
+            }
+        })
+        .unwrap(),
     )
 }
+
+/// Redirects to the target of a matched rule, within the path the page is served under (if any).
+fn redirect_response(rule: &RedirectMatch, link_base: Option<&str>) -> HttpResponse {
+    let location = match link_base {
+        Some(base) if rule.to.starts_with('/') => format!("{}{}", base, rule.to),
+        _ => rule.to.clone(),
+    };
+    HttpResponse::build(StatusCode::from_u16(rule.status).unwrap())
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Responds that rewrites didn't lead anywhere (`508 Loop Detected`).
+fn loop_detected_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    owner: &str,
+    repo: &str,
+) -> HttpResponse {
+    let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
+    HttpResponse::build(StatusCode::LOOP_DETECTED)
+        .content_type("text/html")
+        .body(
+            tp.render(context! {
+                server => data.config.template_server_context(),
+                page => TemplatePageContext {
+                    owner: owner.to_string(),
+                    repo: repo.to_string()
+                },
+                error => TemplateErrorContext {
+                    code: 508,
+                    message: "Loop detected".to_string(),
+                    about: "The page's redirect rules rewrite this path too many times.".to_string()
+                }
+            })
+            .unwrap(),
+        )
+}
+
+/// Renders the error page shown when an asset is larger than what may be served.
+fn too_large_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    owner: &str,
+    repo: &str,
+) -> HttpResponse {
+    let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
+    HttpResponse::PayloadTooLarge()
+        .content_type("text/html")
+        .body(
+            tp.render(context! {
+                server => data.config.template_server_context(),
+                page => TemplatePageContext {
+                    owner: owner.to_string(),
+                    repo: repo.to_string()
+                },
+                error => TemplateErrorContext {
+                    code: 413,
+                    message: "Payload too large".to_string(),
+                    about: "The file you were looking for is too large to be served.".to_string()
+                }
+            })
+            .unwrap(),
+        )
+}
+
+/// Renders the error page shown when upstream couldn't be reached.
+///
+/// If the provider knows when upstream can be reached again (e.g. once a rate limit is over),
+/// clients are told to retry after that with `Retry-After`.
+fn unavailable_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    owner: &str,
+    repo: &str,
+) -> HttpResponse {
+    let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
+    let mut response = HttpResponse::ServiceUnavailable();
+    if let Some(v) = data.provider.retry_after() {
+        response.insert_header((header::RETRY_AFTER, v.as_millis().div_ceil(1000) as u64));
+    }
+    response
+        .content_type("text/html")
+        .body(
+            tp.render(context! {
+                server => data.config.template_server_context(),
+                page => TemplatePageContext {
+                    owner: owner.to_string(),
+                    repo: repo.to_string()
+                },
+                error => TemplateErrorContext {
+                    code: 503,
+                    message: "Service unavailable".to_string(),
+                    about: "The page couldn't be retrieved right now. Please try again later.".to_string()
+                }
+            })
+            .unwrap(),
+        )
+}
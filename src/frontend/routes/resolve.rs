@@ -0,0 +1,176 @@
+/// Admin endpoint explaining how a URL resolves, without serving anything from it.
+use std::{path::Path, sync::Arc};
+
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    http::{
+        StatusCode,
+        header::{CacheControl, CacheDirective},
+    },
+    web,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    AssetSource, PageError, PageSource, decode_path,
+    frontend::routes::{
+        SharedRoutingState,
+        errors::{json_error_response, unauthorized_response},
+        pages::{is_branch_allowed, is_refused, served_paths},
+        server::find_domain_page,
+        stats::is_authorized,
+    },
+    resolver::{UrlResolution, UrlResolver},
+};
+
+/// Path of the resolution endpoint.
+pub const RESOLVE_PATH: &str = "/.pageshelf/resolve";
+
+#[derive(Deserialize, Debug)]
+pub struct ResolveQuery {
+    /// The URL to explain, as a client would request it.
+    pub url: String,
+}
+
+/// How a URL resolves, and whether what it resolves to exists.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Resolution {
+    /// What the resolver made of the URL: `page`, `builtin`, `external`, `malformed` or `misdirected`.
+    pub resolution: String,
+    /// Why the URL was malformed or misdirected, if it was.
+    pub detail: Option<String>,
+    /// The configured page domain the URL's host is (under), if any.
+    pub page_domain: Option<String>,
+    /// How an external domain led to a page: `domain` (claimed by it), `domain_branch`
+    /// (a branch subdomain of a claimed domain) or `domain_fallback` (the operator's fallback).
+    pub via: Option<String>,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    pub branch: Option<String>,
+    /// Path of the asset within the page.
+    pub asset: Option<String>,
+    /// Whether or not the page exists; None if there's no page, or upstream failed.
+    pub page_exists: Option<bool>,
+    /// Whether or not the asset would be served (for directories, one of their index files);
+    /// None if there's no page, or upstream failed.
+    pub asset_exists: Option<bool>,
+}
+
+pub async fn get_resolve<'a, PS: PageSource, UR: UrlResolver>(
+    state: web::Data<SharedRoutingState<'a, PS, UR>>,
+    req: HttpRequest,
+    query: web::Query<ResolveQuery>,
+) -> impl Responder {
+    let data = web::Data::from(state.load_full());
+    match is_authorized(&req, &data.config) {
+        None => return json_error_response(StatusCode::NOT_FOUND, "Not found"),
+        Some(false) => {
+            info!("Refusing unauthorized request to explain a resolution");
+            return unauthorized_response();
+        }
+        Some(true) => {}
+    }
+
+    let url = match Url::parse(&query.url) {
+        Ok(v) => v,
+        Err(e) => {
+            return json_error_response(StatusCode::BAD_REQUEST, format!("Invalid URL: {}", e));
+        }
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+    let mut resolution = Resolution {
+        page_domain: data
+            .config
+            .url_resolver()
+            .page_domain_of(&host)
+            .map(str::to_string),
+        ..Resolution::default()
+    };
+
+    let asset = decode_path(url.path());
+    let page = match data.resolver.resolve(url.clone()) {
+        UrlResolution::Page(loc) => {
+            resolution.resolution = "page".to_string();
+            Some((loc.page, loc.asset))
+        }
+        UrlResolution::BuiltIn => {
+            resolution.resolution = "builtin".to_string();
+            None
+        }
+        UrlResolution::Malformed(v) => {
+            resolution.resolution = "malformed".to_string();
+            resolution.detail = Some(v);
+            None
+        }
+        UrlResolution::Misdirected(v) => {
+            resolution.resolution = "misdirected".to_string();
+            resolution.detail = Some(v);
+            None
+        }
+        // Looked up the same way as when serving the domain
+        UrlResolution::External(_) => match find_domain_page(&data, &host).await {
+            Some((via, page)) => {
+                resolution.resolution = "external".to_string();
+                resolution.via = Some(via.as_str().to_string());
+                Some((page, asset))
+            }
+            None if data.config.strict_host => {
+                resolution.resolution = "misdirected".to_string();
+                resolution.detail = Some(host);
+                None
+            }
+            None => {
+                resolution.resolution = "external".to_string();
+                None
+            }
+        },
+    };
+
+    if let Some((loc, asset)) = page {
+        // Served the same way as the asset would be, from the branch check to the index files
+        let allowed = !data.config.page_config.enabled
+            || is_branch_allowed(&data, &loc.owner, &loc.name, &loc.branch).await;
+        let found = match allowed {
+            true => {
+                data.provider
+                    .page_at(loc.owner.clone(), loc.name.clone(), loc.branch.clone())
+                    .await
+            }
+            false => Err(PageError::NotFound),
+        };
+        match found {
+            Ok(page) => {
+                resolution.page_exists = Some(true);
+                let overrides = match data.config.page_config.enabled {
+                    true => data.page_configs.get(&page, &data.config.page_config).await,
+                    false => Arc::default(),
+                };
+                resolution.asset_exists = Some(false);
+                for path in served_paths(&data, Path::new(&asset), &overrides) {
+                    if is_refused(&data, &path) {
+                        continue;
+                    }
+                    match page.asset_exists(&path).await {
+                        Ok(false) => {}
+                        v => {
+                            resolution.asset_exists = v.ok();
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(PageError::ProviderError) => {}
+            Err(_) => resolution.page_exists = Some(false),
+        }
+        resolution.owner = Some(loc.owner);
+        resolution.repo = Some(loc.name);
+        resolution.branch = Some(loc.branch);
+        resolution.asset = Some(asset);
+    }
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(resolution)
+}
@@ -1,31 +1,166 @@
 /// Primary route for accessing Pages (and built-in pages).
-use std::path::Path;
+use std::{path::Path, time::Duration};
 
 use actix_web::{
-    HttpRequest, HttpResponse, Responder, get,
-    http::header::{CacheControl, CacheDirective, HeaderValue},
-    web,
+    HttpRequest, HttpResponse, Responder,
+    http::{
+        StatusCode,
+        header::{self, CacheControl, CacheDirective, HeaderValue},
+    },
+    route, web,
 };
-use log::{debug, info};
+use log::{debug, info, warn};
 use minijinja::context;
+use url::Url;
 
 use crate::{
-    Page, PageSource,
+    Page, PageAssetLocation, PageLocation, PageSource, asset_path,
+    conf::{ServerConfig, ServerConfigMissingHost},
+    decode_path,
     frontend::{
-        routes::{RoutingState, pages::get_page_response},
+        routes::{
+            RoutingState, SharedRoutingState,
+            pages::{AssetRequest, get_page_response},
+            token_matches,
+        },
         templates::{TEMPLATE_ERROR, TEMPLATE_INDEX, TemplateErrorContext, TemplatePageContext},
     },
     resolver::{UrlResolution, UrlResolver},
 };
 
-fn resolve_http_request<UR: UrlResolver>(resolver: &UR, req: &HttpRequest) -> UrlResolution {
-    resolver.resolve(req.full_url())
+/// Header that picks the branch of a page to serve, for previews.
+pub const BRANCH_HEADER: &str = "x-pageshelf-branch";
+
+/// Header carrying the token that allows requests to pick a branch with [`BRANCH_HEADER`].
+pub const PREVIEW_TOKEN_HEADER: &str = "x-pageshelf-token";
+
+fn resolve_http_request<UR: UrlResolver>(
+    resolver: &UR,
+    req: &HttpRequest,
+    config: &ServerConfig,
+) -> UrlResolution {
+    match request_url(req, config) {
+        Ok(url) => resolver.resolve(url),
+        Err(e) => {
+            info!("Failed to determine the requested URL: {}", e);
+            UrlResolution::Malformed(e.to_string())
+        }
+    }
+}
+
+/// Whether or not the request came directly from one of the configured trusted proxies.
+fn is_trusted_proxy(req: &HttpRequest, config: &ServerConfig) -> bool {
+    req.peer_addr()
+        .is_some_and(|addr| config.trusted_proxies.contains(&addr.ip()))
+}
+
+/// Gets the first value of a (possibly comma-separated) forwarding header.
+fn forwarded_header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .find(|v| !v.is_empty())
+}
+
+/// Gets the host the request was made to, as seen by the client.
+///
+/// `X-Forwarded-Host` is only considered if the request came from a trusted proxy;
+/// Otherwise, anyone could claim to be requesting any host.
+fn request_host<'a>(req: &'a HttpRequest, config: &ServerConfig) -> Option<&'a str> {
+    let forwarded = match is_trusted_proxy(req, config) {
+        true => forwarded_header(req, "X-Forwarded-Host"),
+        false => None,
+    };
+    forwarded.or_else(|| host_header(req))
+}
+
+/// Gets the host the request was made to, according to the request itself.
+fn host_header(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| req.uri().authority().map(|v| v.as_str()))
+}
+
+/// Composes the URL a request was made to from its scheme, `Host` header, path, and query.
+///
+/// # Arguments
+///
+/// - `req` (`&HttpRequest`) - The request to get the URL of.
+/// - `scheme_override` (`Option<&str>`) - The scheme to use instead of the connection's own.
+///
+/// # Returns
+///
+/// - `Result<Url, url::ParseError>` - The URL, if the request's host could make one.
+pub fn request_to_url(
+    req: &HttpRequest,
+    scheme_override: Option<&str>,
+) -> Result<Url, url::ParseError> {
+    let host = host_header(req).unwrap_or(req.app_config().host());
+    compose_url(req, scheme_override, host)
+}
+
+fn compose_url(
+    req: &HttpRequest,
+    scheme_override: Option<&str>,
+    host: &str,
+) -> Result<Url, url::ParseError> {
+    let scheme = scheme_override.unwrap_or(match req.app_config().secure() {
+        true => "https",
+        false => "http",
+    });
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|v| v.as_str())
+        .unwrap_or("/");
+
+    Url::parse(&format!("{scheme}://{host}{path_and_query}"))
+}
+
+/// Reconstructs the URL the request was made to, as seen by the client.
+///
+/// This intentionally doesn't use Actix's connection info, as it trusts forwarding
+/// headers from anyone.
+fn request_url(req: &HttpRequest, config: &ServerConfig) -> Result<Url, url::ParseError> {
+    if !is_trusted_proxy(req, config) {
+        return request_to_url(req, None);
+    }
+
+    let scheme =
+        forwarded_header(req, "X-Forwarded-Proto").filter(|v| matches!(*v, "http" | "https"));
+    match forwarded_header(req, "X-Forwarded-Host") {
+        Some(host) => compose_url(req, scheme, host),
+        None => request_to_url(req, scheme),
+    }
+}
+
+/// The path a page is served under, given the request path and the page asset it resolved to.
+///
+/// For example, `/owner/repo/style.css` requesting `/style.css` is served under `/owner/repo`.
+/// Returns None if the page is served at the root (e.g. subdomains).
+fn page_base(path: &str, loc: &PageAssetLocation) -> Option<String> {
+    let path = path.trim_end_matches('/');
+    let asset = loc.asset.trim_end_matches('/');
+    match path.strip_suffix(asset) {
+        Some("") | None => None,
+        // `/owner` serves the default repository, but `/owner/style.css` would be another one
+        Some(v) if v.trim_start_matches('/') == loc.page.owner => {
+            Some(format!("{}/{}", v, loc.page.name))
+        }
+        Some(v) => Some(v.to_string()),
+    }
 }
 
 pub async fn get_index<'a, PS: PageSource, UR: UrlResolver>(
-    data: web::Data<RoutingState<'a, PS, UR>>,
+    state: web::Data<SharedRoutingState<'a, PS, UR>>,
     req: HttpRequest,
 ) -> impl Responder {
+    // Stick with one state for the whole request, even if it's swapped out meanwhile
+    let data = web::Data::from(state.load_full());
     debug!(
         "Requested by {}",
         req.headers()
@@ -34,7 +169,189 @@ pub async fn get_index<'a, PS: PageSource, UR: UrlResolver>(
             .to_str()
             .unwrap_or("Unknown Origin")
     );
-    let resolution = resolve_http_request(&data.resolver, &req);
+    let request = AssetRequest {
+        link_base: None,
+        accept: req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok()),
+        accept_encoding: req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok()),
+        if_none_match: req
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok()),
+        branch: preview_branch(&req, &data.config),
+        country: request_country(&req, &data.config),
+        max_age: None,
+        variant: None,
+        soft_404: false,
+    };
+
+    // Every upstream call a request makes counts against its budget, fallbacks included
+    let routed = route_request(&data, &req, request);
+    let mut response = match data.config.upstream.request_budget {
+        Some(budget) => {
+            let budget = Duration::from_millis(budget);
+            match tokio::time::timeout(budget, routed).await {
+                Ok(v) => v,
+                Err(_) => {
+                    warn!(
+                        "Giving up on {} after exceeding its budget of {:?}",
+                        req.uri(),
+                        budget
+                    );
+                    timed_out_response(&data)
+                }
+            }
+        }
+        None => routed.await,
+    };
+    if data.config.preview_token.is_some() {
+        // Responses differ by the header then, so shared caches mustn't mix them up
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static(BRANCH_HEADER));
+    }
+    if cfg!(feature = "geo")
+        && let Some(geo_header) = &data.config.geo_header
+        && let Ok(value) = HeaderValue::from_str(geo_header)
+    {
+        // Pages may serve variants by country, so shared caches mustn't mix them up
+        response.headers_mut().append(header::VARY, value);
+    }
+    if request.branch.is_some() {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-store"),
+        );
+    }
+    response
+}
+
+/// Renders the error page shown when a request ran out of time waiting for upstream.
+fn timed_out_response<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+) -> HttpResponse {
+    HttpResponse::GatewayTimeout()
+        .content_type("text/html")
+        .body(
+            data.jinja
+                .get_template(TEMPLATE_ERROR)
+                .unwrap()
+                .render(context! {
+                    server => data.config.template_server_context(),
+                    page => TemplatePageContext {
+                        owner: "".to_string(),
+                        repo: "".to_string()
+                    },
+                    error => TemplateErrorContext {
+                        code: 504,
+                        message: "Gateway timeout".to_string(),
+                        about: "The server took too long to get what you were looking for."
+                            .to_string()
+                    }
+                })
+                .unwrap(),
+        )
+}
+
+/// The branch a request asks to be served instead of the one its URL resolves to.
+///
+/// The header is only honored along with the configured preview token, as it bypasses the
+/// URL-based isolation of branches; Otherwise, it's ignored.
+fn preview_branch<'a>(req: &'a HttpRequest, config: &ServerConfig) -> Option<&'a str> {
+    let branch = req.headers().get(BRANCH_HEADER)?.to_str().ok()?.trim();
+    let authorized = config.preview_token.as_deref().is_some_and(|token| {
+        req.headers()
+            .get(PREVIEW_TOKEN_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| token_matches(v, token))
+    });
+    if !authorized {
+        info!("Ignoring unauthorized {} header", BRANCH_HEADER);
+        return None;
+    }
+    Some(branch).filter(|v| !v.is_empty())
+}
+
+/// The client's country code, as given by the configured header (e.g. `CF-IPCountry` from a CDN).
+///
+/// Clients could send any country themselves, but that only picks which (public) variant
+/// of a page they're served.
+#[cfg(feature = "geo")]
+fn request_country<'a>(req: &'a HttpRequest, config: &ServerConfig) -> Option<&'a str> {
+    let header = config.geo_header.as_deref()?;
+    let country = req.headers().get(header)?.to_str().ok()?.trim();
+    Some(country).filter(|v| !v.is_empty())
+}
+
+#[cfg(not(feature = "geo"))]
+fn request_country<'a>(_req: &'a HttpRequest, _config: &ServerConfig) -> Option<&'a str> {
+    None
+}
+
+/// Routes a request to the page (or built-in page) it's for.
+async fn route_request<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    req: &HttpRequest,
+    request: AssetRequest<'_>,
+) -> HttpResponse {
+    let resolution = match request_host(req, &data.config).is_some() {
+        true => resolve_http_request(&data.resolver, req, &data.config),
+        false => match data.config.missing_host {
+            ServerConfigMissingHost::Root => {
+                debug!("No host specified, resolving as root");
+                match request_url(req, &data.config) {
+                    Ok(mut url) => {
+                        if let Some(home) = &data.config.url {
+                            let _ = url.set_host(home.host_str());
+                            let _ = url.set_port(home.port());
+                        }
+                        data.resolver.resolve(url)
+                    }
+                    Err(e) => UrlResolution::Malformed(e.to_string()),
+                }
+            }
+            ServerConfigMissingHost::DefaultUser => {
+                debug!("No host specified, serving the default user's page");
+                match request_url(req, &data.config).map(|url| asset_path(&url)) {
+                    Ok(Some(s)) => {
+                        return get_page_response(data, None, None, None, Path::new(&s), request)
+                            .await;
+                    }
+                    Ok(None) => {
+                        warn!("Refusing to serve unsafe path {}", req.uri().path());
+                        UrlResolution::Malformed(req.uri().path().to_string())
+                    }
+                    Err(e) => UrlResolution::Malformed(e.to_string()),
+                }
+            }
+            ServerConfigMissingHost::Reject => {
+                info!("Rejecting request without a host");
+                return HttpResponse::BadRequest().content_type("text/html").body(
+                    data.jinja
+                        .get_template(TEMPLATE_ERROR)
+                        .unwrap()
+                        .render(context! {
+                            server => data.config.template_server_context(),
+                            page => TemplatePageContext {
+                                owner: "".to_string(),
+                                repo: "".to_string()
+                            },
+                            error => TemplateErrorContext {
+                                code: 400,
+                                message: "Bad request".to_string(),
+                                about: "The request didn't specify a host.".to_string()
+                            }
+                        })
+                        .unwrap(),
+                );
+            }
+        },
+    };
     match resolution {
         UrlResolution::BuiltIn => {
             info!("Serving Built-In page");
@@ -50,36 +367,48 @@ pub async fn get_index<'a, PS: PageSource, UR: UrlResolver>(
         }
         UrlResolution::Page(loc) => {
             info!("Page: {:?}", loc);
+            let path = decode_path(req.uri().path());
             return get_page_response(
-                &data,
+                data,
                 Some(&loc.page.owner),
                 Some(&loc.page.name),
                 Some(&loc.page.branch),
                 Path::new(&loc.asset),
+                AssetRequest {
+                    link_base: page_base(&path, &loc).as_deref(),
+                    ..request
+                },
             )
             .await;
         }
         UrlResolution::External(url) => {
             info!("External URL: {}", url);
-            let domains = [url.host_str().unwrap()];
-            match data.provider.find_by_domains(&domains).await {
-                Ok(page) => {
-                    let s = req.uri().to_string();
-                    let file = Path::new(&s);
-                    return get_page_response(
-                        &data,
-                        Some(page.owner()),
-                        Some(page.name()),
-                        Some(page.branch()),
-                        file,
-                    )
-                    .await;
+            let host = url.host_str().unwrap();
+            match find_domain_page(data, host).await {
+                Some((_, page)) => {
+                    // Unsafe paths are refused by the resolver already, but never served regardless
+                    if let Some(s) = asset_path(&url) {
+                        return get_page_response(
+                            data,
+                            Some(&page.owner),
+                            Some(&page.name),
+                            Some(&page.branch),
+                            Path::new(&s),
+                            request,
+                        )
+                        .await;
+                    }
                 }
-                Err(e) => {
-                    info!("Failed to find repo by domain \"{}\": {}", url, e);
+                // Only domains that pages claim are served in strict mode
+                None if data.config.strict_host => {
+                    return misdirected_response(&data.config, &data.jinja, host);
                 }
+                None => {}
             }
         }
+        UrlResolution::Misdirected(host) => {
+            return misdirected_response(&data.config, &data.jinja, &host);
+        }
         _ => {}
     };
     let tp = data.jinja.get_template(TEMPLATE_ERROR).unwrap();
@@ -100,7 +429,101 @@ pub async fn get_index<'a, PS: PageSource, UR: UrlResolver>(
     )
 }
 
-#[get("/pages_favicon.webp")]
+/// How an external domain led to the page it's served from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainVia {
+    /// A page claims the domain.
+    Domain,
+    /// The domain is a branch subdomain (`<branch>.<domain>`) of a domain a page claims on its
+    /// default branch.
+    DomainBranch,
+    /// No page claims the domain, so it's served the operator's fallback page.
+    DomainFallback,
+}
+
+impl DomainVia {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Domain => "domain",
+            Self::DomainBranch => "domain_branch",
+            Self::DomainFallback => "domain_fallback",
+        }
+    }
+}
+
+/// Finds the page an external domain is served from, and how, if any.
+pub(crate) async fn find_domain_page<'a, PS: PageSource, UR: UrlResolver>(
+    data: &web::Data<RoutingState<'a, PS, UR>>,
+    host: &str,
+) -> Option<(DomainVia, PageLocation)> {
+    match data.provider.find_by_domains(vec![host.to_string()]).await {
+        Ok(page) => return Some((DomainVia::Domain, page.location())),
+        Err(e) => info!("Failed to find repo by domain \"{}\": {}", host, e),
+    }
+
+    // `<branch>.<domain>`, where the page declares the domain on its default branch
+    if data.config.domain_branches
+        && let Some((branch, domain)) = host.split_once('.')
+        && let Ok(page) = data
+            .provider
+            .find_by_domains(vec![domain.to_string()])
+            .await
+        && page.branch() == data.config.upstream.default_branch
+    {
+        info!("Serving branch {} of the page at domain {}", branch, domain);
+        let page = PageLocation {
+            branch: branch.to_string(),
+            ..page.location()
+        };
+        return Some((DomainVia::DomainBranch, page));
+    }
+
+    // Domains that no page claims get the operator's fallback page, if there is one
+    let page = data.config.domain_fallback_page()?;
+    info!(
+        "No page claims domain {}; Serving fallback page {}",
+        host, page
+    );
+    Some((DomainVia::DomainFallback, page))
+}
+
+/// Responds that the host isn't served here (`421 Misdirected Request`).
+fn misdirected_response(
+    config: &ServerConfig,
+    jinja: &minijinja::Environment,
+    host: &str,
+) -> HttpResponse {
+    info!("Rejecting request for unknown host {}", host);
+    HttpResponse::build(StatusCode::MISDIRECTED_REQUEST)
+        .content_type("text/html")
+        .body(
+            jinja
+                .get_template(TEMPLATE_ERROR)
+                .unwrap()
+                .render(context! {
+                    server => config.template_server_context(),
+                    page => TemplatePageContext {
+                        owner: "".to_string(),
+                        repo: "".to_string()
+                    },
+                    error => TemplateErrorContext {
+                        code: 421,
+                        message: "Misdirected request".to_string(),
+                        about: "This server isn't configured to serve this host.".to_string()
+                    }
+                })
+                .unwrap(),
+        )
+}
+
+/// Responds to methods that pages don't support, pointing to the ones they do.
+pub async fn method_not_allowed() -> impl Responder {
+    HttpResponse::MethodNotAllowed()
+        .insert_header((header::ALLOW, "GET, HEAD"))
+        .finish()
+}
+
+#[route("/pages_favicon.webp", method = "GET", method = "HEAD")]
 pub async fn get_favicon_webp() -> impl Responder {
     HttpResponse::Ok()
         .insert_header(CacheControl(vec![
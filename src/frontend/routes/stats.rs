@@ -0,0 +1,109 @@
+/// Admin endpoint reporting aggregate statistics about the served pages.
+use std::{collections::HashSet, sync::LazyLock, time::Instant};
+
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    http::{
+        StatusCode,
+        header::{self, CacheControl, CacheDirective},
+    },
+    web,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AssetSource, Page, PageSource, ScanStats,
+    conf::ServerConfig,
+    frontend::routes::{
+        SharedRoutingState,
+        errors::{json_error_response, unauthorized_response},
+        token_matches,
+    },
+    provider::layers::cache::asset_hit_ratio,
+    resolver::UrlResolver,
+};
+
+/// Path of the statistics endpoint.
+pub const STATS_PATH: &str = "/.pageshelf/stats";
+
+/// When the server started serving, for the uptime.
+pub(crate) static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Stats {
+    /// Pages (every branch counts) currently served.
+    pub pages: usize,
+    /// Owners that have at least one page.
+    pub owners: usize,
+    /// Bytes taken by the assets of all pages whose size is known.
+    pub total_bytes: u64,
+    /// Pages whose size isn't known, so they're missing from `total_bytes`.
+    pub pages_unsized: usize,
+    /// Share of asset lookups served from cache, if any went through one.
+    pub cache_hit_ratio: Option<f64>,
+    /// Seconds since the server started.
+    pub uptime: u64,
+    /// Statistics about the latest completed scan of upstream, if the provider scans it.
+    pub last_scan: Option<ScanStats>,
+}
+
+/// Whether or not the request carries the configured admin token (for any admin endpoint).
+/// Returns None if no token is configured, as the admin endpoints are disabled then.
+pub(crate) fn is_authorized(req: &HttpRequest, config: &ServerConfig) -> Option<bool> {
+    let token = config.admin_token.as_deref()?;
+    Some(
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|v| token_matches(v, token)),
+    )
+}
+
+pub async fn get_stats<'a, PS: PageSource, UR: UrlResolver>(
+    state: web::Data<SharedRoutingState<'a, PS, UR>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let data = state.load_full();
+    match is_authorized(&req, &data.config) {
+        None => return json_error_response(StatusCode::NOT_FOUND, "Not found"),
+        Some(false) => {
+            info!("Refusing unauthorized request for statistics");
+            return unauthorized_response();
+        }
+        Some(true) => {}
+    }
+
+    let pages = match data.provider.pages().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to list pages for statistics: {}", e);
+            return json_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list pages");
+        }
+    };
+
+    let mut stats = Stats {
+        pages: 0,
+        owners: 0,
+        total_bytes: 0,
+        pages_unsized: 0,
+        cache_hit_ratio: asset_hit_ratio(),
+        uptime: STARTED.elapsed().as_secs(),
+        last_scan: data.provider.scan_stats().await,
+    };
+    let mut owners = HashSet::new();
+    for page in pages {
+        stats.pages += 1;
+        owners.insert(page.owner().to_string());
+        match page.total_bytes() {
+            Some(v) => stats.total_bytes += v as u64,
+            None => stats.pages_unsized += 1,
+        }
+    }
+    stats.owners = owners.len();
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(stats)
+}
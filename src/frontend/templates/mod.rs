@@ -1,5 +1,5 @@
 /// Utilities for handling [MiniJinja](https://docs.rs/minijinja/latest/minijinja/) templates.
-use log::{error, info};
+use log::{error, info, warn};
 use minijinja::Environment;
 use serde::{Deserialize, Serialize};
 
@@ -11,6 +11,8 @@ use serde::{Deserialize, Serialize};
 pub const TEMPLATE_ERROR: &str = "error.html";
 /// Identifier for the Index template.
 pub const TEMPLATE_INDEX: &str = "index.html";
+/// Identifier for the Coming Soon template (owners that exist, but have no page).
+pub const TEMPLATE_COMING_SOON: &str = "coming_soon.html";
 
 /* -------------------------------------------------------------------------- */
 /*                             Rendering contexts                             */
@@ -21,6 +23,8 @@ pub struct TemplateServerContext {
     pub name: String,
     pub about: String,
     pub url: Option<String>,
+    /// The scheme (`http`/`https`) absolute links should use.
+    pub scheme: String,
     pub icon_url: Option<String>,
     pub default_branch: String,
     pub version: &'static str,
@@ -56,19 +60,100 @@ fn checked_add_template<'a>(env: &mut Environment<'a>, entry: &'a str, data: &'a
     }
 }
 
+/// Templates that ship with the server, by identifier.
+#[cfg(feature = "builtin-templates")]
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    // Styles
+    ("styles.css", include_str!("styles.css")),
+    // Pages
+    (TEMPLATE_ERROR, include_str!("error.jinja")),
+    (TEMPLATE_INDEX, include_str!("index.jinja")),
+    (TEMPLATE_COMING_SOON, include_str!("coming_soon.jinja")),
+    ("footer.html", include_str!("footer.jinja")),
+    ("header.html", include_str!("header.jinja")),
+];
+
+/// Bare-bones stand-ins for the built-in templates, for builds without them.
+/// Custom templates are expected; These only keep routes from failing without them.
+#[cfg(not(feature = "builtin-templates"))]
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("styles.css", ""),
+    (
+        TEMPLATE_ERROR,
+        "<!DOCTYPE html><title>{{ error.code }}</title><h1>{{ error.code }}</h1><p>{{ error.message }}</p>{% if page %}<p>Site: {{ page.owner }}/{{ page.repo }}</p>{% endif %}",
+    ),
+    (
+        TEMPLATE_INDEX,
+        "<!DOCTYPE html><title>{{ server.name }}</title><h1>{{ server.name }}</h1><p>{{ server.about }}</p>",
+    ),
+    (
+        TEMPLATE_COMING_SOON,
+        "<!DOCTYPE html><title>{{ page.owner }}</title><h1>{{ page.owner }}</h1><p>Coming soon!</p>",
+    ),
+    ("footer.html", ""),
+    ("header.html", ""),
+];
+
 /// Generates a MiniJinja environment from built-in resources.
 /// This will include various pages off the bat.
+///
+/// Without the `builtin-templates` feature, these are only minimal stand-ins.
 pub fn templates_from_builtin<'a>() -> Environment<'a> {
     let mut env = Environment::new();
 
-    // Styles
-    checked_add_template(&mut env, "styles.css", include_str!("styles.css"));
-
-    // Pages
-    checked_add_template(&mut env, TEMPLATE_ERROR, include_str!("error.jinja"));
-    checked_add_template(&mut env, TEMPLATE_INDEX, include_str!("index.jinja"));
-    checked_add_template(&mut env, "footer.html", include_str!("footer.jinja"));
-    checked_add_template(&mut env, "header.html", include_str!("header.jinja"));
+    for (entry, data) in BUILTIN_TEMPLATES {
+        checked_add_template(&mut env, entry, data);
+    }
 
     env
 }
+
+/// Fills in any templates an environment is missing with the built-in ones,
+/// so routes can always rely on them being present.
+///
+/// # Returns
+///
+/// - `Result<(), String>` - An error naming the template if even the built-in one couldn't be loaded.
+pub fn ensure_builtin_templates(env: &mut Environment<'_>) -> Result<(), String> {
+    for (entry, data) in BUILTIN_TEMPLATES {
+        if env.get_template(entry).is_ok() {
+            continue;
+        }
+        warn!(
+            "Template {} is missing; Falling back to the built-in one",
+            entry
+        );
+        if let Err(e) = env.add_template(entry, data) {
+            return Err(format!(
+                "Failed to load built-in template \"{}\": {}",
+                entry, e
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use minijinja::Environment;
+
+    use super::{TEMPLATE_ERROR, TEMPLATE_INDEX, ensure_builtin_templates};
+
+    /// Missing templates should be filled in, without replacing custom ones
+    #[test]
+    fn builtin_fallback() {
+        let mut env = Environment::new();
+        env.add_template(TEMPLATE_INDEX, "custom").unwrap();
+
+        ensure_builtin_templates(&mut env).unwrap();
+
+        assert_eq!(
+            env.get_template(TEMPLATE_INDEX)
+                .unwrap()
+                .render(())
+                .unwrap(),
+            "custom"
+        );
+        assert!(env.get_template(TEMPLATE_ERROR).is_ok());
+    }
+}
@@ -13,12 +13,14 @@
 //! </div>
 #![forbid(unsafe_code)]
 
+#[cfg(feature = "server")]
 use frontend::routes::RoutingState;
 
 mod core;
 pub use core::*;
 
 pub mod conf;
+#[cfg(feature = "server")]
 pub mod frontend;
 pub mod provider;
-//pub mod util;
+pub mod testing;
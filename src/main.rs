@@ -1,26 +1,43 @@
-use std::sync::Arc;
+use std::{io::IsTerminal, str::FromStr, sync::Arc};
 
 use actix_web::{
     App, HttpServer, Result,
     middleware::{self, NormalizePath},
+    web,
 };
+use arc_swap::ArcSwap;
 use chrono::{Datelike, Local};
 use clap::Command;
 use config::{Config, File};
 use fern::colors::{Color, ColoredLevelConfig};
-use log::{Level, debug, error, info, warn};
+use log::{Level, LevelFilter, debug, error, info, warn};
 use minijinja::Environment;
 use pageshelf::{
-    PageSource, PageSourceFactory,
+    BoxedPageSource, PageSource, PageSourceFactory,
     conf::ServerConfig,
-    frontend::{setup_service_config, templates::templates_from_builtin},
+    frontend::{
+        cors, request_id,
+        routes::{RoutingState, SharedRoutingState},
+        setup_shared_service_config,
+        templates::templates_from_builtin,
+    },
+    provider::layers::pin::PinLayer,
 };
 
 #[cfg(feature = "forgejo")]
-use pageshelf::provider::ForgejoProviderFactory;
+use pageshelf::{
+    conf::ServerConfigUpstreamMethod,
+    provider::{
+        ForgejoProviderFactory,
+        layers::{inject::InjectLayer, single_flight::SingleFlightLayer},
+    },
+};
 
 use pageshelf::conf::ServerConfigUpstreamType;
 
+#[cfg(all(feature = "forgejo", feature = "git"))]
+use pageshelf::provider::{git::GitMirrors, layers::mirror::MirrorLayer};
+
 #[cfg(feature = "redis")]
 use pageshelf::provider::layers::cache::CacheLayer;
 
@@ -30,7 +47,12 @@ use clap::{arg, crate_authors, crate_description, crate_name, crate_version};
 /*                                    Main                                    */
 /* -------------------------------------------------------------------------- */
 
+/// Exit code for failures during startup, so supervisors can tell them apart from a clean exit.
+const EXIT_FAILURE: i32 = 1;
+
 #[actix_web::main]
+// Without any provider, the server can't be started
+#[cfg_attr(not(feature = "forgejo"), allow(unreachable_code, unused_variables))]
 async fn main() -> std::io::Result<()> {
     println!("{} v{}", crate_name!(), crate_version!());
     println!("Copyright {}", crate_authors!());
@@ -45,78 +67,159 @@ async fn main() -> std::io::Result<()> {
         .about(crate_description!())
         .arg(arg!(-c --config <FILE> "Path to a config file").required(false))
         .arg(arg!(-d --debug "Enables debug information").required(false))
-        //.arg(arg!(-l --log_level "Sets the logging level").required(false))
+        .arg(
+            arg!(-l --"log-level" <LEVEL> "Sets the logging level (off, error, warn, info, debug, trace); Overrides --debug")
+                .required(false)
+                .env("PAGESHELF_LOG_LEVEL")
+                .value_parser(parse_log_level),
+        )
         .get_matches();
 
-    if let Err(e) = setup_logger(cmd.get_flag("debug")) {
-        eprintln!("Failed to initialize logger: {}", e);
-        return Ok(()); // TODO: Use Err()
-    }
-
-    debug!("Debug logging is enabled.");
-
-    let mut settings_builder = Config::builder();
-    if let Some(v) = cmd.get_one::<String>("config") {
-        settings_builder = settings_builder.add_source(File::with_name(v));
-    } else {
-        warn!("No configuration file was specified; Only environment variables will be used.")
-    }
-
-    settings_builder =
-        settings_builder.add_source(config::Environment::with_prefix("page").separator("_"));
-
-    let settings = match settings_builder.build() {
+    // The config is loaded before the logger (which it configures), so errors go to stderr
+    let config_file = cmd.get_one::<String>("config");
+    let config = match load_config(config_file.map(String::as_str)) {
         Ok(v) => v,
         Err(e) => {
-            error!("Failed to build config: {}", e);
-            return Ok(()); // TODO: Use Err()
+            eprintln!("{}", e);
+            std::process::exit(EXIT_FAILURE);
         }
     };
 
-    let config = match settings.try_deserialize::<ServerConfig>() {
-        Ok(v) => v,
-        Err(e) => panic!("Failed to deserialize server configuration: {}", e),
+    // The command line takes priority over the config file
+    let level = match cmd.get_one::<LevelFilter>("log-level") {
+        Some(v) => *v,
+        None => match &config.log_level {
+            Some(v) => match parse_log_level(v) {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("Invalid log_level in the configuration: {}", e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            },
+            None => match cmd.get_flag("debug") {
+                true => LevelFilter::Debug,
+                false => LevelFilter::Info,
+            },
+        },
     };
+    if let Err(e) = setup_logger(level, config.log_file.as_deref(), config.log_color) {
+        eprintln!("Failed to initialize logger: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    debug!("Debug logging is enabled.");
+    if config_file.is_none() {
+        warn!("No configuration file was specified; Only environment variables will be used.")
+    }
+    if let Some(v) = &config.log_file {
+        info!("Logging to {}", v);
+    }
+    #[cfg(not(feature = "images"))]
+    if config.images.webp {
+        warn!("WebP conversion is enabled, but this build lacks the \"images\" feature; Ignoring.");
+    }
 
     let templates = templates_from_builtin();
 
-    match config.upstream.r#type {
+    let source: BoxedPageSource = match config.upstream.r#type {
         #[cfg(feature = "forgejo")]
         ServerConfigUpstreamType::Forgejo => {
             match ForgejoProviderFactory::from_config(config.clone()) {
-                Some(factory) => {
-                    #[cfg(feature = "redis")]
-                    use pageshelf::provider::cache::RedisCache;
-
-                    #[cfg(feature = "redis")]
-                    let redis = CacheLayer::from_cache(
-                        RedisCache::new(&config.cache.address, config.cache.port, config.cache.ttl)
-                            .unwrap(),
-                    );
-                    #[cfg(feature = "redis")]
-                    if config.cache.enabled {
-                        use log::info;
-
-                        info!("Redis is enabled");
-                        let factory = factory.wrap(redis);
-                        return run_server(factory.build(), config, templates).await;
+                Some(factory) => match config.upstream.method {
+                    ServerConfigUpstreamMethod::Direct => build_source(
+                        factory
+                            .wrap(SingleFlightLayer::new())
+                            .wrap(InjectLayer::from_config(&config)),
+                        &config,
+                    ),
+                    #[cfg(feature = "git")]
+                    ServerConfigUpstreamMethod::Mirror => {
+                        info!("Mirroring repositories into {}", config.upstream.mirror_dir);
+                        build_source(
+                            factory
+                                .wrap(MirrorLayer::new(GitMirrors::new(
+                                    &config.upstream.mirror_dir,
+                                    &config.upstream.url,
+                                )))
+                                .wrap(SingleFlightLayer::new())
+                                .wrap(InjectLayer::from_config(&config)),
+                            &config,
+                        )
                     }
-                    run_server(factory.build(), config, templates).await
-                }
+                    #[cfg(not(feature = "git"))]
+                    ServerConfigUpstreamMethod::Mirror => {
+                        error!("The mirror method requires a build with the \"git\" feature.");
+                        std::process::exit(EXIT_FAILURE);
+                    }
+                },
                 None => {
-                    log::error!("The configuration failed to provide a valid Forgejo provider.");
-                    return Ok(());
+                    error!("The configuration failed to provide a valid Forgejo provider.");
+                    std::process::exit(EXIT_FAILURE);
                 }
             }
         }
-    }
+        #[cfg(not(feature = "forgejo"))]
+        ServerConfigUpstreamType::Forgejo => {
+            error!("The Forgejo upstream requires a build with the \"forgejo\" feature.");
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    run_server(source, config, templates, config_file.cloned()).await
 }
 
 /* -------------------------------------------------------------------------- */
 /*                                Major Actions                               */
 /* -------------------------------------------------------------------------- */
 
-fn setup_logger(debug: bool) -> Result<(), fern::InitError> {
+/// Loads the server configuration from a file (if any) and the environment.
+fn load_config(config_file: Option<&str>) -> Result<ServerConfig, String> {
+    let mut settings_builder = Config::builder();
+    if let Some(v) = config_file {
+        settings_builder = settings_builder.add_source(File::with_name(v));
+    }
+
+    settings_builder =
+        settings_builder.add_source(config::Environment::with_prefix("page").separator("_"));
+
+    let settings = match settings_builder.build() {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to build config: {}", e)),
+    };
+
+    match settings.try_deserialize::<ServerConfig>() {
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("Failed to deserialize server configuration: {}", e)),
+    }
+}
+
+/// Parses a logging level, such as `info` or `debug` (case insensitive).
+fn parse_log_level(s: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(s).map_err(|_| {
+        format!(
+            "\"{}\" is not a log level; Expected one of off, error, warn, info, debug, trace",
+            s
+        )
+    })
+}
+
+/// Sets up logging to stdout, and optionally a file.
+///
+/// # Arguments
+///
+/// - `level` (`LevelFilter`) - The most verbose level to log.
+/// - `log_file` (`Option<&str>`) - A file to also append logs to.
+/// - `color` (`Option<bool>`) - Whether to color stdout, or None to only do so for terminals.
+fn setup_logger(
+    level: LevelFilter,
+    log_file: Option<&str>,
+    color: Option<bool>,
+) -> Result<(), fern::InitError> {
+    // Escape codes are noise when logs are captured (files, journald, ...)
+    let color = color.unwrap_or_else(|| std::io::stdout().is_terminal());
+
+    let debug = level >= LevelFilter::Debug;
+
     let colors = ColoredLevelConfig::new()
         .info(Color::BrightGreen)
         .error(Color::BrightRed)
@@ -126,8 +229,29 @@ fn setup_logger(debug: bool) -> Result<(), fern::InitError> {
     let bold_code = "\x1b[1m";
     let reset_code = "\x1b[0m";
 
-    fern::Dispatch::new()
+    // Where the log came from (when debugging), and which request it belongs to
+    let location = move |record: &log::Record| {
+        let mut location = match request_id::current() {
+            Some(id) => format!("[{}]", id),
+            None => "".to_string(),
+        };
+        if debug && let Some(file) = record.file_static() {
+            location.push_str(&format!("[{}:{}]", file, record.line().unwrap_or(0)));
+        }
+        location
+    };
+
+    let stdout = fern::Dispatch::new()
         .format(move |out, message, record| {
+            if !color {
+                return out.finish(format_args!(
+                    "[{}][{}]{} - {}",
+                    Local::now().format("%H:%M:%S"),
+                    record.level(),
+                    location(record),
+                    message
+                ));
+            }
             out.finish(format_args!(
                 "[{}][{}{}{}]{} - {}",
                 Local::now().format("%H:%M:%S"),
@@ -142,41 +266,108 @@ fn setup_logger(debug: bool) -> Result<(), fern::InitError> {
                 } else {
                     ""
                 },
-                if debug && let Some(file) = record.file_static() {
-                    format!("[{}:{}]", file, record.line().unwrap_or(0),)
-                } else {
-                    "".to_string()
-                },
+                location(record),
                 message
             ))
         })
-        .level(match debug {
-            true => log::LevelFilter::Debug,
-            false => log::LevelFilter::Info,
-        })
-        .chain(std::io::stdout())
-        .apply()?;
+        .chain(std::io::stdout());
+
+    let mut dispatch = fern::Dispatch::new().level(level).chain(stdout);
+
+    // Files get the full date and no color codes, and are appended to rather than replaced
+    if let Some(path) = log_file {
+        dispatch = dispatch.chain(
+            fern::Dispatch::new()
+                .format(move |out, message, record| {
+                    out.finish(format_args!(
+                        "[{}][{}]{} - {}",
+                        Local::now().format("%Y-%m-%d %H:%M:%S"),
+                        record.level(),
+                        location(record),
+                        message
+                    ))
+                })
+                .chain(fern::log_file(path)?),
+        );
+    }
+
+    dispatch.apply()?;
     Ok(())
 }
 
+/// Builds the page source from a factory, putting the cache in front of it if enabled,
+/// and pinned pages in front of everything.
+#[cfg_attr(not(feature = "forgejo"), allow(dead_code))]
+fn build_source<F>(factory: F, config: &ServerConfig) -> BoxedPageSource
+where
+    F: PageSourceFactory,
+    F::Source: Send + Sync + 'static,
+{
+    #[cfg(feature = "redis")]
+    if config.cache.enabled {
+        use pageshelf::provider::cache::RedisCache;
+
+        info!("Redis is enabled");
+        let mut redis = CacheLayer::from_cache(
+            RedisCache::new(&config.cache.address, config.cache.port, config.cache.ttl).unwrap(),
+        );
+        if config.cache.compress {
+            redis = redis.with_compression(config.cache.compress_min_size);
+        }
+        let source = factory
+            .wrap(redis.clone())
+            .wrap(PinLayer::from_config(config))
+            .build();
+        // Pages removed upstream shouldn't linger in the cache until they expire
+        if let Some(removed) = source.subscribe_removed() {
+            tokio::spawn(async move { redis.evict_removed(removed).await });
+        }
+        return Box::new(source);
+    }
+    Box::new(factory.wrap(PinLayer::from_config(config)).build())
+}
+
 async fn run_server<PS: PageSource + Sync + Send + 'static>(
     page_source: PS,
     config: ServerConfig,
     templates: Environment<'static>,
+    config_file: Option<String>,
 ) -> std::io::Result<()> {
     let page_source = Arc::new(page_source);
     let port = config.port;
-    let resolver = config.url_resolver();
+    let cors = config.cors.clone();
+    let state = match RoutingState::new(
+        &config,
+        page_source.clone(),
+        config.url_resolver(),
+        Some(templates),
+    ) {
+        Ok(v) => web::Data::new(ArcSwap::from_pointee(v)),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(
+        state.clone(),
+        config,
+        config_file,
+        page_source,
+    ));
+    #[cfg(not(unix))]
+    let _ = config_file;
+
     HttpServer::new(move || {
-        let config = config.clone();
-        let page_source = page_source.clone();
-        let templates = templates.clone();
-        let resolver = resolver.clone();
+        let state = state.clone();
         App::new()
             .wrap(NormalizePath::trim())
             .wrap(middleware::Compress::default())
+            .wrap(cors::middleware(&cors))
+            .wrap(middleware::from_fn(request_id::middleware))
             .configure(move |f| {
-                setup_service_config(f, &config, page_source, resolver, Some(templates));
+                setup_shared_service_config(f, state);
             })
     })
     .bind(("0.0.0.0", port))?
@@ -184,6 +375,67 @@ async fn run_server<PS: PageSource + Sync + Send + 'static>(
     .await
 }
 
+/// Reloads the configuration whenever the process receives `SIGHUP`.
+///
+/// Settings used by the routes (templates, security lists, header policies, ...) are applied to
+/// requests arriving afterwards. Anything else only takes effect on a restart, which is warned about.
+#[cfg(unix)]
+async fn reload_on_hangup<PS: PageSource + Sync + Send + 'static>(
+    state: web::Data<SharedRoutingState<'static, PS, pageshelf::resolver::DefaultUrlResolver>>,
+    mut config: ServerConfig,
+    config_file: Option<String>,
+    page_source: Arc<PS>,
+) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!(
+                "Failed to listen for SIGHUP; The config can't be reloaded: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the configuration...");
+        let reloaded = match load_config(config_file.as_deref()) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{}; Keeping the current configuration.", e);
+                continue;
+            }
+        };
+
+        let restart = config.restart_required(&reloaded);
+        if !restart.is_empty() {
+            warn!(
+                "Changes to [{}] only take effect after a restart.",
+                restart.join(", ")
+            );
+        }
+
+        // Templates are reloaded along with the config, keeping the current ones if they fail
+        let routing = RoutingState::new(
+            &reloaded,
+            page_source.clone(),
+            reloaded.url_resolver(),
+            Some(templates_from_builtin()),
+        );
+        match routing {
+            Ok(v) => state.store(Arc::new(v)),
+            Err(e) => {
+                error!("{}; Keeping the current configuration.", e);
+                continue;
+            }
+        }
+        config = reloaded;
+        info!("Reloaded the configuration.");
+    }
+}
+
 // A little seasonal message, because why not
 fn print_seasonal_message() {
     let now = Local::now();
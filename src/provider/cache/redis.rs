@@ -10,6 +10,9 @@ use redis::{AsyncCommands, Client, RedisError, aio::MultiplexedConnection};
 
 use crate::{Cache, CacheConnection, CacheError};
 
+/// How many keys to look at per `SCAN` while expanding a pattern.
+const SCAN_BATCH: usize = 500;
+
 #[derive(Clone)]
 pub struct RedisCache {
     client: Arc<Client>,
@@ -69,9 +72,7 @@ impl CacheConnection for RedisCacheConnection {
             }
         }
 
-        if self.ttl.is_some() {
-            let ttl = self.ttl.unwrap();
-
+        if let Some(ttl) = self.ttl {
             let result = self.conn.expire(key, i64::from(ttl)).await;
 
             match result {
@@ -119,7 +120,46 @@ impl CacheConnection for RedisCacheConnection {
     }
 
     async fn delete(&mut self, key: &str) -> Result<u32, CacheError> {
-        let result = self.conn.del::<&str, u32>(key).await;
+        // UNLINK only takes literal keys, so patterns have to be expanded first
+        if !key.contains(['*', '?', '[']) {
+            return self.unlink(key, vec![key.to_string()]).await;
+        }
+
+        // KEYS would block the whole (possibly shared) server while it walks every key,
+        // so patterns are expanded in batches instead
+        let mut deleted = 0;
+        let mut cursor = 0u64;
+        loop {
+            let result = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(key)
+                .arg("COUNT")
+                .arg(SCAN_BATCH)
+                .query_async::<(u64, Vec<String>)>(&mut self.conn)
+                .await;
+            let (next, keys) = match result {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Redis error while finding keys \"{}\": {}", key, e);
+                    return Err(CacheError::OperationError(e.to_string()));
+                }
+            };
+            if !keys.is_empty() {
+                deleted += self.unlink(key, keys).await?;
+            }
+            if next == 0 {
+                return Ok(deleted);
+            }
+            cursor = next;
+        }
+    }
+}
+
+impl RedisCacheConnection {
+    /// Deletes keys without blocking the server while their memory is freed.
+    async fn unlink(&mut self, key: &str, keys: Vec<String>) -> Result<u32, CacheError> {
+        let result = self.conn.unlink::<Vec<String>, u32>(keys).await;
 
         match result {
             Ok(v) => Ok(v),
@@ -1,24 +1,191 @@
 /// Utilities for sourcing pages from Forgejo directly, via raw file access.
-use std::path::Path;
+use std::{fmt, path::Path, sync::Arc, time::Duration};
 
-use forgejo_api::{Forgejo, structs::RepoGetRawFileQuery};
-use log::{error, info};
+use forgejo_api::{Forgejo, ForgejoError};
+use log::{debug, error, info, warn};
+use reqwest::{StatusCode, header::CONTENT_TYPE};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use url::Url;
 
-use crate::{Asset, AssetError, AssetSource};
+use crate::{Asset, AssetError, AssetSource, content_hash};
 
 use crate::provider::memory::MemoryAsset;
 
+use super::{
+    rate_limit::RateLimit,
+    tree::{FileTree, TreeCache},
+};
+
+/// How failed upstream fetches should be retried.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryPolicy {
+    /// How many additional attempts to make after the first failure.
+    pub retries: u32,
+    /// The delay before the first retry, doubled for every further attempt.
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, base_delay: Duration) -> Self {
+        Self {
+            retries,
+            base_delay,
+        }
+    }
+
+    /// The delay to wait before the given (zero-indexed) retry.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+/// Caps how many upstream fetches may be in flight at once.
+///
+/// Fetches beyond the cap queue for a permit, and give up after a timeout.
+#[derive(Debug)]
+pub struct FetchLimiter {
+    permits: Semaphore,
+    timeout: Duration,
+}
+
+impl FetchLimiter {
+    pub fn new(max_concurrent: usize, timeout: Duration) -> Self {
+        Self {
+            permits: Semaphore::new(max_concurrent),
+            timeout,
+        }
+    }
+
+    /// Waits for a free slot, returning None if none freed up in time.
+    pub(super) async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        tokio::time::timeout(self.timeout, self.permits.acquire())
+            .await
+            .ok()?
+            .ok()
+    }
+}
+
+/// Fetches raw files from Forgejo, along with their `Content-Type`.
+///
+/// The Forgejo client only hands back the content of raw files, so they're requested directly.
+#[derive(Debug, Clone)]
+pub struct RawFileClient {
+    client: reqwest::Client,
+    url: Url,
+}
+
+/// Why a raw file couldn't be fetched.
+#[derive(Debug)]
+pub enum RawFileError {
+    /// Upstream answered, but not with the file.
+    Status(StatusCode),
+    /// Upstream couldn't be reached, or its response couldn't be read.
+    Request(reqwest::Error),
+}
+
+impl RawFileError {
+    /// The status upstream answered with, if it answered at all.
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Status(status) => Some(*status),
+            Self::Request(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for RawFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status(status) => write!(f, "Unexpected status code {}", status),
+            Self::Request(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl RawFileClient {
+    pub fn new(url: Url, user_agent: &str) -> Result<Self, reqwest::Error> {
+        Ok(Self {
+            client: reqwest::Client::builder().user_agent(user_agent).build()?,
+            url,
+        })
+    }
+
+    /// The API URL of a file in a repository, at the given ref.
+    fn file_url(&self, owner: &str, repo: &str, path: &str, r#ref: &str) -> Url {
+        let mut url = self.url.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments
+                .pop_if_empty()
+                .extend(["api", "v1", "repos", owner, repo, "raw"])
+                .extend(path.split('/').filter(|s| !s.is_empty()));
+        }
+        url.query_pairs_mut().append_pair("ref", r#ref);
+        url
+    }
+
+    /// Fetches a file's content, typed by the response if Forgejo knew its type.
+    async fn get(
+        &self,
+        owner: &str,
+        repo: &str,
+        path: &str,
+        r#ref: &str,
+    ) -> Result<MemoryAsset, RawFileError> {
+        let response = self
+            .client
+            .get(self.file_url(owner, repo, path, r#ref))
+            .send()
+            .await
+            .map_err(RawFileError::Request)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(RawFileError::Status(status));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(known_type)
+            .map(str::to_string);
+        let bytes = response.bytes().await.map_err(RawFileError::Request)?;
+        let asset = MemoryAsset::from(bytes.to_vec());
+        Ok(match content_type {
+            Some(mime) => asset.with_mime_type(&mime),
+            None => asset,
+        })
+    }
+}
+
+/// The `Content-Type` of a raw file, if Forgejo actually knew its type.
+///
+/// Forgejo serves every text file (HTML included) as `text/plain`, and files it doesn't
+/// recognize as `application/octet-stream`, so those are left to be guessed from the name.
+fn known_type(content_type: &str) -> Option<&str> {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    match essence {
+        "" | "text/plain" | "application/octet-stream" => None,
+        _ => Some(content_type),
+    }
+}
+
 pub struct ForgejoDirectReadStorage<'a> {
     forgejo: &'a Forgejo,
+    raw: &'a RawFileClient,
     owner: String,
     repo: String,
     branch: String,
     version: String,
+    retry: RetryPolicy,
+    tree_cache: Option<&'a TreeCache>,
+    limiter: Option<&'a FetchLimiter>,
+    rate_limit: Option<&'a RateLimit>,
+    timeout: Option<Duration>,
 }
 
 impl<'a> ForgejoDirectReadStorage<'a> {
     pub fn new(
         forgejo: &'a Forgejo,
+        raw: &'a RawFileClient,
         owner: String,
         repo: String,
         branch: String,
@@ -26,13 +193,49 @@ impl<'a> ForgejoDirectReadStorage<'a> {
     ) -> Self {
         Self {
             forgejo,
+            raw,
             owner,
             repo,
             branch,
             version,
+            retry: RetryPolicy::default(),
+            tree_cache: None,
+            limiter: None,
+            rate_limit: None,
+            timeout: None,
         }
     }
 
+    /// Factory function to set how failed fetches are retried.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Factory function to check the (cached) file tree before fetching assets.
+    pub fn with_tree_cache(mut self, tree_cache: &'a TreeCache) -> Self {
+        self.tree_cache = Some(tree_cache);
+        self
+    }
+
+    /// Factory function to cap concurrent upstream fetches.
+    pub fn with_limiter(mut self, limiter: &'a FetchLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Factory function to pause fetches while upstream is rate limiting, and start pauses.
+    pub fn with_rate_limit(mut self, rate_limit: &'a RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Factory function to give up on upstream requests that take too long.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn owner(&self) -> &str {
         &self.owner
     }
@@ -50,34 +253,432 @@ impl<'a> ForgejoDirectReadStorage<'a> {
     }
 }
 
+/// Whether or not upstream answered that what was requested doesn't exist.
+///
+/// Only an explicit 404 means that; anything else (5xx responses, network failures,
+/// malformed responses) says nothing about whether it exists.
+pub fn is_not_found(e: &ForgejoError) -> bool {
+    match e {
+        ForgejoError::UnexpectedStatusCode(status) | ForgejoError::ApiError(status, _) => {
+            status.as_u16() == 404
+        }
+        _ => false,
+    }
+}
+
+/// Maps a raw file error to an asset error.
+///
+/// Only an explicit 404 from upstream means the file is missing; anything else is a
+/// provider error.
+fn asset_error_from(e: &RawFileError) -> AssetError {
+    match e.status() == Some(StatusCode::NOT_FOUND) {
+        true => AssetError::NotFound,
+        false => AssetError::ProviderError,
+    }
+}
+
+impl<'a> ForgejoDirectReadStorage<'a> {
+    async fn fetch_raw(&self, path: &str) -> Result<MemoryAsset, RawFileError> {
+        self.raw
+            .get(&self.owner, &self.repo, path, &self.branch)
+            .await
+    }
+
+    /// The file tree of this page, if there's a tree cache and the tree could be fetched.
+    ///
+    /// The tree cache gates its fetches itself, with the limiter and rate limit it was given.
+    async fn tree(&self) -> Option<Arc<FileTree>> {
+        let tree = self.tree_cache?.get(
+            self.forgejo,
+            &self.owner,
+            &self.repo,
+            &self.branch,
+            &self.version,
+        );
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, tree)
+                .await
+                .unwrap_or_else(|_| {
+                    warn!(
+                        "Timed out fetching Forgejo file tree of {}/{}:{}",
+                        self.owner, self.repo, self.branch
+                    );
+                    None
+                }),
+            None => tree.await,
+        }
+    }
+}
+
 impl<'a> AssetSource for ForgejoDirectReadStorage<'a> {
     async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
         let p = path.to_string_lossy();
+        if let Some(tree) = self.tree().await
+            && tree.contains(path) == Some(false)
+        {
+            debug!(
+                "No file {} in the tree of Forgejo repository {}/{}:{}",
+                p, self.owner, self.repo, self.branch
+            );
+            return Err(AssetError::NotFound);
+        }
+
         info!("Fetching Forgejo raw data at {}", p);
-        match self
-            .forgejo
-            .repo_get_raw_file(
-                self.owner.as_str(),
-                self.repo.as_str(),
-                &p,
-                RepoGetRawFileQuery {
-                    r#ref: Some(self.branch.clone()),
+
+        let mut attempt = 0;
+        loop {
+            if let Some(left) = self.rate_limit.and_then(RateLimit::remaining) {
+                warn!(
+                    "Not fetching (raw) data file {} in Forgejo repository {}/{}:{} while rate limited ({:?} left)",
+                    p, self.owner, self.repo, self.branch, left
+                );
+                return Err(AssetError::ProviderError);
+            }
+            let permit = match self.limiter {
+                Some(limiter) => match limiter.acquire().await {
+                    Some(v) => Some(v),
+                    None => {
+                        error!(
+                            "Timed out waiting to fetch (raw) data file {} in Forgejo repository {}/{}:{}",
+                            p, self.owner, self.repo, self.branch
+                        );
+                        return Err(AssetError::ProviderError);
+                    }
                 },
-            )
-            .await
-        {
-            Ok(v) => Ok(MemoryAsset::from(v)),
-            Err(e) => {
+                None => None,
+            };
+            let result = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, self.fetch_raw(&p)).await,
+                None => Ok(self.fetch_raw(&p).await),
+            };
+            drop(permit);
+
+            // A timed out request is treated like any other upstream failure
+            let (err, e) = match result {
+                Ok(Ok(v)) => return Ok(v),
+                // Retrying would only dig deeper into the limit
+                Ok(Err(e)) if e.status() == Some(StatusCode::TOO_MANY_REQUESTS) => {
+                    error!(
+                        "Rate limited getting (raw) data file {} in Forgejo repository {}/{}:{}",
+                        p, self.owner, self.repo, self.branch
+                    );
+                    if let Some(rate_limit) = self.rate_limit {
+                        rate_limit.pause();
+                    }
+                    return Err(AssetError::ProviderError);
+                }
+                Ok(Err(e)) => (asset_error_from(&e), e.to_string()),
+                Err(_) => (
+                    AssetError::ProviderError,
+                    format!("Timed out after {:?}", self.timeout.unwrap_or_default()),
+                ),
+            };
+            if err == AssetError::NotFound {
+                info!(
+                    "No (raw) data file {} in Forgejo repository {}/{}:{}",
+                    p, self.owner, self.repo, self.branch
+                );
+                return Err(err);
+            }
+
+            if attempt >= self.retry.retries {
                 error!(
-                    "Failed to find (raw) data file {} in Forgejo repository {}/{}:{} - {}",
-                    path.to_string_lossy(),
-                    self.owner,
-                    self.repo,
-                    self.branch,
-                    e
+                    "Failed to get (raw) data file {} in Forgejo repository {}/{}:{} - {}",
+                    p, self.owner, self.repo, self.branch, e
+                );
+                return Err(err);
+            }
+
+            let delay = self.retry.delay(attempt);
+            warn!(
+                "Failed to get (raw) data file {} in Forgejo repository {}/{}:{} - {}; Retrying in {:?}",
+                p, self.owner, self.repo, self.branch, e, delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Uses the git blob SHA from the file tree, only hashing the content if it's unknown.
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        if let Some(tree) = self.tree().await {
+            match tree.contains(path) {
+                Some(false) => return None,
+                Some(true) => {
+                    if let Some(sha) = tree.sha(path) {
+                        return Some(sha.to_string());
+                    }
+                }
+                None => {}
+            }
+        }
+        match self.get_asset(path).await {
+            Ok(v) => Some(content_hash(v.bytes())),
+            Err(_) => None,
+        }
+    }
+
+    /// Answered from the file tree if possible, so nothing has to be downloaded.
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        if let Some(tree) = self.tree().await
+            && let Some(v) = tree.contains(path)
+        {
+            return Ok(v);
+        }
+        match self.get_asset(path).await {
+            Ok(_) => Ok(true),
+            Err(AssetError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::{
+        path::Path,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use forgejo_api::{Auth, Forgejo};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{FetchLimiter, ForgejoDirectReadStorage, RawFileClient, RetryPolicy};
+    use crate::provider::forgejo::{
+        rate_limit::RateLimit,
+        tree::{FileTree, TreeCache},
+    };
+    use crate::{Asset, AssetError, AssetSource};
+
+    /// Starts a fake forge that answers every request with the given status line.
+    ///
+    /// Also returns a counter of how many requests it received.
+    pub(in crate::provider::forgejo) async fn mock_forge(
+        status: &'static str,
+    ) -> (url::Url, Arc<AtomicUsize>) {
+        mock_forge_with_headers(status, String::new()).await
+    }
+
+    /// Like [mock_forge], but also answering with the given (CRLF terminated) header lines.
+    async fn mock_forge_with_headers(
+        status: &'static str,
+        headers: String,
+    ) -> (url::Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 {}\r\n{}content-length: 0\r\nconnection: close\r\n\r\n",
+                    status, headers
                 );
-                Err(AssetError::NotFound)
+                let _ = stream.write_all(response.as_bytes()).await;
             }
+        });
+        (url::Url::parse(&format!("http://{}/", addr)).unwrap(), hits)
+    }
+
+    /// The API client and raw file client of the forge at the given URL.
+    fn clients(url: url::Url) -> (Forgejo, RawFileClient) {
+        (
+            Forgejo::new(Auth::None, url.clone()).unwrap(),
+            RawFileClient::new(url, "pageshelf").unwrap(),
+        )
+    }
+
+    /// Storage of the `owner/pages:pages` page, at no particular version.
+    fn storage<'a>(forgejo: &'a Forgejo, raw: &'a RawFileClient) -> ForgejoDirectReadStorage<'a> {
+        ForgejoDirectReadStorage::new(
+            forgejo,
+            raw,
+            "owner".to_string(),
+            "pages".to_string(),
+            "pages".to_string(),
+            "".to_string(),
+        )
+    }
+
+    async fn fetch(url: url::Url, retry: RetryPolicy) -> AssetError {
+        let (forgejo, raw) = clients(url);
+        let storage = storage(&forgejo, &raw).with_retry(retry);
+        match storage.get_asset(Path::new("index.html")).await {
+            Ok(_) => panic!("Expected the fetch to fail"),
+            Err(e) => e,
         }
     }
+
+    /// A 404 from upstream means the asset is missing
+    #[tokio::test]
+    async fn upstream_not_found() {
+        let (url, _) = mock_forge("404 Not Found").await;
+        assert_eq!(
+            fetch(url, RetryPolicy::default()).await,
+            AssetError::NotFound
+        );
+    }
+
+    /// Server and network failures must not look like missing assets
+    #[tokio::test]
+    async fn upstream_failure() {
+        let (url, _) = mock_forge("502 Bad Gateway").await;
+        assert_eq!(
+            fetch(url, RetryPolicy::default()).await,
+            AssetError::ProviderError
+        );
+
+        // Nothing is listening here once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let url = url::Url::parse(&format!("http://{}/", addr)).unwrap();
+        assert_eq!(
+            fetch(url, RetryPolicy::default()).await,
+            AssetError::ProviderError
+        );
+    }
+
+    /// Upstream failures should be retried, but missing assets shouldn't
+    #[tokio::test]
+    async fn retries() {
+        let retry = RetryPolicy::new(2, Duration::from_millis(1));
+
+        let (url, hits) = mock_forge("503 Service Unavailable").await;
+        assert_eq!(fetch(url, retry).await, AssetError::ProviderError);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        let (url, hits) = mock_forge("404 Not Found").await;
+        assert_eq!(fetch(url, retry).await, AssetError::NotFound);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// Rate limited fetches shouldn't be retried, and should pause further fetches
+    #[tokio::test]
+    async fn rate_limited() {
+        let (url, hits) = mock_forge("429 Too Many Requests").await;
+        let (forgejo, raw) = clients(url);
+        let rate_limit = RateLimit::new(Duration::from_secs(60));
+        let storage = storage(&forgejo, &raw)
+            .with_retry(RetryPolicy::new(2, Duration::from_millis(1)))
+            .with_rate_limit(&rate_limit);
+
+        for _ in 0..2 {
+            assert!(matches!(
+                storage.get_asset(Path::new("index.html")).await,
+                Err(AssetError::ProviderError)
+            ));
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(rate_limit.remaining().is_some());
+    }
+
+    /// Fetches that can't get a slot in time should fail without reaching upstream
+    #[tokio::test]
+    async fn limiter_timeout() {
+        let (url, hits) = mock_forge("404 Not Found").await;
+        let (forgejo, raw) = clients(url);
+        let limiter = FetchLimiter::new(1, Duration::from_millis(10));
+        let storage = storage(&forgejo, &raw).with_limiter(&limiter);
+
+        let held = limiter.acquire().await.unwrap();
+        assert!(matches!(
+            storage.get_asset(Path::new("index.html")).await,
+            Err(AssetError::ProviderError)
+        ));
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        drop(held);
+        assert!(matches!(
+            storage.get_asset(Path::new("index.html")).await,
+            Err(AssetError::NotFound)
+        ));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// Raw files should keep the type Forgejo served them with, unless that's only text
+    #[tokio::test]
+    async fn upstream_content_type() {
+        for (content_type, expected) in [
+            ("image/png", Some("image/png")),
+            ("text/plain; charset=utf-8", None),
+        ] {
+            let headers = format!("content-type: {}\r\n", content_type);
+            let (url, _) = mock_forge_with_headers("200 OK", headers).await;
+            let (forgejo, raw) = clients(url);
+            let asset = storage(&forgejo, &raw)
+                .get_asset(Path::new("index.html"))
+                .await
+                .unwrap();
+            assert_eq!(asset.mime_type(), expected);
+        }
+    }
+
+    /// Existence should be answered from the file tree, without fetching anything
+    #[tokio::test]
+    async fn exists_from_tree() {
+        let (url, hits) = mock_forge("200 OK").await;
+        let (forgejo, raw) = clients(url);
+        let trees = TreeCache::new();
+        trees
+            .insert(
+                "owner",
+                "pages",
+                "pages",
+                "v1",
+                FileTree::new().with_file("index.html"),
+            )
+            .await;
+        let storage = ForgejoDirectReadStorage::new(
+            &forgejo,
+            &raw,
+            "owner".to_string(),
+            "pages".to_string(),
+            "pages".to_string(),
+            "v1".to_string(),
+        )
+        .with_tree_cache(&trees);
+
+        assert_eq!(
+            storage.asset_exists(Path::new("/index.html")).await,
+            Ok(true)
+        );
+        assert_eq!(
+            storage.asset_exists(Path::new("/missing.html")).await,
+            Ok(false)
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+    }
+
+    /// Upstream requests that hang should fail rather than block forever
+    #[tokio::test]
+    async fn upstream_timeout() {
+        // Accepts connections, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut streams = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+        let url = url::Url::parse(&format!("http://{}/", addr)).unwrap();
+
+        let (forgejo, raw) = clients(url);
+        let storage = storage(&forgejo, &raw).with_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            storage.get_asset(Path::new("index.html")).await,
+            Err(AssetError::ProviderError)
+        ));
+    }
 }
@@ -1,21 +1,50 @@
 mod asset_direct;
+mod rate_limit;
 mod scanner;
+pub mod tree;
 
-use std::{path::Path, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
 use crate::{
     conf::ServerConfig,
-    {Asset, AssetError, AssetSource}, {Page, PageError, PageSource, PageSourceFactory},
+    provider::scanner::{PreviewBranches, repo_key},
+    {Asset, AssetError, AssetSource, DomainClaims},
+    {Page, PageError, PageLocation, PageSource, PageSourceFactory, ScanStats},
 };
 use forgejo_api::{Auth, Forgejo};
-use log::{error, warn};
-use scanner::ForgejoScanner;
+use log::{error, info, warn};
+use scanner::{ForgejoScanOptions, ForgejoScanner};
+use tokio::sync::broadcast;
+
+use asset_direct::{
+    FetchLimiter, ForgejoDirectReadStorage, RawFileClient, RetryPolicy, is_not_found,
+};
+use rate_limit::RateLimit;
+use tree::TreeCache;
+
+/// How long checks of whether owners without pages exist are remembered for.
+const OWNER_TTL: Duration = Duration::from_secs(300);
 
-use asset_direct::ForgejoDirectReadStorage;
+/// Owner -> Whether they exist, and when that was checked.
+type OwnerMap = HashMap<String, (bool, Instant)>;
 
 pub struct ForgejoProvider {
     forgejo: Arc<Forgejo>,
+    raw: Arc<RawFileClient>,
     analyzer: Arc<ForgejoScanner>,
+    retry: RetryPolicy,
+    trees: Arc<TreeCache>,
+    limiter: Option<Arc<FetchLimiter>>,
+    rate_limit: Arc<RateLimit>,
+    owners: Arc<RwLock<OwnerMap>>,
+    timeout: Duration,
+    lowercase_names: bool,
 }
 
 struct ForgejoPage<'a> {
@@ -44,11 +73,73 @@ impl<'a> AssetSource for ForgejoPage<'a> {
     async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
         self.storage.get_asset(path).await
     }
+
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        self.storage.asset_hash(path).await
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        self.storage.asset_exists(path).await
+    }
 }
 
 impl ForgejoProvider {
-    pub fn new(forgejo: Arc<Forgejo>, analyzer: Arc<ForgejoScanner>) -> Self {
-        Self { forgejo, analyzer }
+    pub fn new(
+        forgejo: Arc<Forgejo>,
+        raw: Arc<RawFileClient>,
+        analyzer: Arc<ForgejoScanner>,
+        retry: RetryPolicy,
+        trees: Arc<TreeCache>,
+        limiter: Option<Arc<FetchLimiter>>,
+        rate_limit: Arc<RateLimit>,
+        owners: Arc<RwLock<OwnerMap>>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            forgejo,
+            raw,
+            analyzer,
+            retry,
+            trees,
+            limiter,
+            rate_limit,
+            owners,
+            timeout,
+            lowercase_names: false,
+        }
+    }
+
+    /// Looks pages up by their lowercased owner and repository names, as the scanner indexes
+    /// them with `lowercase_names`. Forgejo's own lookups ignore case, so assets are still found.
+    pub fn with_lowercase_names(mut self, lowercase_names: bool) -> Self {
+        self.lowercase_names = lowercase_names;
+        self
+    }
+
+    /// Creates the storage to read a page's assets with.
+    fn storage(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+        version: &str,
+    ) -> ForgejoDirectReadStorage<'_> {
+        let storage = ForgejoDirectReadStorage::new(
+            &self.forgejo,
+            &self.raw,
+            owner.to_string(),
+            repo.to_string(),
+            branch.to_string(),
+            version.to_string(),
+        )
+        .with_retry(self.retry)
+        .with_tree_cache(&self.trees)
+        .with_rate_limit(&self.rate_limit)
+        .with_timeout(self.timeout);
+        match &self.limiter {
+            Some(limiter) => storage.with_limiter(limiter),
+            None => storage,
+        }
     }
 }
 
@@ -59,13 +150,7 @@ impl PageSource for ForgejoProvider {
         name: String,
         channel: String,
     ) -> Result<impl Page, PageError> {
-        if !self
-            .analyzer
-            .data
-            .target_branches
-            .iter()
-            .any(|f| f == &channel)
-        {
+        if !self.analyzer.data.accepts_branch(&channel) {
             warn!(
                 "Failed to access a Forgejo page: The branch {} is not in the list of accepted branches",
                 channel
@@ -77,28 +162,95 @@ impl PageSource for ForgejoProvider {
             return Err(PageError::NotFound);
         }
 
+        let (owner, name, channel) = repo_key(&owner, &name, &channel, self.lowercase_names);
         let repos = self.analyzer.data.repos.read().await;
 
         match repos.get(&(owner.clone(), name.clone(), channel.clone())) {
             Some(v) => Ok(ForgejoPage {
-                storage: ForgejoDirectReadStorage::new(
-                    &self.forgejo,
-                    owner.to_string(),
-                    name.to_string(),
-                    channel.to_string(),
-                    v.version.clone(),
-                ),
+                storage: self.storage(&owner, &name, &channel, &v.version),
             }),
             None => {
+                let key = (owner.clone(), name.clone(), channel.clone());
+                if self.analyzer.data.is_gone(&key).await {
+                    info!(
+                        "Forgejo repository at {}/{}:{} was recently removed",
+                        owner, name, channel
+                    );
+                    return Err(PageError::Gone);
+                }
                 error!(
                     "Failed to find Forgejo repository at {}/{}:{}",
                     owner, name, channel
                 );
-                Err::<ForgejoPage, PageError>(PageError::ProviderError)
+                Err::<ForgejoPage, PageError>(PageError::NotFound)
             }
         }
     }
 
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        // Owners with pages are already known from scanning
+        if self
+            .analyzer
+            .data
+            .repos
+            .read()
+            .await
+            .keys()
+            .any(|(o, _, _)| o == owner)
+        {
+            return Ok(true);
+        }
+
+        if let Some((exists, checked)) = self.owners.read().unwrap().get(owner)
+            && checked.elapsed() < OWNER_TTL
+        {
+            return Ok(*exists);
+        }
+
+        if self.rate_limit.remaining().is_some() {
+            warn!(
+                "Not checking if Forgejo user {} exists while rate limited",
+                owner
+            );
+            return Err(PageError::ProviderError);
+        }
+        // Counts towards the same limit as fetching assets
+        let _permit = match &self.limiter {
+            Some(limiter) => match limiter.acquire().await {
+                Some(v) => Some(v),
+                None => {
+                    error!(
+                        "Timed out waiting to check if Forgejo user {} exists",
+                        owner
+                    );
+                    return Err(PageError::ProviderError);
+                }
+            },
+            None => None,
+        };
+        let user = match tokio::time::timeout(self.timeout, self.forgejo.user_get(owner)).await {
+            Ok(v) => v,
+            Err(_) => {
+                error!("Timed out checking if Forgejo user {} exists", owner);
+                return Err(PageError::ProviderError);
+            }
+        };
+        let exists = match user {
+            Ok(_) => true,
+            Err(e) if is_not_found(&e) => false,
+            Err(e) => {
+                self.rate_limit.check(&e);
+                error!("Failed to check if Forgejo user {} exists: {}", owner, e);
+                return Err(PageError::ProviderError);
+            }
+        };
+        let mut owners = self.owners.write().unwrap();
+        // Anyone can ask for any owner, so expired checks are dropped rather than piling up
+        owners.retain(|_, (_, checked)| checked.elapsed() < OWNER_TTL);
+        owners.insert(owner.to_string(), (exists, Instant::now()));
+        Ok(exists)
+    }
+
     async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
         let repos = self.analyzer.data.repos.read().await;
 
@@ -106,18 +258,55 @@ impl PageSource for ForgejoProvider {
 
         for repo in repos.keys() {
             pages.push(ForgejoPage {
-                storage: ForgejoDirectReadStorage::new(
-                    &self.forgejo,
-                    repo.0.to_string(),
-                    repo.1.to_string(),
-                    repo.2.to_string(),
-                    repos[repo].version.clone(),
-                ),
+                storage: self.storage(&repo.0, &repo.1, &repo.2, &repos[repo].version),
             });
         }
 
         Ok(pages.into_iter())
     }
+
+    fn default_branch(&self) -> &str {
+        &self.analyzer.data.default_branch
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        Some(self.analyzer.data.removed_events.subscribe())
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.rate_limit.remaining()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.analyzer.data.last_scan.read().await.clone()
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        Some(self.analyzer.data.domains.read().await.clone())
+    }
+
+    /// Looks the domains up in the index built while scanning, rather than reading every
+    /// page's domain file.
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+        let found = {
+            let index = self.analyzer.data.domains.read().await;
+            domains
+                .iter()
+                .find_map(|v| index.get(v).map(|v| v.serving.clone()))
+        };
+        let Some(loc) = found else {
+            return Err(PageError::NotFound);
+        };
+
+        let repos = self.analyzer.data.repos.read().await;
+        match repos.get(&(loc.owner.clone(), loc.name.clone(), loc.branch.clone())) {
+            Some(v) => Ok(ForgejoPage {
+                storage: self.storage(&loc.owner, &loc.name, &loc.branch, &v.version),
+            }),
+            // Removed since the index was built
+            None => Err(PageError::NotFound),
+        }
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -128,6 +317,14 @@ impl PageSource for ForgejoProvider {
 pub struct ForgejoProviderFactory {
     analyzer: Arc<ForgejoScanner>,
     forgejo: Arc<Forgejo>,
+    raw: Arc<RawFileClient>,
+    retry: RetryPolicy,
+    trees: Arc<TreeCache>,
+    limiter: Option<Arc<FetchLimiter>>,
+    rate_limit: Arc<RateLimit>,
+    owners: Arc<RwLock<OwnerMap>>,
+    timeout: Duration,
+    lowercase_names: bool,
 }
 
 impl ForgejoProviderFactory {
@@ -140,10 +337,21 @@ impl ForgejoProviderFactory {
             }
         };
 
-        let fj = Arc::new(match Forgejo::new(Auth::None, url.clone()) {
+        let user_agent = config.upstream_user_agent();
+        info!("Identifying to Forgejo as \"{}\"", user_agent);
+        let fj = Arc::new(
+            match Forgejo::with_user_agent(Auth::None, url.clone(), &user_agent) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Failed to create Forgejo authentication: {}", e);
+                    return None;
+                }
+            },
+        );
+        let raw = Arc::new(match RawFileClient::new(url.clone(), &user_agent) {
             Ok(v) => v,
             Err(e) => {
-                error!("Failed to create Forgejo authentication: {}", e);
+                error!("Failed to create Forgejo raw file client: {}", e);
                 return None;
             }
         });
@@ -153,13 +361,60 @@ impl ForgejoProviderFactory {
             branches.push("pages".to_string());
         }
 
+        // Shared with the scanner, so either being rate limited pauses both
+        let rate_limit = Arc::new(RateLimit::new(Duration::from_secs(
+            config.upstream.rate_limit_pause,
+        )));
+        let limiter = config.upstream.max_fetches.map(|max| {
+            Arc::new(FetchLimiter::new(
+                max,
+                Duration::from_millis(config.upstream.fetch_timeout),
+            ))
+        });
+        // Shared with the scanner too, so trees it fetches to find empty pages are reused for
+        // serving. Tree fetches are upstream requests like any other, so they're gated the same.
+        let mut trees = TreeCache::new().with_rate_limit(rate_limit.clone());
+        if let Some(limiter) = &limiter {
+            trees = trees.with_limiter(limiter.clone());
+        }
+        let trees = Arc::new(trees);
         Some(Self {
             forgejo: fj.clone(),
+            raw,
             analyzer: Arc::new(ForgejoScanner::start(
                 fj,
-                branches,
-                config.upstream.poll_interval.unwrap_or(240),
+                trees.clone(),
+                rate_limit.clone(),
+                ForgejoScanOptions {
+                    target_branches: branches,
+                    preview: config.upstream.preview_branches.clone().map(|pattern| {
+                        PreviewBranches {
+                            pattern,
+                            idle: config.upstream.preview_idle.map(Duration::from_secs),
+                        }
+                    }),
+                    poll_interval: config.upstream.poll_interval.unwrap_or(240),
+                    gone_window: config.upstream.gone_window.map(Duration::from_secs),
+                    max_pages: config.upstream.max_pages,
+                    // Domains are only ever looked up if they're allowed
+                    index_domains: config.allow_domains,
+                    default_branch: config.upstream.default_branch.clone(),
+                    skip_empty: config.upstream.skip_empty,
+                    queue_scans: config.upstream.queue_scans,
+                    lowercase_names: config.lowercase_names,
+                    timeout: Duration::from_millis(config.upstream.request_timeout),
+                },
             )),
+            retry: RetryPolicy::new(
+                config.upstream.retries,
+                Duration::from_millis(config.upstream.retry_delay),
+            ),
+            trees,
+            limiter,
+            rate_limit,
+            owners: Arc::default(),
+            timeout: Duration::from_millis(config.upstream.request_timeout),
+            lowercase_names: config.lowercase_names,
         })
     }
 }
@@ -168,6 +423,17 @@ impl PageSourceFactory for ForgejoProviderFactory {
     type Source = ForgejoProvider;
 
     fn build(&self) -> Self::Source {
-        ForgejoProvider::new(self.forgejo.clone(), self.analyzer.clone())
+        ForgejoProvider::new(
+            self.forgejo.clone(),
+            self.raw.clone(),
+            self.analyzer.clone(),
+            self.retry,
+            self.trees.clone(),
+            self.limiter.clone(),
+            self.rate_limit.clone(),
+            self.owners.clone(),
+            self.timeout,
+        )
+        .with_lowercase_names(self.lowercase_names)
     }
 }
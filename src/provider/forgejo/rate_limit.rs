@@ -0,0 +1,91 @@
+/// Backing off from Forgejo while it's rate limiting us.
+///
+/// Once upstream answers with `429 Too Many Requests`, every upstream call is skipped until the
+/// pause is over, rather than hammering through the limit. The Forgejo client doesn't expose
+/// response headers, so the pause lasts for the configured `rate_limit_pause`.
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use forgejo_api::ForgejoError;
+use log::warn;
+
+/// Whether or not upstream refused a request for being over its rate limit.
+pub fn is_rate_limited(e: &ForgejoError) -> bool {
+    match e {
+        ForgejoError::UnexpectedStatusCode(status) | ForgejoError::ApiError(status, _) => {
+            status.as_u16() == 429
+        }
+        _ => false,
+    }
+}
+
+/// Shared pause of upstream calls, started whenever upstream rate limits a request.
+#[derive(Debug)]
+pub struct RateLimit {
+    pause: Duration,
+    until: RwLock<Option<Instant>>,
+}
+
+impl RateLimit {
+    pub fn new(pause: Duration) -> Self {
+        Self {
+            pause,
+            until: RwLock::new(None),
+        }
+    }
+
+    /// How long upstream calls are still paused for, if they are.
+    pub fn remaining(&self) -> Option<Duration> {
+        let until = (*self.until.read().unwrap())?;
+        until
+            .checked_duration_since(Instant::now())
+            .filter(|v| !v.is_zero())
+    }
+
+    /// Pauses upstream calls, extending the pause if one is already running.
+    pub fn pause(&self) {
+        let until = Instant::now() + self.pause;
+        let mut current = self.until.write().unwrap();
+        if current.is_none_or(|v| v < until) {
+            warn!(
+                "Rate limited by Forgejo; Pausing upstream requests for {:?}",
+                self.pause
+            );
+            *current = Some(until);
+        }
+    }
+
+    /// Checks a failed request, pausing upstream calls if it was rate limited.
+    ///
+    /// Returns whether or not it was.
+    pub fn check(&self, e: &ForgejoError) -> bool {
+        let limited = is_rate_limited(e);
+        if limited {
+            self.pause();
+        }
+        limited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RateLimit;
+
+    /// Pauses should only last for their duration
+    #[test]
+    fn pause() {
+        let limit = RateLimit::new(Duration::from_millis(50));
+        assert_eq!(limit.remaining(), None);
+
+        limit.pause();
+        let remaining = limit.remaining().unwrap();
+        assert!(remaining <= Duration::from_millis(50));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(limit.remaining(), None);
+    }
+}
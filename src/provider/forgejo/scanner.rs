@@ -4,16 +4,61 @@ use std::{
     time::{Duration, Instant},
 };
 
-use forgejo_api::{Forgejo, structs::RepoSearchQuery};
-use log::info;
-use tokio::{sync::RwLock, task::JoinHandle};
+use forgejo_api::{
+    Forgejo,
+    structs::{Branch, RepoGetRawFileQuery, RepoListBranchesQuery, RepoSearchQuery},
+};
+use log::{info, warn};
+use tokio::{
+    sync::{RwLock, broadcast},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+use crate::{
+    DOMAIN_FILE_PATH, PageLocation, ScanStats, domain_file_entries, glob_matches,
+    provider::{
+        forgejo::{asset_direct::is_not_found, rate_limit::RateLimit, tree::TreeCache},
+        scanner::{
+            DomainMap, PreviewBranches, ProviderScannedRepoData, ProviderScannerData,
+            REMOVED_EVENTS_CAPACITY, RemovedMap, RepoKey, RepoMap, index_domains, insert_capped,
+            is_glob, keep_previous, notify_removed, repo_key, track_removed,
+        },
+    },
+};
 
-use crate::provider::scanner::{ProviderScannedRepoData, ProviderScannerData, RepoMap};
+/// What a Forgejo scanner should look for, and how often.
+#[derive(Clone, Debug)]
+pub struct ForgejoScanOptions {
+    /// Branches that pages are served from, which may be glob patterns (e.g. `pages-*`).
+    pub target_branches: Vec<String>,
+    /// Preview branches to also serve, if any.
+    pub preview: Option<PreviewBranches>,
+    /// How often to scan, in seconds.
+    pub poll_interval: u64,
+    /// How long removed pages should be remembered for; None disables tracking.
+    pub gone_window: Option<Duration>,
+    /// The most pages to index, to bound memory use on very large instances.
+    pub max_pages: Option<usize>,
+    /// Whether or not to index the custom domains pages declare.
+    pub index_domains: bool,
+    /// The branch whose domains take precedence over other branches of the same page.
+    pub default_branch: String,
+    /// Whether or not to skip branches without any files.
+    pub skip_empty: bool,
+    /// Whether or not to start another scan right after one that ran past the interval,
+    /// rather than waiting for the next tick.
+    pub queue_scans: bool,
+    /// Whether or not to index pages by their lowercased owner and repository names.
+    pub lowercase_names: bool,
+    /// How long each request to upstream may take.
+    pub timeout: Duration,
+}
 
 /// Analysis on the current state of a Forgejo instance
 pub struct ForgejoScanner {
     pub data: ProviderScannerData,
-    auto_scan: Arc<AtomicBool>, // TODO: Domain name resolution data
+    auto_scan: Arc<AtomicBool>,
     handle: JoinHandle<()>,
 }
 
@@ -27,35 +72,46 @@ impl Drop for ForgejoScanner {
 }
 
 impl ForgejoScanner {
-    pub fn start(forgejo: Arc<Forgejo>, target_branches: Vec<String>, poll_interval: u64) -> Self {
-        let repos = Arc::new(RwLock::new(HashMap::new()));
+    pub fn start(
+        forgejo: Arc<Forgejo>,
+        trees: Arc<TreeCache>,
+        rate_limit: Arc<RateLimit>,
+        options: ForgejoScanOptions,
+    ) -> Self {
         let auto_scan = Arc::new(AtomicBool::new(true));
+        let data = ProviderScannerData {
+            repos: Arc::new(RwLock::new(HashMap::new())),
+            last_scan: Arc::new(RwLock::new(None)),
+            removed: Arc::new(RwLock::new(HashMap::new())),
+            domains: Arc::new(RwLock::new(HashMap::new())),
+            target_branches: options.target_branches.clone(),
+            preview: options.preview.clone(),
+            default_branch: options.default_branch.clone(),
+            gone_window: options.gone_window,
+            removed_events: broadcast::channel(REMOVED_EVENTS_CAPACITY).0,
+        };
         Self {
-            data: ProviderScannerData {
-                repos: repos.clone(),
-                target_branches: target_branches.clone(),
-            },
+            data: data.clone(),
             auto_scan: auto_scan.clone(),
             handle: tokio::spawn(Self::auto_scan(
-                poll_interval,
-                auto_scan,
-                forgejo,
-                repos,
-                target_branches,
+                auto_scan, forgejo, trees, rate_limit, data, options,
             )),
         }
     }
 
     async fn auto_scan(
-        poll_interval: u64,
         run: Arc<AtomicBool>,
         forgejo: Arc<Forgejo>,
-        repo_storage: Arc<RwLock<RepoMap>>,
-        target_branches: Vec<String>,
+        trees: Arc<TreeCache>,
+        rate_limit: Arc<RateLimit>,
+        data: ProviderScannerData,
+        options: ForgejoScanOptions,
     ) {
-        let interval_duration = Duration::from_secs(poll_interval);
+        let interval_duration = Duration::from_secs(options.poll_interval);
         let start = tokio::time::Instant::now() + interval_duration;
         let mut interval = tokio::time::interval_at(start, interval_duration);
+        // Ticks during a scan are handled while it runs, rather than bursting once it's done
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         loop {
             if !run.load(std::sync::atomic::Ordering::SeqCst) {
@@ -68,22 +124,53 @@ impl ForgejoScanner {
                 tokio::time::Instant::now()
             );
 
-            Self::update(&forgejo, repo_storage.clone(), &target_branches).await;
+            // Only one scan runs at a time; Ticks while it's running are skipped (or queued)
+            let scan = Self::update(&forgejo, &trees, &rate_limit, &data, &options);
+            tokio::pin!(scan);
+            let mut queued = false;
+            loop {
+                tokio::select! {
+                    _ = &mut scan => break,
+                    _ = interval.tick() => match options.queue_scans && !queued {
+                        true => {
+                            info!("A Forgejo scan is still running; Queueing the next one");
+                            queued = true;
+                        }
+                        false => warn!(
+                            "A Forgejo scan is still running after {:?}; Skipping a scan",
+                            interval_duration
+                        ),
+                    },
+                }
+            }
 
-            interval.tick().await;
+            if !queued {
+                interval.tick().await;
+            }
         }
     }
 
+    /// Scans Forgejo for pages, keeping the previous analysis if upstream is rate limiting.
     async fn update(
         forgejo: &Forgejo,
-        repo_storage: Arc<RwLock<RepoMap>>,
-        target_branches: &Vec<String>,
+        trees: &TreeCache,
+        rate_limit: &RateLimit,
+        data: &ProviderScannerData,
+        options: &ForgejoScanOptions,
     ) {
+        if let Some(left) = rate_limit.remaining() {
+            warn!(
+                "Skipping Forgejo analysis while rate limited ({:?} left)",
+                left
+            );
+            return;
+        }
         info!("Updating Forgejo analysis...");
         let start = Instant::now();
 
-        let upstream_repos = forgejo
-            .repo_search(RepoSearchQuery {
+        let upstream_repos = tokio::time::timeout(
+            options.timeout,
+            forgejo.repo_search(RepoSearchQuery {
                 q: None,
                 topic: None,
                 include_desc: None,
@@ -101,41 +188,78 @@ impl ForgejoScanner {
                 order: None,
                 page: None,
                 limit: Some(99999),
-            })
-            .await;
-
-        if upstream_repos.is_err() {
-            log::error!(
-                "Failed to update Forgejo analysis: {}",
-                upstream_repos.unwrap_err()
-            );
-            return;
-        }
+            }),
+        )
+        .await;
 
-        let upstream_repos = upstream_repos.unwrap();
+        let upstream_repos = match upstream_repos {
+            Ok(Ok(v)) => v,
+            Err(_) => {
+                log::error!("Timed out updating Forgejo analysis");
+                return;
+            }
+            Ok(Err(e)) => {
+                rate_limit.check(&e);
+                log::error!("Failed to update Forgejo analysis: {}", e);
+                return;
+            }
+        };
 
         if upstream_repos.data.is_none() {
             return;
         }
 
         let mut update_count = 0;
+        let mut skipped = 0;
+        let mut empty = 0;
+        let mut repos_scanned = 0;
+
+        // Literal branches can be fetched directly, but patterns need every branch to be listed
+        let patterns: Vec<&String> = options
+            .target_branches
+            .iter()
+            .filter(|v| is_glob(v))
+            .collect();
 
-        let mut repos = repo_storage.write().await;
-        repos.clear();
+        let previous = data.repos.read().await.clone();
+        // Built without holding the lock, and swapped in once the scan is done, so pages keep
+        // being served from the previous scan while upstream is queried
+        let mut repos = RepoMap::new();
 
         for repo in upstream_repos.data.unwrap() {
+            if rate_limit.remaining().is_some() {
+                warn!("Rate limited while updating Forgejo analysis; Keeping the previous one");
+                return;
+            }
+            repos_scanned += 1;
             let login = repo.owner.unwrap().login.unwrap();
             let repo_name = repo.name.unwrap();
-            for branch_name in target_branches {
-                let branch = forgejo
-                    .repo_get_branch(&login, &repo_name, branch_name)
-                    .await;
-
-                if branch.is_err() {
-                    continue;
-                }
-
-                let branch = branch.unwrap();
+            for branch_name in options.target_branches.iter().filter(|v| !is_glob(v)) {
+                let branch = tokio::time::timeout(
+                    options.timeout,
+                    forgejo.repo_get_branch(&login, &repo_name, branch_name),
+                )
+                .await;
+                let branch = match branch {
+                    Ok(Ok(v)) => v,
+                    Ok(Err(e)) if rate_limit.check(&e) => break,
+                    Ok(Err(e)) if is_not_found(&e) => continue,
+                    failed => {
+                        let reason = match failed {
+                            Ok(Err(e)) => e.to_string(),
+                            _ => "timed out".to_string(),
+                        };
+                        warn!(
+                            "Failed to get branch {} of {}/{}: {}; Keeping it as it was",
+                            branch_name, login, repo_name, reason
+                        );
+                        let key =
+                            repo_key(&login, &repo_name, branch_name, options.lowercase_names);
+                        skipped +=
+                            keep_previous(&mut repos, &previous, |v| *v == key, options.max_pages);
+                        continue;
+                    }
+                };
 
                 if branch.commit.is_none() {
                     continue;
@@ -148,16 +272,24 @@ impl ForgejoScanner {
                 }
 
                 let version = commit.id.unwrap();
-                repos.insert(
-                    (
-                        login.to_string(),
-                        repo_name.to_string(),
-                        branch_name.to_string(),
-                    ),
+                if options.skip_empty
+                    && Self::is_empty(trees, forgejo, &login, &repo_name, branch_name, &version)
+                        .await
+                {
+                    empty += 1;
+                    continue;
+                }
+                if !insert_capped(
+                    &mut repos,
+                    repo_key(&login, &repo_name, branch_name, options.lowercase_names),
                     ProviderScannedRepoData {
                         version: version.clone(),
                     },
-                );
+                    options.max_pages,
+                ) {
+                    skipped += 1;
+                    continue;
+                }
 
                 update_count += 1;
 
@@ -169,13 +301,351 @@ impl ForgejoScanner {
                     version
                 )
             }
+
+            if (patterns.is_empty() && options.preview.is_none())
+                || rate_limit.remaining().is_some()
+            {
+                continue;
+            }
+
+            let branches =
+                match Self::list_branches(forgejo, rate_limit, &login, &repo_name, options.timeout)
+                    .await
+                {
+                    Some(v) => v,
+                    None => {
+                        // Keeps the branches that were served from it, as they may still be there
+                        let (owner, name, _) =
+                            repo_key(&login, &repo_name, "", options.lowercase_names);
+                        skipped += keep_previous(
+                            &mut repos,
+                            &previous,
+                            |(o, n, _)| *o == owner && *n == name,
+                            options.max_pages,
+                        );
+                        continue;
+                    }
+                };
+            let now = chrono::Utc::now().timestamp();
+            for branch in branches {
+                let Some(branch_name) = branch.name else {
+                    continue;
+                };
+                let key = repo_key(&login, &repo_name, &branch_name, options.lowercase_names);
+                if repos.contains_key(&key) {
+                    continue;
+                }
+
+                let branch_name = &key.2;
+                let is_target = patterns.iter().any(|p| glob_matches(p, branch_name));
+                let preview = match is_target {
+                    true => None,
+                    false => match &options.preview {
+                        Some(v) if v.matches(branch_name) => Some(v),
+                        _ => continue,
+                    },
+                };
+
+                let Some(commit) = branch.commit else {
+                    continue;
+                };
+                if let Some(preview) = preview
+                    && preview.is_expired(commit.timestamp.map(|v| v.unix_timestamp()), now)
+                {
+                    log::debug!(
+                        "Skipping idle preview {}/{}:{}",
+                        login,
+                        repo_name,
+                        branch_name
+                    );
+                    continue;
+                }
+                let Some(version) = commit.id else {
+                    continue;
+                };
+                if options.skip_empty
+                    && Self::is_empty(trees, forgejo, &login, &repo_name, branch_name, &version)
+                        .await
+                {
+                    empty += 1;
+                    continue;
+                }
+
+                log::debug!(
+                    "Analyzed {}/{}:{} (version {}, matched {})",
+                    login,
+                    repo_name,
+                    branch_name,
+                    version,
+                    match preview {
+                        Some(_) => "preview",
+                        None => "pattern",
+                    }
+                );
+                if !insert_capped(
+                    &mut repos,
+                    key,
+                    ProviderScannedRepoData { version },
+                    options.max_pages,
+                ) {
+                    skipped += 1;
+                    continue;
+                }
+                update_count += 1;
+            }
+        }
+
+        if rate_limit.remaining().is_some() {
+            warn!("Rate limited while updating Forgejo analysis; Keeping the previous one");
+            return;
+        }
+
+        if empty > 0 {
+            info!("Skipped {} branch(es) without any files", empty);
+        }
+
+        if skipped > 0 {
+            warn!(
+                "Reached the limit of {} pages; Skipped {} page(s). Raise max_pages to serve them.",
+                options.max_pages.unwrap_or(0),
+                skipped
+            );
+        }
+
+        let mut current = data.repos.write().await;
+        *current = repos;
+        let repos = current.downgrade();
+
+        let previous: Vec<_> = previous.into_keys().collect();
+        let removed_count = notify_removed(&previous, &repos, &data.removed_events);
+        if removed_count > 0 {
+            info!("{} page(s) disappeared since the last scan", removed_count);
+        }
+
+        if let Some(window) = options.gone_window {
+            let mut removed = data.removed.write().await;
+            track_removed(
+                previous.into_iter(),
+                &repos,
+                &mut removed,
+                window,
+                std::time::Instant::now(),
+            );
+            if !removed.is_empty() {
+                info!("{} page(s) were recently removed upstream", removed.len());
+            }
         }
 
-        let end = Instant::now();
-        let duration = (end - start).as_secs_f32();
+        let mut domain_conflicts = 0;
+        if options.index_domains {
+            // Don't hold up requests for pages while every domain file is fetched
+            let pages: Vec<_> = repos.keys().cloned().collect();
+            drop(repos);
+            let (domains, conflicts) = Self::index_domains(
+                forgejo,
+                rate_limit,
+                &pages,
+                &options.default_branch,
+                options.timeout,
+            )
+            .await;
+            match rate_limit.remaining() {
+                Some(_) => {
+                    warn!("Rate limited while indexing custom domains; Keeping the previous index")
+                }
+                None => {
+                    info!(
+                        "Indexed {} custom domain(s) ({} conflicting)",
+                        domains.len(),
+                        conflicts
+                    );
+                    *data.domains.write().await = domains;
+                    domain_conflicts = conflicts;
+                }
+            }
+        }
+
+        let duration = start.elapsed().as_secs_f64();
         info!(
-            "Updated Forgejo analysis (updated {} branches, took {} seconds)",
-            update_count, duration
-        )
+            "Updated Forgejo analysis (duration={:.3}s repos_scanned={} branches_matched={} pages_skipped={})",
+            duration, repos_scanned, update_count, skipped
+        );
+        *data.last_scan.write().await = Some(ScanStats {
+            finished_at: chrono::Utc::now().timestamp(),
+            duration,
+            repos_scanned,
+            branches_matched: update_count,
+            pages_skipped: skipped,
+            pages_empty: empty,
+            domain_conflicts,
+        });
+    }
+
+    /// Whether or not a branch has no files at all.
+    ///
+    /// Trees are cached by version, so they're only fetched when a branch changes.
+    /// If the tree can't be fetched, the branch isn't considered empty.
+    async fn is_empty(
+        trees: &TreeCache,
+        forgejo: &Forgejo,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+        version: &str,
+    ) -> bool {
+        match trees.get(forgejo, owner, repo, branch, version).await {
+            Some(tree) if tree.files().next().is_none() => {
+                log::debug!("Skipping empty branch {}/{}:{}", owner, repo, branch);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Builds the domain index by reading the domain file of every page.
+    ///
+    /// Pages are indexed in order, so conflicts are logged the same way every scan.
+    /// If upstream starts rate limiting, the rest of the pages aren't indexed.
+    ///
+    /// # Returns
+    ///
+    /// - `(DomainMap, usize)` - The index, and how many conflicting domain claims were found.
+    async fn index_domains(
+        forgejo: &Forgejo,
+        rate_limit: &RateLimit,
+        pages: &[RepoKey],
+        default_branch: &str,
+        timeout: Duration,
+    ) -> (DomainMap, usize) {
+        let mut pages = pages.to_vec();
+        pages.sort();
+
+        let mut index = DomainMap::new();
+        let mut conflicts = 0;
+        for (owner, name, branch) in pages {
+            let body = tokio::time::timeout(
+                timeout,
+                forgejo.repo_get_raw_file(
+                    owner,
+                    name,
+                    DOMAIN_FILE_PATH.trim_start_matches('/'),
+                    RepoGetRawFileQuery {
+                        r#ref: Some(branch.clone()),
+                    },
+                ),
+            )
+            .await;
+            let body = match body {
+                Ok(Ok(v)) => v,
+                Ok(Err(e)) if rate_limit.check(&e) => break,
+                // Most pages don't have a domain file
+                Ok(Err(_)) => continue,
+                Err(_) => {
+                    warn!(
+                        "Timed out reading the domain file of {}/{}:{}",
+                        owner, name, branch
+                    );
+                    continue;
+                }
+            };
+            let Ok(body) = std::str::from_utf8(&body) else {
+                warn!(
+                    "Domain file of {}/{}:{} isn't valid UTF-8",
+                    owner, name, branch
+                );
+                continue;
+            };
+            let page = PageLocation {
+                owner: owner.clone(),
+                name: name.clone(),
+                branch: branch.clone(),
+            };
+            conflicts +=
+                index_domains(&mut index, &page, domain_file_entries(body), default_branch);
+        }
+        (index, conflicts)
+    }
+
+    /// Lists every branch of a repository.
+    ///
+    /// Returns None if they couldn't all be listed, unless upstream says the repository is gone.
+    async fn list_branches(
+        forgejo: &Forgejo,
+        rate_limit: &RateLimit,
+        owner: &str,
+        repo: &str,
+        timeout: Duration,
+    ) -> Option<Vec<Branch>> {
+        const LIMIT: u32 = 50;
+
+        let mut branches = vec![];
+        let mut page = 1;
+        loop {
+            let listed = tokio::time::timeout(
+                timeout,
+                forgejo.repo_list_branches(
+                    owner,
+                    repo,
+                    RepoListBranchesQuery {
+                        page: Some(page),
+                        limit: Some(LIMIT),
+                    },
+                ),
+            )
+            .await;
+            match listed {
+                Ok(Ok((_, v))) => {
+                    let done = v.len() < LIMIT as usize;
+                    branches.extend(v);
+                    if done {
+                        return Some(branches);
+                    }
+                    page += 1;
+                }
+                Ok(Err(e)) if is_not_found(&e) => return Some(vec![]),
+                Ok(Err(e)) => {
+                    rate_limit.check(&e);
+                    log::warn!("Failed to list branches of {}/{}: {}", owner, repo, e);
+                    return None;
+                }
+                Err(_) => {
+                    log::warn!("Timed out listing branches of {}/{}", owner, repo);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use forgejo_api::{Auth, Forgejo};
+
+    use super::ForgejoScanner;
+    use crate::provider::forgejo::{asset_direct::tests::mock_forge, rate_limit::RateLimit};
+
+    /// Branches that failed to be listed mustn't look like a repository without any
+    #[tokio::test]
+    async fn list_branches_failure() {
+        let rate_limit = RateLimit::new(Duration::from_secs(60));
+
+        let (url, _) = mock_forge("502 Bad Gateway").await;
+        let forgejo = Forgejo::new(Auth::None, url).unwrap();
+        assert!(
+            ForgejoScanner::list_branches(&forgejo, &rate_limit, "owner", "pages")
+                .await
+                .is_err()
+        );
+
+        let (url, _) = mock_forge("404 Not Found").await;
+        let forgejo = Forgejo::new(Auth::None, url).unwrap();
+        assert!(
+            ForgejoScanner::list_branches(&forgejo, &rate_limit, "owner", "pages")
+                .await
+                .unwrap()
+                .is_empty()
+        );
     }
 }
@@ -0,0 +1,433 @@
+/// Caching of the git file tree of Forgejo pages, keyed by page version.
+///
+/// Knowing which files a page has avoids asking upstream for files that don't exist,
+/// which is most of the index/404 fallback chain.
+use std::{
+    collections::HashMap,
+    path::{Component, Path},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use forgejo_api::{Forgejo, ForgejoError, structs::GetTreeQuery};
+use log::{debug, warn};
+use tokio::sync::{OnceCell, RwLock};
+
+use super::{asset_direct::FetchLimiter, rate_limit::RateLimit};
+
+/// How long a failed tree fetch is remembered for, before it's tried again.
+const FAILED_TTL: Duration = Duration::from_secs(10);
+
+/// The set of files in a page at a given version, with their blob SHAs (if known).
+#[derive(Debug, Default)]
+pub struct FileTree {
+    files: HashMap<String, Option<String>>,
+}
+
+impl FileTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Factory function to add a file to this tree.
+    pub fn with_file(mut self, path: &str) -> Self {
+        self.files
+            .insert(path.trim_start_matches('/').to_string(), None);
+        self
+    }
+
+    /// Factory function to add a file with a known blob SHA to this tree.
+    pub fn with_blob(mut self, path: &str, sha: &str) -> Self {
+        self.files.insert(
+            path.trim_start_matches('/').to_string(),
+            Some(sha.to_string()),
+        );
+        self
+    }
+
+    /// Converts a path into the form files are stored in, if it can be.
+    fn key(path: &Path) -> Option<String> {
+        let mut segments = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(v) => segments.push(v.to_str()?),
+                Component::CurDir | Component::RootDir => {}
+                Component::ParentDir | Component::Prefix(_) => return None,
+            }
+        }
+        Some(segments.join("/"))
+    }
+
+    /// Whether or not the tree has a file at the given path.
+    ///
+    /// Returns None if the path can't be checked against the tree (e.g. it contains `..`),
+    /// in which case the caller should ask upstream instead.
+    pub fn contains(&self, path: &Path) -> Option<bool> {
+        Some(self.files.contains_key(&Self::key(path)?))
+    }
+
+    /// The blob SHA of the file at the given path, if it's in the tree and known.
+    pub fn sha(&self, path: &Path) -> Option<&str> {
+        self.files.get(&Self::key(path)?)?.as_deref()
+    }
+
+    /// Every file in the tree, relative to the page root.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|v| v.as_str())
+    }
+
+    /// Fetches the full tree of a repository at the given commit.
+    async fn fetch(
+        forgejo: &Forgejo,
+        owner: &str,
+        repo: &str,
+        version: &str,
+    ) -> Result<Self, ForgejoError> {
+        let mut tree = Self::new();
+        let mut page = 1;
+        loop {
+            let response = forgejo
+                .get_tree(
+                    owner,
+                    repo,
+                    version,
+                    GetTreeQuery {
+                        recursive: Some(true),
+                        page: Some(page),
+                        per_page: None,
+                    },
+                )
+                .await?;
+
+            let entries = response.tree.unwrap_or_default();
+            if entries.is_empty() {
+                break;
+            }
+            for entry in entries {
+                if entry.r#type.as_deref() == Some("blob")
+                    && let Some(path) = entry.path
+                {
+                    tree.files.insert(path, entry.sha);
+                }
+            }
+
+            if !response.truncated.unwrap_or(false) {
+                break;
+            }
+            page += 1;
+        }
+        Ok(tree)
+    }
+}
+
+/// Page (owner, repo, branch) -> (version, tree)
+type TreeMap = HashMap<(String, String, String), (String, Arc<FileTree>)>;
+
+/// Page (owner, repo, branch, version) -> When fetching its tree last failed
+type FailedMap = HashMap<(String, String, String, String), Instant>;
+
+/// The shared result of a tree fetch in flight.
+type Flight = Arc<OnceCell<Option<Arc<FileTree>>>>;
+
+/// Page (owner, repo, branch, version) -> tree fetch in flight
+type FlightMap = HashMap<(String, String, String, String), Flight>;
+
+/// Keeps the latest known file tree of every page.
+///
+/// Trees are stored alongside the version they were fetched for, so a push
+/// (which changes the version) makes the old tree stale.
+///
+/// Concurrent misses of the same tree share one fetch, and failures are remembered briefly,
+/// so an unreachable tree isn't asked for by every request.
+#[derive(Default)]
+pub struct TreeCache {
+    trees: RwLock<TreeMap>,
+    failed: Mutex<FailedMap>,
+    flights: Mutex<FlightMap>,
+    limiter: Option<Arc<FetchLimiter>>,
+    rate_limit: Option<Arc<RateLimit>>,
+}
+
+impl TreeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Factory function to have tree fetches wait for the same limiter as other upstream fetches.
+    pub fn with_limiter(mut self, limiter: Arc<FetchLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Factory function to skip tree fetches while upstream is rate limiting us, and pause
+    /// upstream calls when a fetch is rate limited.
+    pub fn with_rate_limit(mut self, rate_limit: Arc<RateLimit>) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Gets the cached tree of a page, if it was cached for this version.
+    pub async fn cached(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+        version: &str,
+    ) -> Option<Arc<FileTree>> {
+        let trees = self.trees.read().await;
+        match trees.get(&(owner.to_string(), repo.to_string(), branch.to_string())) {
+            Some((v, tree)) if v == version => Some(tree.clone()),
+            _ => None,
+        }
+    }
+
+    /// Stores the tree of a page for a version, replacing any older one.
+    pub async fn insert(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+        version: &str,
+        tree: FileTree,
+    ) -> Arc<FileTree> {
+        let tree = Arc::new(tree);
+        self.trees.write().await.insert(
+            (owner.to_string(), repo.to_string(), branch.to_string()),
+            (version.to_string(), tree.clone()),
+        );
+        tree
+    }
+
+    /// Gets the tree of a page, fetching it from upstream if it isn't cached for this version.
+    ///
+    /// Returns None if the tree couldn't be fetched (recently), or upstream is rate limiting us.
+    pub async fn get(
+        &self,
+        forgejo: &Forgejo,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+        version: &str,
+    ) -> Option<Arc<FileTree>> {
+        if version.is_empty() {
+            return None;
+        }
+        if let Some(tree) = self.cached(owner, repo, branch, version).await {
+            return Some(tree);
+        }
+
+        let key = (
+            owner.to_string(),
+            repo.to_string(),
+            branch.to_string(),
+            version.to_string(),
+        );
+        if self
+            .failed
+            .lock()
+            .unwrap()
+            .get(&key)
+            .is_some_and(|v| v.elapsed() < FAILED_TTL)
+        {
+            debug!(
+                "Not fetching Forgejo file tree of {}/{}:{}, as it recently failed",
+                owner, repo, branch
+            );
+            return None;
+        }
+
+        let flight = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(&key) {
+                Some(v) => {
+                    debug!(
+                        "Sharing in-flight fetch of Forgejo file tree of {}/{}:{}",
+                        owner, repo, branch
+                    );
+                    v.clone()
+                }
+                None => {
+                    let v = Flight::default();
+                    flights.insert(key.clone(), v.clone());
+                    v
+                }
+            }
+        };
+        let tree = flight
+            .get_or_init(|| self.fetch(forgejo, owner, repo, branch, version))
+            .await
+            .clone();
+
+        // Only concurrent misses share a fetch; The cache (or failure) answers the next one
+        let mut flights = self.flights.lock().unwrap();
+        if flights.get(&key).is_some_and(|v| Arc::ptr_eq(v, &flight)) {
+            flights.remove(&key);
+        }
+        tree
+    }
+
+    /// Fetches the tree of a page from upstream and caches it, remembering if that failed.
+    async fn fetch(
+        &self,
+        forgejo: &Forgejo,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+        version: &str,
+    ) -> Option<Arc<FileTree>> {
+        if let Some(left) = self.rate_limit.as_deref().and_then(RateLimit::remaining) {
+            debug!(
+                "Not fetching Forgejo file tree of {}/{}:{} while rate limited ({:?} left)",
+                owner, repo, branch, left
+            );
+            return None;
+        }
+        let _permit = match &self.limiter {
+            Some(limiter) => match limiter.acquire().await {
+                Some(v) => Some(v),
+                None => {
+                    warn!(
+                        "Timed out waiting to fetch Forgejo file tree of {}/{}:{}",
+                        owner, repo, branch
+                    );
+                    return None;
+                }
+            },
+            None => None,
+        };
+
+        debug!(
+            "Fetching Forgejo file tree of {}/{}:{} (version {})",
+            owner, repo, branch, version
+        );
+        match FileTree::fetch(forgejo, owner, repo, version).await {
+            Ok(tree) => Some(self.insert(owner, repo, branch, version, tree).await),
+            Err(e) => {
+                if let Some(rate_limit) = &self.rate_limit {
+                    rate_limit.check(&e);
+                }
+                warn!(
+                    "Failed to fetch Forgejo file tree of {}/{}:{} - {}",
+                    owner, repo, branch, e
+                );
+                let mut failed = self.failed.lock().unwrap();
+                failed.retain(|_, v| v.elapsed() < FAILED_TTL);
+                failed.insert(
+                    (
+                        owner.to_string(),
+                        repo.to_string(),
+                        branch.to_string(),
+                        version.to_string(),
+                    ),
+                    Instant::now(),
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::atomic::Ordering};
+
+    use forgejo_api::{Auth, Forgejo};
+
+    use super::{FileTree, TreeCache};
+    use crate::provider::forgejo::asset_direct::tests::mock_forge;
+
+    /// Paths should be matched regardless of how they're written
+    #[test]
+    fn tree_contains() {
+        let tree = FileTree::new()
+            .with_file("index.html")
+            .with_file("blog/index.html");
+
+        assert_eq!(tree.contains(Path::new("index.html")), Some(true));
+        assert_eq!(tree.contains(Path::new("/index.html")), Some(true));
+        assert_eq!(tree.contains(Path::new("./blog/index.html")), Some(true));
+        assert_eq!(tree.contains(Path::new("/blog/./index.html")), Some(true));
+        assert_eq!(tree.contains(Path::new("/404.html")), Some(false));
+        assert_eq!(tree.contains(Path::new("/blog")), Some(false));
+        assert_eq!(tree.contains(Path::new("/blog/../index.html")), None);
+    }
+
+    /// Blob SHAs should be found by path, when known
+    #[test]
+    fn tree_sha() {
+        let tree = FileTree::new()
+            .with_blob("style.css", "abc123")
+            .with_file("index.html");
+
+        assert_eq!(tree.sha(Path::new("/style.css")), Some("abc123"));
+        assert_eq!(tree.sha(Path::new("./style.css")), Some("abc123"));
+        assert_eq!(tree.sha(Path::new("/index.html")), None);
+        assert_eq!(tree.sha(Path::new("/missing.css")), None);
+    }
+
+    /// A new version should invalidate the cached tree
+    #[tokio::test]
+    async fn cache_versioned() {
+        let cache = TreeCache::new();
+        cache
+            .insert(
+                "owner",
+                "pages",
+                "pages",
+                "abc",
+                FileTree::new().with_file("index.html"),
+            )
+            .await;
+
+        assert!(
+            cache
+                .cached("owner", "pages", "pages", "abc")
+                .await
+                .is_some()
+        );
+        assert!(
+            cache
+                .cached("owner", "pages", "pages", "def")
+                .await
+                .is_none()
+        );
+        assert!(
+            cache
+                .cached("owner", "other", "pages", "abc")
+                .await
+                .is_none()
+        );
+
+        cache
+            .insert("owner", "pages", "pages", "def", FileTree::new())
+            .await;
+        assert!(
+            cache
+                .cached("owner", "pages", "pages", "abc")
+                .await
+                .is_none()
+        );
+        assert!(
+            cache
+                .cached("owner", "pages", "pages", "def")
+                .await
+                .is_some()
+        );
+    }
+
+    /// Concurrent misses should share a fetch, and a failure shouldn't be retried right away
+    #[tokio::test]
+    async fn fetch_failed() {
+        let (url, hits) = mock_forge("502 Bad Gateway").await;
+        let forgejo = Forgejo::new(Auth::None, url).unwrap();
+        let cache = TreeCache::new();
+
+        let get = || cache.get(&forgejo, "owner", "pages", "pages", "abc");
+        let (a, b) = tokio::join!(get(), get());
+        assert!(a.is_none() && b.is_none());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        assert!(get().await.is_none());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+}
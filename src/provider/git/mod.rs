@@ -0,0 +1,236 @@
+/// Local Git mirrors of upstream repositories.
+///
+/// Repositories are fetched into bare clones on disk, and files are read straight from the
+/// object store at a given commit; Once a commit is mirrored, serving it takes no upstream requests.
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use git2::{ErrorCode, ObjectType, Oid, Repository};
+use log::{debug, error, info};
+
+/// Why a file couldn't be read from a mirror.
+#[derive(Debug)]
+pub enum MirrorError {
+    /// The repository or commit hasn't been mirrored (yet).
+    NotMirrored,
+    /// The owner or name can't be used as a path on disk.
+    InvalidName,
+    /// Git failed to read or fetch the mirror.
+    Git(git2::Error),
+}
+
+impl Display for MirrorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotMirrored => f.write_str("Not mirrored"),
+            Self::InvalidName => f.write_str("Invalid repository name"),
+            Self::Git(e) => write!(f, "Git error: {}", e),
+        }
+    }
+}
+
+impl From<git2::Error> for MirrorError {
+    fn from(value: git2::Error) -> Self {
+        Self::Git(value)
+    }
+}
+
+/// A directory of bare repository mirrors, fetched from `<remote>/<owner>/<name>.git`.
+pub struct GitMirrors {
+    root: PathBuf,
+    remote: String,
+    /// Repositories (owner, name) being fetched right now.
+    syncing: Mutex<HashSet<(String, String)>>,
+}
+
+impl GitMirrors {
+    /// # Arguments
+    ///
+    /// - `root` (`impl Into<PathBuf>`) - The directory to keep mirrors in.
+    /// - `remote` (`&str`) - The base URL repositories are cloned from (e.g. `https://codeberg.org`).
+    pub fn new(root: impl Into<PathBuf>, remote: &str) -> Self {
+        Self {
+            root: root.into(),
+            remote: remote.trim_end_matches('/').to_string(),
+            syncing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Where the mirror of a repository is, if its owner and name are safe to use as a path.
+    fn path(&self, owner: &str, name: &str) -> Result<PathBuf, MirrorError> {
+        let safe = |v: &str| !v.is_empty() && !v.starts_with('.') && !v.contains(['/', '\\', '\0']);
+        match safe(owner) && safe(name) {
+            true => Ok(self.root.join(owner).join(format!("{}.git", name))),
+            false => Err(MirrorError::InvalidName),
+        }
+    }
+
+    /// Fetches a branch of a repository into its mirror, creating the mirror if needed.
+    ///
+    /// This blocks on network and disk access.
+    pub fn sync(&self, owner: &str, name: &str, branch: &str) -> Result<(), MirrorError> {
+        let path = self.path(owner, name)?;
+        let repo = match Repository::open_bare(&path) {
+            Ok(v) => v,
+            Err(_) => Repository::init_bare(&path)?,
+        };
+        let url = format!("{}/{}/{}.git", self.remote, owner, name);
+        let refspec = format!("+refs/heads/{0}:refs/heads/{0}", branch);
+        repo.remote_anonymous(&url)?
+            .fetch(&[&refspec], None, None)?;
+        Ok(())
+    }
+
+    /// Fetches a branch in the background, unless its repository is already being fetched.
+    pub fn sync_in_background(self: &Arc<Self>, owner: &str, name: &str, branch: &str) {
+        let key = (owner.to_string(), name.to_string());
+        if !self.syncing.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+
+        let mirrors = self.clone();
+        let branch = branch.to_string();
+        tokio::task::spawn_blocking(move || {
+            let (owner, name) = &key;
+            info!("Mirroring {}/{}:{}...", owner, name, branch);
+            match mirrors.sync(owner, name, &branch) {
+                Ok(()) => info!("Mirrored {}/{}:{}", owner, name, branch),
+                Err(e) => error!("Failed to mirror {}/{}:{}: {}", owner, name, branch, e),
+            }
+            mirrors.syncing.lock().unwrap().remove(&key);
+        });
+    }
+
+    /// Reads a file of a repository at a commit.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<Vec<u8>>, MirrorError>` - The content of the file, or None if the commit
+    ///   has no such file (directories included).
+    pub fn read(
+        &self,
+        owner: &str,
+        name: &str,
+        version: &str,
+        path: &Path,
+    ) -> Result<Option<Vec<u8>>, MirrorError> {
+        match self.find_file(owner, name, version, path)? {
+            Some((repo, id)) => Ok(Some(repo.find_blob(id)?.content().to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds the blob SHA of a file of a repository at a commit, without reading the file.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<String>, MirrorError>` - The blob SHA (which changes whenever the content
+    ///   does), or None if the commit has no such file (directories included).
+    pub fn blob_id(
+        &self,
+        owner: &str,
+        name: &str,
+        version: &str,
+        path: &Path,
+    ) -> Result<Option<String>, MirrorError> {
+        Ok(self
+            .find_file(owner, name, version, path)?
+            .map(|(_, id)| id.to_string()))
+    }
+
+    /// Finds the blob of a file of a repository at a commit, and the mirror it's in.
+    fn find_file(
+        &self,
+        owner: &str,
+        name: &str,
+        version: &str,
+        path: &Path,
+    ) -> Result<Option<(Repository, Oid)>, MirrorError> {
+        let repo = match Repository::open_bare(self.path(owner, name)?) {
+            Ok(v) => v,
+            Err(e) if e.code() == ErrorCode::NotFound => return Err(MirrorError::NotMirrored),
+            Err(e) => return Err(e.into()),
+        };
+        // The commit borrows the repository, so it's only kept while looking the file up
+        let id = {
+            let commit = match repo.find_commit(Oid::from_str(version)?) {
+                Ok(v) => v,
+                Err(e) if e.code() == ErrorCode::NotFound => return Err(MirrorError::NotMirrored),
+                Err(e) => return Err(e.into()),
+            };
+
+            let path = path.strip_prefix("/").unwrap_or(path);
+            let entry = match commit.tree()?.get_path(path) {
+                Ok(v) => v,
+                Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            if entry.kind() != Some(ObjectType::Blob) {
+                debug!("{:?} in mirror {}/{} is not a file", path, owner, name);
+                return Ok(None);
+            }
+            entry.id()
+        };
+        Ok(Some((repo, id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use git2::{Repository, Signature};
+
+    use super::{GitMirrors, MirrorError};
+
+    /// Mirrored commits should be readable, and unmirrored ones reported as such
+    #[test]
+    fn mirror_read() {
+        let dir = std::env::temp_dir().join(format!("pageshelf-mirror-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // An "upstream" repository with one commit on the pages branch
+        let upstream = Repository::init_bare(dir.join("remote/owner/name.git")).unwrap();
+        let blob = upstream.blob(b"meow").unwrap();
+        let mut tree = upstream.treebuilder(None).unwrap();
+        tree.insert("index.html", blob, 0o100644).unwrap();
+        let tree = upstream.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("Pageshelf", "pageshelf@example.domain").unwrap();
+        let version = upstream
+            .commit(
+                Some("refs/heads/pages"),
+                &signature,
+                &signature,
+                "Initial commit",
+                &tree,
+                &[],
+            )
+            .unwrap()
+            .to_string();
+
+        let remote = format!("file://{}", dir.join("remote").display());
+        let mirrors = GitMirrors::new(dir.join("mirrors"), &remote);
+        let read = |path: &str| mirrors.read("owner", "name", &version, Path::new(path));
+
+        assert!(matches!(read("/index.html"), Err(MirrorError::NotMirrored)));
+
+        mirrors.sync("owner", "name", "pages").unwrap();
+        assert_eq!(read("/index.html").unwrap().unwrap(), b"meow");
+        assert!(read("/missing.html").unwrap().is_none());
+
+        let blob_id = |path: &str| mirrors.blob_id("owner", "name", &version, Path::new(path));
+        assert_eq!(blob_id("/index.html").unwrap(), Some(blob.to_string()));
+        assert!(blob_id("/missing.html").unwrap().is_none());
+
+        assert!(matches!(
+            mirrors.read("..", "name", &version, Path::new("/index.html")),
+            Err(MirrorError::InvalidName)
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,23 +1,247 @@
 /// A Layer that allows using Caches to temporarily store page info and Assets.
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use tokio::sync::broadcast::{self, error::RecvError};
 
 use crate::{
-    Asset, AssetError, AssetSource, Cache, CacheConnection, Page, PageError, PageSource,
-    PageSourceLayer,
+    Asset, AssetError, AssetSource, Cache, CacheConnection, CacheError, DomainClaims, Page,
+    PageError, PageLocation, PageSource, PageSourceLayer, ScanStats,
 };
 
+/// Asset lookups that were served from a cache, across every cache layer.
+static ASSET_HITS: AtomicU64 = AtomicU64::new(0);
+/// Asset lookups that had to go upstream, across every cache layer.
+static ASSET_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// The share of asset lookups served from a cache since startup.
+///
+/// # Returns
+///
+/// - `Option<f64>` - Between 0 and 1, or None if no assets were looked up in a cache.
+pub fn asset_hit_ratio() -> Option<f64> {
+    let hits = ASSET_HITS.load(Ordering::Relaxed);
+    let total = hits + ASSET_MISSES.load(Ordering::Relaxed);
+    match total {
+        0 => None,
+        total => Some(hits as f64 / total as f64),
+    }
+}
+
+/// Builds the keys that cached data is stored under, so reads and writes always agree.
+pub struct CacheKeys;
+
+impl CacheKeys {
+    /// The prefix of every key that belongs to a page.
+    fn page(owner: &str, name: &str, branch: &str) -> String {
+        format!("page:{}:{}:{}", owner, name, branch)
+    }
+
+    /// A pattern matching every key that belongs to a page, for deleting them all at once.
+    pub fn page_all(owner: &str, name: &str, branch: &str) -> String {
+        format!("{}:*", Self::page(owner, name, branch))
+    }
+
+    /// The key of the version a page's cached data belongs to.
+    pub fn page_version(owner: &str, name: &str, branch: &str) -> String {
+        format!("{}:version", Self::page(owner, name, branch))
+    }
+
+    /// The key of an asset of a page.
+    pub fn page_asset(owner: &str, name: &str, branch: &str, path: &Path) -> String {
+        format!(
+            "{}:asset:{}",
+            Self::page(owner, name, branch),
+            path.display()
+        )
+    }
+
+    /// The key of an asset of a page, stored in the compressed format (see [`compress_value`]).
+    pub fn page_asset_compressed(owner: &str, name: &str, branch: &str, path: &Path) -> String {
+        format!(
+            "{}:zasset:{}",
+            Self::page(owner, name, branch),
+            path.display()
+        )
+    }
+
+    /// The key of the custom domains cached as served by a page, one per line, so the
+    /// lookups can be evicted along with the page.
+    pub fn page_domains(owner: &str, name: &str, branch: &str) -> String {
+        format!("{}:domains", Self::page(owner, name, branch))
+    }
+
+    /// The keys of a cached lookup of the page serving a domain.
+    pub fn domain_all(domain: &str) -> [String; 4] {
+        [
+            Self::domain_owner(domain),
+            Self::domain_name(domain),
+            Self::domain_branch(domain),
+            Self::domain_version(domain),
+        ]
+    }
+
+    /// The key of the owner of the page serving a domain.
+    pub fn domain_owner(domain: &str) -> String {
+        format!("domain:{}:owner", domain)
+    }
+
+    /// The key of the name of the page serving a domain.
+    pub fn domain_name(domain: &str) -> String {
+        format!("domain:{}:name", domain)
+    }
+
+    /// The key of the branch of the page serving a domain.
+    pub fn domain_branch(domain: &str) -> String {
+        format!("domain:{}:branch", domain)
+    }
+
+    /// The key of the version of the page serving a domain, when the lookup was cached.
+    ///
+    /// Lookups are only used while the page is at that version, as it may have stopped
+    /// declaring the domain with any change.
+    pub fn domain_version(domain: &str) -> String {
+        format!("domain:{}:version", domain)
+    }
+}
+
+/// Tags a value in the compressed format as stored as-is.
+const VALUE_RAW: u8 = 0;
+/// Tags a value in the compressed format as compressed with zstd.
+const VALUE_ZSTD: u8 = 1;
+
+/// Encodes data in the compressed format: A tag byte, followed by the data (compressed if it's at
+/// least `min_size` bytes long, and compressing made it smaller).
+pub fn compress_value(data: &[u8], min_size: usize) -> Vec<u8> {
+    if data.len() >= min_size {
+        match zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL) {
+            Ok(compressed) if compressed.len() < data.len() => {
+                return tagged(VALUE_ZSTD, &compressed);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to compress value for cache: {}", e),
+        }
+    }
+    tagged(VALUE_RAW, data)
+}
+
+fn tagged(tag: u8, data: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(data.len() + 1);
+    value.push(tag);
+    value.extend_from_slice(data);
+    value
+}
+
+/// Decodes data stored in the compressed format (see [`compress_value`]).
+pub fn decompress_value(value: &[u8]) -> Result<Vec<u8>, CacheError> {
+    match value.split_first() {
+        Some((&VALUE_RAW, data)) => Ok(data.to_vec()),
+        Some((&VALUE_ZSTD, data)) => zstd::stream::decode_all(data)
+            .map_err(|e| CacheError::OperationError(format!("Decompression error: {}", e))),
+        _ => Err(CacheError::OperationError(
+            "Unknown cached value format".to_string(),
+        )),
+    }
+}
+
+/// Encodes a cached asset: Its MIME type (empty if there's none) and a newline, followed by its
+/// content.
+pub fn asset_entry(mime_type: Option<&str>, data: &[u8]) -> Vec<u8> {
+    let mime_type = mime_type.unwrap_or_default();
+    let mut value = Vec::with_capacity(mime_type.len() + data.len() + 1);
+    value.extend_from_slice(mime_type.as_bytes());
+    value.push(b'\n');
+    value.extend_from_slice(data);
+    value
+}
+
+/// Decodes a cached asset (see [`asset_entry`]) into its MIME type and content.
+pub fn read_asset_entry(mut value: Vec<u8>) -> Result<(Option<String>, Vec<u8>), CacheError> {
+    let Some(end) = value.iter().position(|v| *v == b'\n') else {
+        return Err(CacheError::OperationError(
+            "Unknown cached asset format".to_string(),
+        ));
+    };
+    let data = value.split_off(end + 1);
+    value.truncate(end);
+    let mime_type = String::from_utf8(value)
+        .map_err(|_| CacheError::OperationError("Invalid cached MIME type".to_string()))?;
+    Ok(((!mime_type.is_empty()).then_some(mime_type), data))
+}
+
 /// A Layer that caches page info and assets passed through it via Redis.
 #[derive(Clone)]
 pub struct CacheLayer<C: Cache> {
     cache: Arc<C>,
+    /// Minimum size of assets to compress, if compression is enabled.
+    compression: Option<usize>,
 }
 
 impl<C: Cache> CacheLayer<C> {
     pub fn from_cache(cache: C) -> Self {
         Self {
             cache: Arc::new(cache),
+            compression: None,
+        }
+    }
+
+    /// Factory function to compress cached assets that are at least `min_size` bytes long.
+    pub fn with_compression(mut self, min_size: usize) -> Self {
+        self.compression = Some(min_size);
+        self
+    }
+
+    /// Deletes everything cached for pages as they're removed upstream, until the
+    /// source stops announcing removals. Meant to be spawned as a background task, with
+    /// the receiver from [`PageSource::subscribe_removed`].
+    pub async fn evict_removed(&self, mut removed: broadcast::Receiver<PageLocation>) {
+        loop {
+            let page = match removed.recv().await {
+                Ok(v) => v,
+                Err(RecvError::Lagged(count)) => {
+                    warn!(
+                        "Missed {} removed page(s); Their cache entries will linger until they expire",
+                        count
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            self.evict(&page).await;
+        }
+    }
+
+    /// Deletes everything cached for a page.
+    pub async fn evict(&self, page: &PageLocation) {
+        let mut conn = match self.cache.connect().await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to create cache connection: {:?}", e);
+                return;
+            }
+        };
+        // Domain lookups are stored by domain, so they're only found through the page
+        let domains_key = CacheKeys::page_domains(&page.owner, &page.name, &page.branch);
+        if let Ok(domains) = conn.get_string(&domains_key).await {
+            for key in domains.lines().flat_map(CacheKeys::domain_all) {
+                let _ = conn.delete(&key).await;
+            }
+        }
+        let key = CacheKeys::page_all(&page.owner, &page.name, &page.branch);
+        match conn.delete(&key).await {
+            Ok(count) => info!("Page {} was removed; Evicted {} cache entries", page, count),
+            Err(e) => error!(
+                "Failed to evict cache entries of removed page {}: {:?}",
+                page, e
+            ),
         }
     }
 }
@@ -29,6 +253,7 @@ impl<PS: PageSource, C: Cache> PageSourceLayer<PS> for CacheLayer<C> {
         Self::Source {
             upstream: page_source,
             cache: self.cache.clone(),
+            compression: self.compression,
         }
     }
 }
@@ -36,6 +261,7 @@ impl<PS: PageSource, C: Cache> PageSourceLayer<PS> for CacheLayer<C> {
 pub struct CachePage<P: Page, C: Cache> {
     upstream: P,
     cache: Arc<C>,
+    compression: Option<usize>,
 }
 
 impl<P: Page, C: Cache> Page for CachePage<P, C> {
@@ -57,20 +283,26 @@ impl<P: Page, C: Cache> Page for CachePage<P, C> {
 }
 
 pub enum CacheAsset<A: Asset> {
-    Hold(Vec<u8>),
+    Hold(Option<String>, Vec<u8>),
     Load(A),
 }
 
 impl<A: Asset> Asset for CacheAsset<A> {
+    fn mime_type(&self) -> Option<&str> {
+        match self {
+            Self::Hold(mime_type, _) => mime_type.as_deref(),
+            Self::Load(asset) => asset.mime_type(),
+        }
+    }
     fn into_bytes(self) -> Vec<u8> {
         match self {
-            Self::Hold(data) => data,
+            Self::Hold(_, data) => data,
             Self::Load(asset) => asset.into_bytes(),
         }
     }
     fn bytes(&self) -> &[u8] {
         match self {
-            Self::Hold(data) => data,
+            Self::Hold(_, data) => data,
             Self::Load(asset) => asset.bytes(),
         }
     }
@@ -82,6 +314,12 @@ pub enum CacheAssetEither<A: Asset, B: Asset> {
 }
 
 impl<A: Asset, B: Asset> Asset for CacheAssetEither<A, B> {
+    fn mime_type(&self) -> Option<&str> {
+        match self {
+            Self::A(data) => data.mime_type(),
+            Self::B(data) => data.mime_type(),
+        }
+    }
     fn into_bytes(self) -> Vec<u8> {
         match self {
             Self::A(data) => data.into_bytes(),
@@ -164,7 +402,7 @@ impl<PA: Page, PB: Page> Page for RedisCachePageMerge<PA, PB> {
 }
 
 impl<PA: Page, PB: Page> AssetSource for RedisCachePageMerge<PA, PB> {
-    async fn get_asset(&self, path: &std::path::Path) -> Result<impl Asset, AssetError> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
         match self {
             Self::A(v) => match v.get_asset(path).await {
                 Ok(v) => Ok(CacheAssetEither::A(v)),
@@ -176,10 +414,31 @@ impl<PA: Page, PB: Page> AssetSource for RedisCachePageMerge<PA, PB> {
             },
         }
     }
+
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        match self {
+            Self::A(v) => v.asset_hash(path).await,
+            Self::B(v) => v.asset_hash(path).await,
+        }
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        match self {
+            Self::A(v) => v.asset_exists(path).await,
+            Self::B(v) => v.asset_exists(path).await,
+        }
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        match self {
+            Self::A(v) => v.asset_mime_type(path).await,
+            Self::B(v) => v.asset_mime_type(path).await,
+        }
+    }
 }
 
 impl<P: Page, C: Cache> AssetSource for CachePage<P, C> {
-    async fn get_asset(&self, path: &std::path::Path) -> Result<impl Asset, AssetError> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
         let mut conn = match self.cache.connect().await {
             Ok(v) => v,
             Err(e) => {
@@ -187,24 +446,35 @@ impl<P: Page, C: Cache> AssetSource for CachePage<P, C> {
                 return Err(AssetError::ProviderError);
             }
         };
-        let key = format!(
-            "page:{}:{}:{}:asset:{}",
-            self.owner(),
-            self.name(),
-            self.branch(),
-            path.to_str().unwrap()
-        );
+        let key = match self.compression {
+            Some(_) => {
+                CacheKeys::page_asset_compressed(self.owner(), self.name(), self.branch(), path)
+            }
+            None => CacheKeys::page_asset(self.owner(), self.name(), self.branch(), path),
+        };
         debug!("Checking if asset \"{}\" asset is in cache...", key);
-        match conn.get(&key).await {
-            Ok(v) => {
+        let cached = match (conn.get(&key).await, self.compression) {
+            (Ok(v), Some(_)) => decompress_value(&v),
+            (cached, _) => cached,
+        };
+        match cached.and_then(read_asset_entry) {
+            Ok((mime_type, v)) => {
                 info!("Cache hit: {:?}", path);
-                Ok(CacheAsset::Hold(v))
+                ASSET_HITS.fetch_add(1, Ordering::Relaxed);
+                Ok(CacheAsset::Hold(mime_type, v))
             }
             Err(e) => {
                 info!("Cache miss (loading from upstream): {:?}", e);
+                ASSET_MISSES.fetch_add(1, Ordering::Relaxed);
                 match self.upstream.get_asset(path).await {
                     Ok(v) => {
-                        let _ = conn.set(&key, v.bytes()).await;
+                        let entry = asset_entry(v.mime_type(), v.bytes());
+                        let _ = match self.compression {
+                            Some(min_size) => {
+                                conn.set(&key, &compress_value(&entry, min_size)).await
+                            }
+                            None => conn.set(&key, &entry).await,
+                        };
                         Ok(CacheAsset::Load(v))
                     }
                     Err(e) => {
@@ -215,11 +485,24 @@ impl<P: Page, C: Cache> AssetSource for CachePage<P, C> {
             }
         }
     }
+
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        self.upstream.asset_hash(path).await
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        self.upstream.asset_exists(path).await
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        self.upstream.asset_mime_type(path).await
+    }
 }
 
 pub struct CacheLayerSource<PS: PageSource, C: Cache> {
     upstream: PS,
     cache: Arc<C>,
+    compression: Option<usize>,
 }
 
 impl<PS: PageSource, C: Cache> PageSource for CacheLayerSource<PS, C> {
@@ -238,12 +521,7 @@ impl<PS: PageSource, C: Cache> PageSource for CacheLayerSource<PS, C> {
         };
         match self.upstream.page_at(owner, name, branch).await {
             Ok(page) => Ok({
-                let version_key = format!(
-                    "page:{}:{}:{}:version",
-                    page.owner(),
-                    page.name(),
-                    page.branch()
-                );
+                let version_key = CacheKeys::page_version(page.owner(), page.name(), page.branch());
                 match conn.get(&version_key).await {
                     Ok(v) => {
                         let version = std::str::from_utf8(&v);
@@ -259,12 +537,7 @@ impl<PS: PageSource, C: Cache> PageSource for CacheLayerSource<PS, C> {
                                 "Page was updated (version: {}); Invalidating cache...",
                                 version
                             );
-                            let key = format!(
-                                "page:{}:{}:{}:*",
-                                page.owner(),
-                                page.name(),
-                                page.branch()
-                            );
+                            let key = CacheKeys::page_all(page.owner(), page.name(), page.branch());
                             let _ = conn.delete(&key).await;
 
                             let _ = conn.set(&version_key, page.version().as_bytes()).await;
@@ -278,6 +551,7 @@ impl<PS: PageSource, C: Cache> PageSource for CacheLayerSource<PS, C> {
                 CachePage {
                     upstream: page,
                     cache: self.cache.clone(),
+                    compression: self.compression,
                 }
             }),
             Err(e) => Err(e),
@@ -288,7 +562,31 @@ impl<PS: PageSource, C: Cache> PageSource for CacheLayerSource<PS, C> {
         self.upstream.pages().await
     }
 
-    async fn find_by_domains(&self, domains: &[&str]) -> Result<impl Page, PageError> {
+    fn default_branch(&self) -> &str {
+        self.upstream.default_branch()
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.upstream.subscribe_removed()
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.upstream.retry_after()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.upstream.scan_stats().await
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        self.upstream.domain_index().await
+    }
+
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        self.upstream.owner_exists(owner).await
+    }
+
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
         debug!("Connecting to Redis to cache search...");
         let mut conn = match self.cache.connect().await {
             Ok(v) => v,
@@ -297,39 +595,339 @@ impl<PS: PageSource, C: Cache> PageSource for CacheLayerSource<PS, C> {
                 return Err(PageError::ProviderError);
             }
         };
-        for domain in domains {
-            let key_o = format!("domain:owner:{}", domain);
-            let key_r = format!("domain:name:{}", domain);
+        for domain in &domains {
+            let [key_o, key_r, key_b, key_v] = CacheKeys::domain_all(domain);
+            // Domains may be served by other branches than the default one
             if let Ok(o) = conn.get_string(&key_o).await
                 && let Ok(r) = conn.get_string(&key_r).await
-                && let Ok(upstream) = self.page_at(o, r, self.default_branch().to_string()).await
+                && let Ok(b) = conn.get_string(&key_b).await
+                && let Ok(v) = conn.get_string(&key_v).await
+                && let Ok(upstream) = self.page_at(o, r, b).await
+                && upstream.version() == v
             {
                 info!("Cache hit! Found by cached domain.");
                 return Ok(CachePage {
                     upstream: RedisCachePageMerge::A(upstream),
                     cache: self.cache.clone(),
+                    compression: self.compression,
                 });
             }
         }
         info!("Cache miss! Finding by domain...");
 
-        let find = self.upstream.find_by_domains(domains).await;
+        let find = self.upstream.find_by_domains(domains.clone()).await;
         match find {
             Ok(page) => {
-                for domain in domains {
-                    let key_o = format!("domain:{}:owner", domain);
-                    let key_r = format!("domain:{}:name", domain);
+                let domains_key = CacheKeys::page_domains(page.owner(), page.name(), page.branch());
+                let mut cached = conn.get_string(&domains_key).await.unwrap_or_default();
+                for domain in &domains {
+                    let [key_o, key_r, key_b, key_v] = CacheKeys::domain_all(domain);
                     // TODO: Error reporting
                     let _ = conn.set(&key_o, page.owner().as_bytes()).await;
                     let _ = conn.set(&key_r, page.name().as_bytes()).await;
+                    let _ = conn.set(&key_b, page.branch().as_bytes()).await;
+                    let _ = conn.set(&key_v, page.version().as_bytes()).await;
+                    if !cached.lines().any(|v| v == domain) {
+                        cached.push_str(domain);
+                        cached.push('\n');
+                    }
                 }
+                let _ = conn.set(&domains_key, cached.as_bytes()).await;
 
                 Ok(CachePage {
                     upstream: RedisCachePageMerge::B(page),
                     cache: self.cache.clone(),
+                    compression: self.compression,
                 })
             }
             Err(e) => Err(e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::Path,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use crate::{
+        Asset, AssetSource, Cache, CacheConnection, CacheError, Page, PageError, PageLocation,
+        PageSource, PageSourceFactory, PageSourceLayer,
+        provider::{
+            MemoryPageProvider, memory::MemoryAsset, testing::create_example_provider_factory,
+        },
+    };
+
+    use super::{CacheKeys, CacheLayer, compress_value, decompress_value};
+
+    /// A cache kept in memory, shared between its connections.
+    #[derive(Clone, Default)]
+    struct TestCache {
+        data: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    }
+
+    impl Cache for TestCache {
+        type Connection = TestCache;
+
+        async fn connect(&self) -> Result<Self::Connection, CacheError> {
+            Ok(self.clone())
+        }
+    }
+
+    impl CacheConnection for TestCache {
+        async fn set(&mut self, key: &str, value: &[u8]) -> Result<(), CacheError> {
+            self.data
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), value.to_vec());
+            Ok(())
+        }
+
+        async fn get(&mut self, key: &str) -> Result<Vec<u8>, CacheError> {
+            self.data
+                .lock()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .ok_or(CacheError::NotFound)
+        }
+
+        async fn delete(&mut self, key: &str) -> Result<u32, CacheError> {
+            let mut data = self.data.lock().unwrap();
+            let before = data.len();
+            match key.strip_suffix('*') {
+                Some(prefix) => data.retain(|k, _| !k.starts_with(prefix)),
+                None => data.retain(|k, _| k != key),
+            }
+            Ok((before - data.len()) as u32)
+        }
+    }
+
+    /// Counts lookups by domain that reach it.
+    struct CountingSource {
+        upstream: MemoryPageProvider,
+        finds: Arc<AtomicUsize>,
+    }
+
+    impl PageSource for CountingSource {
+        async fn page_at(
+            &self,
+            owner: String,
+            name: String,
+            branch: String,
+        ) -> Result<impl Page, PageError> {
+            self.upstream.page_at(owner, name, branch).await
+        }
+
+        async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+            self.upstream.pages().await
+        }
+
+        async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+            self.finds.fetch_add(1, Ordering::SeqCst);
+            self.upstream.find_by_domains(domains).await
+        }
+    }
+
+    /// A domain lookup should be served from the cache after it was cached
+    #[tokio::test]
+    async fn domain_cached() {
+        let cache = TestCache::default();
+        let finds = Arc::new(AtomicUsize::new(0));
+        let source = CacheLayer::from_cache(cache.clone()).wrap(CountingSource {
+            upstream: create_example_provider_factory()
+                .with_asset(
+                    "owner_1",
+                    "name_1",
+                    "pages",
+                    Path::new("/.domain"),
+                    MemoryAsset::from("custom.domain"),
+                )
+                .build(),
+            finds: finds.clone(),
+        });
+
+        for _ in 0..2 {
+            let page = source
+                .find_by_domains(vec!["custom.domain".to_string()])
+                .await
+                .unwrap();
+            assert_eq!((page.owner(), page.name()), ("owner_1", "name_1"));
+        }
+        assert_eq!(finds.load(Ordering::SeqCst), 1);
+
+        let mut conn = cache.connect().await.unwrap();
+        assert_eq!(
+            conn.get_string(&CacheKeys::domain_owner("custom.domain"))
+                .await
+                .unwrap(),
+            "owner_1"
+        );
+    }
+
+    /// Cached domain lookups should only be used while the page is at the version they were
+    /// cached at, and be evicted along with the page
+    #[tokio::test]
+    async fn domain_cached_invalidation() {
+        let cache = TestCache::default();
+        let finds = Arc::new(AtomicUsize::new(0));
+        let layer = CacheLayer::from_cache(cache.clone());
+        let source = layer.wrap(CountingSource {
+            upstream: create_example_provider_factory()
+                .with_asset(
+                    "owner_1",
+                    "name_1",
+                    "pages",
+                    Path::new("/.domain"),
+                    MemoryAsset::from("custom.domain"),
+                )
+                .build(),
+            finds: finds.clone(),
+        });
+        source
+            .find_by_domains(vec!["custom.domain".to_string()])
+            .await
+            .unwrap();
+
+        // Cached while the page was at another version
+        let mut conn = cache.connect().await.unwrap();
+        conn.set(&CacheKeys::domain_version("custom.domain"), b"old")
+            .await
+            .unwrap();
+        source
+            .find_by_domains(vec!["custom.domain".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(finds.load(Ordering::SeqCst), 2);
+        source
+            .find_by_domains(vec!["custom.domain".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(finds.load(Ordering::SeqCst), 2);
+
+        layer
+            .evict(&PageLocation {
+                owner: "owner_1".to_string(),
+                name: "name_1".to_string(),
+                branch: "pages".to_string(),
+            })
+            .await;
+        for key in CacheKeys::domain_all("custom.domain") {
+            assert!(conn.get(&key).await.is_err());
+        }
+    }
+
+    /// Cached domain lookups should keep serving the branch that declared the domain
+    #[tokio::test]
+    async fn domain_cached_branch() {
+        let finds = Arc::new(AtomicUsize::new(0));
+        let source = CacheLayer::from_cache(TestCache::default()).wrap(CountingSource {
+            upstream: create_example_provider_factory()
+                .with_asset(
+                    "owner_1",
+                    "name_1",
+                    "preview",
+                    Path::new("/.domain"),
+                    MemoryAsset::from("preview.domain"),
+                )
+                .build(),
+            finds: finds.clone(),
+        });
+
+        for _ in 0..2 {
+            let page = source
+                .find_by_domains(vec!["preview.domain".to_string()])
+                .await
+                .unwrap();
+            assert_eq!(page.branch(), "preview");
+        }
+        assert_eq!(finds.load(Ordering::SeqCst), 1);
+    }
+
+    /// Compressed assets should be stored smaller, and read back as they were
+    #[tokio::test]
+    async fn asset_mime_type() {
+        let source = CacheLayer::from_cache(TestCache::default()).wrap(
+            create_example_provider_factory()
+                .with_asset(
+                    "owner_1",
+                    "name_1",
+                    "pages",
+                    Path::new("/data"),
+                    MemoryAsset::from("{}").with_mime_type("application/json"),
+                )
+                .build(),
+        );
+        let page = source
+            .page_at(
+                "owner_1".to_string(),
+                "name_1".to_string(),
+                "pages".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // Miss, then hit
+        for _ in 0..2 {
+            let asset = page.get_asset(Path::new("/data")).await.unwrap();
+            assert_eq!(asset.mime_type(), Some("application/json"));
+            assert_eq!(asset.bytes(), b"{}");
+        }
+    }
+
+    #[tokio::test]
+    async fn asset_compressed() {
+        let body = "meow ".repeat(1000);
+        let cache = TestCache::default();
+        let source = CacheLayer::from_cache(cache.clone())
+            .with_compression(64)
+            .wrap(
+                create_example_provider_factory()
+                    .with_asset(
+                        "owner_1",
+                        "name_1",
+                        "pages",
+                        Path::new("/index.html"),
+                        MemoryAsset::from(body.clone()),
+                    )
+                    .build(),
+            );
+        let page = source
+            .page_at(
+                "owner_1".to_string(),
+                "name_1".to_string(),
+                "pages".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // Miss, then hit
+        for _ in 0..2 {
+            let asset = page.get_asset(Path::new("/index.html")).await.unwrap();
+            assert_eq!(asset.bytes(), body.as_bytes());
+        }
+
+        let key = CacheKeys::page_asset_compressed(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/index.html"),
+        );
+        let stored = cache.connect().await.unwrap().get(&key).await.unwrap();
+        assert!(stored.len() < body.len());
+
+        // Small values aren't worth compressing
+        assert_eq!(compress_value(b"nya", 64), b"\0nya");
+        assert_eq!(
+            decompress_value(&compress_value(b"nya", 64)).unwrap(),
+            b"nya"
+        );
+        assert!(decompress_value(b"").is_err());
+    }
+}
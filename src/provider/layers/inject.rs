@@ -0,0 +1,290 @@
+/// A Layer that injects synthetic assets into every page passed through it.
+///
+/// Synthetic assets are only served when the upstream page doesn't provide
+/// the asset itself, so page owners can still override them.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{debug, error, info};
+use tokio::sync::broadcast;
+
+use crate::{
+    Asset, AssetError, AssetSource, DomainClaims, Page, PageError, PageLocation, PageSource,
+    PageSourceLayer, ScanStats, conf::ServerConfig, content_hash, provider::memory::MemoryAsset,
+};
+
+/// Normalizes an asset path so that `security.txt`, `./security.txt` and
+/// `/security.txt` all refer to the same synthetic asset.
+fn normalize_path(path: &Path) -> PathBuf {
+    std::path::absolute(Path::new("/").join(path)).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A Layer that serves configured assets for reserved paths when upstream doesn't have them.
+#[derive(Clone, Default)]
+pub struct InjectLayer {
+    assets: Arc<HashMap<PathBuf, MemoryAsset>>,
+}
+
+impl InjectLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Factory function to add a synthetic asset to this layer.
+    pub fn with_asset(mut self, path: &Path, asset: MemoryAsset) -> Self {
+        Arc::make_mut(&mut self.assets).insert(normalize_path(path), asset);
+        self
+    }
+
+    /// Creates the layer from the `inject` entries in the server configuration.
+    ///
+    /// Entries that can't be loaded are logged and skipped.
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let mut layer = Self::new();
+        for entry in &config.inject {
+            let asset = match (&entry.content, &entry.file) {
+                (Some(content), _) => MemoryAsset::from(content.as_str()),
+                (None, Some(file)) => match std::fs::read(file) {
+                    Ok(v) => MemoryAsset::from(v),
+                    Err(e) => {
+                        error!(
+                            "Failed to read injected asset {} from \"{}\": {}",
+                            entry.path, file, e
+                        );
+                        continue;
+                    }
+                },
+                (None, None) => {
+                    error!(
+                        "Injected asset {} has neither content nor a file; Skipping.",
+                        entry.path
+                    );
+                    continue;
+                }
+            };
+            info!("Injecting synthetic asset {}", entry.path);
+            layer = layer.with_asset(Path::new(&entry.path), asset);
+        }
+        layer
+    }
+
+    /// Whether or not this layer has any assets to inject.
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for InjectLayer {
+    type Source = InjectLayerSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        Self::Source {
+            upstream: page_source,
+            assets: self.assets.clone(),
+        }
+    }
+}
+
+pub struct InjectLayerSource<PS: PageSource> {
+    upstream: PS,
+    assets: Arc<HashMap<PathBuf, MemoryAsset>>,
+}
+
+impl<PS: PageSource> PageSource for InjectLayerSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        match self.upstream.page_at(owner, name, branch).await {
+            Ok(page) => Ok(InjectPage {
+                upstream: page,
+                assets: self.assets.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        match self.upstream.pages().await {
+            Ok(pages) => Ok(pages.map(|page| InjectPage {
+                upstream: page,
+                assets: self.assets.clone(),
+            })),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn default_branch(&self) -> &str {
+        self.upstream.default_branch()
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.upstream.subscribe_removed()
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.upstream.retry_after()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.upstream.scan_stats().await
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        self.upstream.domain_index().await
+    }
+
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        self.upstream.owner_exists(owner).await
+    }
+
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+        match self.upstream.find_by_domains(domains).await {
+            Ok(page) => Ok(InjectPage {
+                upstream: page,
+                assets: self.assets.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+pub struct InjectPage<P: Page> {
+    upstream: P,
+    assets: Arc<HashMap<PathBuf, MemoryAsset>>,
+}
+
+impl<P: Page> Page for InjectPage<P> {
+    fn name(&self) -> &str {
+        self.upstream.name()
+    }
+
+    fn branch(&self) -> &str {
+        self.upstream.branch()
+    }
+
+    fn owner(&self) -> &str {
+        self.upstream.owner()
+    }
+
+    fn version(&self) -> &str {
+        self.upstream.version()
+    }
+}
+
+impl<P: Page> AssetSource for InjectPage<P> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+        match self.upstream.get_asset(path).await {
+            Ok(v) => Ok(InjectAsset::Upstream(v)),
+            Err(AssetError::NotFound) => match self.assets.get(&normalize_path(path)) {
+                Some(v) => {
+                    debug!("Serving injected asset {:?}", path);
+                    Ok(InjectAsset::Synthetic(v.clone()))
+                }
+                None => Err(AssetError::NotFound),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn total_bytes(&self) -> Option<u32> {
+        self.upstream.total_bytes()
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        match self.upstream.asset_exists(path).await {
+            Ok(false) => Ok(self.assets.contains_key(&normalize_path(path))),
+            v => v,
+        }
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        match self.upstream.asset_mime_type(path).await {
+            Some(v) => Some(v),
+            None => self
+                .assets
+                .get(&normalize_path(path))
+                .and_then(|v| v.mime_type().map(str::to_string)),
+        }
+    }
+
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        match self.upstream.asset_hash(path).await {
+            Some(v) => Some(v),
+            None => self
+                .assets
+                .get(&normalize_path(path))
+                .map(|v| content_hash(v.bytes())),
+        }
+    }
+}
+
+pub enum InjectAsset<A: Asset> {
+    Upstream(A),
+    Synthetic(MemoryAsset),
+}
+
+impl<A: Asset> Asset for InjectAsset<A> {
+    fn mime_type(&self) -> Option<&str> {
+        match self {
+            Self::Upstream(asset) => asset.mime_type(),
+            Self::Synthetic(asset) => asset.mime_type(),
+        }
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Upstream(asset) => asset.into_bytes(),
+            Self::Synthetic(asset) => asset.into_bytes(),
+        }
+    }
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Upstream(asset) => asset.bytes(),
+            Self::Synthetic(asset) => asset.bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        Asset, AssetSource, PageSource, PageSourceFactory,
+        provider::{memory::MemoryAsset, testing::create_example_provider_factory},
+    };
+
+    use super::InjectLayer;
+
+    /// Injected assets should fill gaps, but never replace what upstream provides.
+    #[tokio::test]
+    async fn inject_only_missing() {
+        let layer = InjectLayer::new()
+            .with_asset(Path::new("/security.txt"), MemoryAsset::from("injected"))
+            .with_asset(Path::new("asset_1"), MemoryAsset::from("injected"));
+        let source = create_example_provider_factory().wrap(layer).build();
+
+        let page = source
+            .page_at(
+                "owner_1".to_string(),
+                "name_1".to_string(),
+                "pages".to_string(),
+            )
+            .await
+            .unwrap();
+
+        let injected = page.get_asset(Path::new("/security.txt")).await.unwrap();
+        assert_eq!(injected.body().unwrap(), "injected");
+
+        let upstream = page.get_asset(Path::new("/asset_1")).await.unwrap();
+        assert_eq!(upstream.body().unwrap(), "data_1");
+
+        assert!(page.get_asset(Path::new("/missing.txt")).await.is_err());
+    }
+}
@@ -0,0 +1,211 @@
+/// A Layer that serves assets from local Git mirrors of the pages' repositories.
+///
+/// Assets are read from the mirror at the page's version (commit); If that commit isn't mirrored
+/// yet, the asset comes from upstream while the mirror is fetched in the background.
+/// Once a page's repository is mirrored, its assets are served without any upstream requests.
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+
+use log::{debug, warn};
+use tokio::sync::broadcast;
+
+use crate::{
+    Asset, AssetError, AssetSource, DomainClaims, Page, PageError, PageLocation, PageSource,
+    PageSourceLayer, ScanStats,
+    provider::{
+        git::{GitMirrors, MirrorError},
+        memory::MemoryAsset,
+    },
+};
+
+/// A Layer that reads assets from local Git mirrors.
+#[derive(Clone)]
+pub struct MirrorLayer {
+    mirrors: Arc<GitMirrors>,
+}
+
+impl MirrorLayer {
+    pub fn new(mirrors: GitMirrors) -> Self {
+        Self {
+            mirrors: Arc::new(mirrors),
+        }
+    }
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for MirrorLayer {
+    type Source = MirrorLayerSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        Self::Source {
+            upstream: page_source,
+            mirrors: self.mirrors.clone(),
+        }
+    }
+}
+
+pub struct MirrorLayerSource<PS: PageSource> {
+    upstream: PS,
+    mirrors: Arc<GitMirrors>,
+}
+
+impl<PS: PageSource> PageSource for MirrorLayerSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        match self.upstream.page_at(owner, name, branch).await {
+            Ok(page) => Ok(MirrorPage {
+                upstream: page,
+                mirrors: self.mirrors.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        match self.upstream.pages().await {
+            Ok(pages) => Ok(pages.map(|page| MirrorPage {
+                upstream: page,
+                mirrors: self.mirrors.clone(),
+            })),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn default_branch(&self) -> &str {
+        self.upstream.default_branch()
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.upstream.subscribe_removed()
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.upstream.retry_after()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.upstream.scan_stats().await
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        self.upstream.domain_index().await
+    }
+
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        self.upstream.owner_exists(owner).await
+    }
+
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+        match self.upstream.find_by_domains(domains).await {
+            Ok(page) => Ok(MirrorPage {
+                upstream: page,
+                mirrors: self.mirrors.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+pub struct MirrorPage<P: Page> {
+    upstream: P,
+    mirrors: Arc<GitMirrors>,
+}
+
+impl<P: Page> Page for MirrorPage<P> {
+    fn name(&self) -> &str {
+        self.upstream.name()
+    }
+
+    fn branch(&self) -> &str {
+        self.upstream.branch()
+    }
+
+    fn owner(&self) -> &str {
+        self.upstream.owner()
+    }
+
+    fn version(&self) -> &str {
+        self.upstream.version()
+    }
+}
+
+impl<P: Page> MirrorPage<P> {
+    /// Looks a file up in the page's mirror, starting to mirror the page if it isn't yet.
+    ///
+    /// # Returns
+    ///
+    /// - `Option<T>` - What was looked up, or None if the mirror can't tell (so upstream should).
+    async fn lookup_mirror<T: Send + 'static>(
+        &self,
+        path: &Path,
+        lookup: fn(&GitMirrors, &str, &str, &str, &Path) -> Result<T, MirrorError>,
+    ) -> Option<T> {
+        let mirrors = self.mirrors.clone();
+        let (owner, name, version) = (
+            self.owner().to_string(),
+            self.name().to_string(),
+            self.version().to_string(),
+        );
+        let file = path.to_path_buf();
+        let read =
+            tokio::task::spawn_blocking(move || lookup(&mirrors, &owner, &name, &version, &file))
+                .await;
+
+        match read {
+            Ok(Ok(v)) => return Some(v),
+            Ok(Err(MirrorError::NotMirrored)) => {
+                debug!(
+                    "{}/{} isn't mirrored at {}; Reading from upstream",
+                    self.owner(),
+                    self.name(),
+                    self.version()
+                );
+                self.mirrors
+                    .sync_in_background(self.owner(), self.name(), self.branch());
+            }
+            Ok(Err(e)) => warn!(
+                "Failed to read {:?} from the mirror of {}/{}: {}",
+                path,
+                self.owner(),
+                self.name(),
+                e
+            ),
+            Err(e) => warn!("Failed to read from mirror: {}", e),
+        }
+        None
+    }
+}
+
+impl<P: Page> AssetSource for MirrorPage<P> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+        match self.lookup_mirror(path, GitMirrors::read).await {
+            Some(Some(v)) => Ok(MemoryAsset::from(v)),
+            Some(None) => Err(AssetError::NotFound),
+            None => {
+                let asset = self.upstream.get_asset(path).await?;
+                Ok(MemoryAsset::from_asset(asset))
+            }
+        }
+    }
+
+    fn total_bytes(&self) -> Option<u32> {
+        self.upstream.total_bytes()
+    }
+
+    /// The blob SHA from the mirror, if the commit is mirrored.
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        match self.lookup_mirror(path, GitMirrors::blob_id).await {
+            Some(v) => v,
+            None => self.upstream.asset_hash(path).await,
+        }
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        match self.lookup_mirror(path, GitMirrors::blob_id).await {
+            Some(v) => Ok(v.is_some()),
+            None => self.upstream.asset_exists(path).await,
+        }
+    }
+}
@@ -1 +1,7 @@
 pub mod cache;
+pub mod inject;
+#[cfg(feature = "git")]
+pub mod mirror;
+pub mod pin;
+pub mod single_flight;
+pub mod transform;
@@ -0,0 +1,392 @@
+/// A Layer that keeps pinned pages available while upstream is failing.
+///
+/// Assets of pinned pages are kept in memory (without expiry) as they're served. If upstream
+/// fails later, pinned pages are served from what was kept. While upstream works, kept assets
+/// follow the page's version, so updates still replace them.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use tokio::sync::broadcast;
+
+use crate::{
+    Asset, AssetError, AssetSource, DomainClaims, Page, PageError, PageLocation, PageSource,
+    PageSourceLayer, ScanStats, conf::ServerConfig, content_hash, provider::memory::MemoryAsset,
+};
+
+/// The assets kept for one version of a pinned page.
+pub struct Kept {
+    version: String,
+    assets: HashMap<PathBuf, MemoryAsset>,
+}
+
+/// What's kept of a pinned page, if anything was served yet.
+pub type PinSlot = Arc<RwLock<Option<Kept>>>;
+
+/// A Layer that serves pinned pages from memory when upstream fails.
+#[derive(Clone, Default)]
+pub struct PinLayer {
+    pins: Arc<HashMap<(String, String, String), PinSlot>>,
+}
+
+impl PinLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Factory function to pin a page.
+    pub fn with_page(mut self, page: PageLocation) -> Self {
+        Arc::make_mut(&mut self.pins)
+            .insert((page.owner, page.name, page.branch), PinSlot::default());
+        self
+    }
+
+    /// Creates the layer from the `pinned` entries in the server configuration.
+    ///
+    /// Entries that can't be parsed are logged and skipped.
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let mut layer = Self::new();
+        for entry in &config.pinned {
+            match PageLocation::parse_with_defaults(
+                entry,
+                &config.upstream.default_repo,
+                &config.upstream.default_branch,
+            ) {
+                Ok(page) => {
+                    info!("Pinning page {}", page);
+                    layer = layer.with_page(page);
+                }
+                Err(e) => error!("Failed to parse pinned page \"{}\": {}", entry, e),
+            }
+        }
+        layer
+    }
+
+    /// Whether or not any pages are pinned.
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for PinLayer {
+    type Source = PinLayerSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        Self::Source {
+            upstream: page_source,
+            pins: self.pins.clone(),
+        }
+    }
+}
+
+pub struct PinLayerSource<PS: PageSource> {
+    upstream: PS,
+    pins: Arc<HashMap<(String, String, String), PinSlot>>,
+}
+
+impl<PS: PageSource> PinLayerSource<PS> {
+    fn pin_of(&self, owner: &str, name: &str, branch: &str) -> Option<PinSlot> {
+        self.pins
+            .get(&(owner.to_string(), name.to_string(), branch.to_string()))
+            .cloned()
+    }
+
+    /// Wraps a page from upstream, starting over what's kept of it if its version changed.
+    fn live<P: Page>(&self, page: P) -> PinPage<P> {
+        let pin = self.pin_of(page.owner(), page.name(), page.branch());
+        if let Some(pin) = &pin {
+            let mut kept = pin.write().unwrap();
+            if kept.as_ref().is_none_or(|v| v.version != page.version()) {
+                *kept = Some(Kept {
+                    version: page.version().to_string(),
+                    assets: HashMap::new(),
+                });
+            }
+        }
+        PinPage::Live {
+            upstream: page,
+            pin,
+        }
+    }
+}
+
+impl<PS: PageSource> PageSource for PinLayerSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        let pin = self.pin_of(&owner, &name, &branch);
+        match self
+            .upstream
+            .page_at(owner.clone(), name.clone(), branch.clone())
+            .await
+        {
+            Ok(page) => Ok(self.live(page)),
+            Err(PageError::ProviderError) => {
+                let version = pin
+                    .as_ref()
+                    .and_then(|pin| pin.read().unwrap().as_ref().map(|v| v.version.clone()));
+                match (pin, version) {
+                    (Some(pin), Some(version)) => {
+                        warn!(
+                            "Upstream failed; Serving pinned page {}/{}:{} as kept",
+                            owner, name, branch
+                        );
+                        Ok(PinPage::Kept {
+                            owner,
+                            name,
+                            branch,
+                            version,
+                            pin,
+                        })
+                    }
+                    _ => Err(PageError::ProviderError),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        match self.upstream.pages().await {
+            Ok(pages) => Ok(pages.map(|page| self.live(page))),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn default_branch(&self) -> &str {
+        self.upstream.default_branch()
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.upstream.subscribe_removed()
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.upstream.retry_after()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.upstream.scan_stats().await
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        self.upstream.domain_index().await
+    }
+
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        self.upstream.owner_exists(owner).await
+    }
+
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+        match self.upstream.find_by_domains(domains).await {
+            Ok(page) => Ok(self.live(page)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+pub enum PinPage<P: Page> {
+    /// The page as upstream has it, keeping its assets if it's pinned.
+    Live { upstream: P, pin: Option<PinSlot> },
+    /// A pinned page served from what was kept, as upstream failed.
+    Kept {
+        owner: String,
+        name: String,
+        branch: String,
+        version: String,
+        pin: PinSlot,
+    },
+}
+
+impl<P: Page> PinPage<P> {
+    /// Gets a kept asset of the current version of the page.
+    fn kept_asset(&self, pin: &PinSlot, path: &Path) -> Option<MemoryAsset> {
+        let kept = pin.read().unwrap();
+        kept.as_ref()
+            .filter(|v| v.version == self.version())
+            .and_then(|v| v.assets.get(path).cloned())
+    }
+}
+
+impl<P: Page> Page for PinPage<P> {
+    fn name(&self) -> &str {
+        match self {
+            Self::Live { upstream, .. } => upstream.name(),
+            Self::Kept { name, .. } => name,
+        }
+    }
+
+    fn branch(&self) -> &str {
+        match self {
+            Self::Live { upstream, .. } => upstream.branch(),
+            Self::Kept { branch, .. } => branch,
+        }
+    }
+
+    fn owner(&self) -> &str {
+        match self {
+            Self::Live { upstream, .. } => upstream.owner(),
+            Self::Kept { owner, .. } => owner,
+        }
+    }
+
+    fn version(&self) -> &str {
+        match self {
+            Self::Live { upstream, .. } => upstream.version(),
+            Self::Kept { version, .. } => version,
+        }
+    }
+}
+
+impl<P: Page> AssetSource for PinPage<P> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+        match self {
+            Self::Live {
+                upstream,
+                pin: None,
+            } => Ok(MemoryAsset::from_asset(upstream.get_asset(path).await?)),
+            Self::Live {
+                upstream,
+                pin: Some(pin),
+            } => match upstream.get_asset(path).await {
+                Ok(asset) => {
+                    let asset = MemoryAsset::from_asset(asset);
+                    let mut kept = pin.write().unwrap();
+                    if let Some(kept) = kept.as_mut().filter(|v| v.version == self.version()) {
+                        kept.assets.insert(path.to_path_buf(), asset.clone());
+                    }
+                    Ok(asset)
+                }
+                Err(AssetError::ProviderError) => {
+                    warn!("Upstream failed; Serving pinned asset {:?} as kept", path);
+                    self.kept_asset(pin, path).ok_or(AssetError::ProviderError)
+                }
+                Err(e) => Err(e),
+            },
+            // Whatever wasn't kept can't be told apart from missing assets
+            Self::Kept { pin, .. } => self.kept_asset(pin, path).ok_or(AssetError::NotFound),
+        }
+    }
+
+    fn total_bytes(&self) -> Option<u32> {
+        match self {
+            Self::Live { upstream, .. } => upstream.total_bytes(),
+            Self::Kept { .. } => None,
+        }
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        match self {
+            Self::Live {
+                upstream,
+                pin: None,
+            } => upstream.asset_exists(path).await,
+            Self::Live {
+                upstream,
+                pin: Some(pin),
+            } => match upstream.asset_exists(path).await {
+                Err(AssetError::ProviderError) if self.kept_asset(pin, path).is_some() => Ok(true),
+                v => v,
+            },
+            Self::Kept { pin, .. } => Ok(self.kept_asset(pin, path).is_some()),
+        }
+    }
+
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        match self {
+            Self::Live { upstream, .. } => upstream.asset_hash(path).await,
+            Self::Kept { pin, .. } => self.kept_asset(pin, path).map(|v| content_hash(v.bytes())),
+        }
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        match self {
+            Self::Live { upstream, .. } => upstream.asset_mime_type(path).await,
+            Self::Kept { pin, .. } => self
+                .kept_asset(pin, path)
+                .and_then(|v| v.mime_type().map(str::to_string)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+    };
+
+    use crate::{
+        Asset, AssetError, AssetSource, Page, PageError, PageLocation, PageSource, PageSourceLayer,
+        provider::{MemoryPageProvider, testing::create_example_provider},
+    };
+
+    use super::PinLayer;
+
+    /// Fails every request while it's down.
+    struct FlakySource {
+        upstream: MemoryPageProvider,
+        down: Arc<AtomicBool>,
+    }
+
+    impl PageSource for FlakySource {
+        async fn page_at(
+            &self,
+            owner: String,
+            name: String,
+            branch: String,
+        ) -> Result<impl Page, PageError> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(PageError::ProviderError);
+            }
+            self.upstream.page_at(owner, name, branch).await
+        }
+
+        async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+            self.upstream.pages().await
+        }
+    }
+
+    /// Pinned pages should be served as kept while upstream is down, and others not at all
+    #[tokio::test]
+    async fn pinned_outage() {
+        let down = Arc::new(AtomicBool::new(false));
+        let source = PinLayer::new()
+            .with_page("owner_1/name_1:pages".parse::<PageLocation>().unwrap())
+            .wrap(FlakySource {
+                upstream: create_example_provider(),
+                down: down.clone(),
+            });
+        let get = |owner: &str, name: &str| {
+            source.page_at(owner.to_string(), name.to_string(), "pages".to_string())
+        };
+
+        let page = get("owner_1", "name_1").await.unwrap();
+        let asset = page.get_asset(Path::new("/asset_1")).await.unwrap();
+        assert_eq!(asset.body().unwrap(), "data_1");
+        get("owner_2", "name_2").await.unwrap();
+
+        down.store(true, Ordering::SeqCst);
+        let page = get("owner_1", "name_1").await.unwrap();
+        let asset = page.get_asset(Path::new("/asset_1")).await.unwrap();
+        assert_eq!(asset.body().unwrap(), "data_1");
+        assert!(matches!(
+            page.get_asset(Path::new("/missing")).await,
+            Err(AssetError::NotFound)
+        ));
+        assert!(matches!(
+            get("owner_2", "name_2").await,
+            Err(PageError::ProviderError)
+        ));
+    }
+}
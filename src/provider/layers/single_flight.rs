@@ -0,0 +1,310 @@
+/// A Layer that coalesces concurrent fetches of the same asset into one upstream fetch.
+///
+/// When a popular asset is missing from the cache, every request for it would otherwise
+/// go upstream at once; With this layer, the first request fetches it and the rest wait
+/// for (and share) its result.
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::debug;
+use tokio::sync::{OnceCell, broadcast};
+
+use crate::{
+    Asset, AssetError, AssetSource, DomainClaims, Page, PageError, PageLocation, PageSource,
+    PageSourceLayer, ScanStats, provider::memory::MemoryAsset,
+};
+
+/// The shared result of a fetch in flight.
+type Flight = Arc<OnceCell<Result<MemoryAsset, AssetError>>>;
+
+/// Asset key (owner, name, branch, version, path) -> fetch in flight
+type FlightMap = HashMap<(String, String, String, String, String), Flight>;
+
+/// A Layer that shares one upstream fetch between concurrent requests for the same asset.
+#[derive(Clone, Default)]
+pub struct SingleFlightLayer {
+    flights: Arc<Mutex<FlightMap>>,
+}
+
+impl SingleFlightLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for SingleFlightLayer {
+    type Source = SingleFlightLayerSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        Self::Source {
+            upstream: page_source,
+            flights: self.flights.clone(),
+        }
+    }
+}
+
+pub struct SingleFlightLayerSource<PS: PageSource> {
+    upstream: PS,
+    flights: Arc<Mutex<FlightMap>>,
+}
+
+impl<PS: PageSource> PageSource for SingleFlightLayerSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        match self.upstream.page_at(owner, name, branch).await {
+            Ok(page) => Ok(SingleFlightPage {
+                upstream: page,
+                flights: self.flights.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        match self.upstream.pages().await {
+            Ok(pages) => Ok(pages.map(|page| SingleFlightPage {
+                upstream: page,
+                flights: self.flights.clone(),
+            })),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn default_branch(&self) -> &str {
+        self.upstream.default_branch()
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.upstream.subscribe_removed()
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.upstream.retry_after()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.upstream.scan_stats().await
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        self.upstream.domain_index().await
+    }
+
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        self.upstream.owner_exists(owner).await
+    }
+
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+        match self.upstream.find_by_domains(domains).await {
+            Ok(page) => Ok(SingleFlightPage {
+                upstream: page,
+                flights: self.flights.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+pub struct SingleFlightPage<P: Page> {
+    upstream: P,
+    flights: Arc<Mutex<FlightMap>>,
+}
+
+impl<P: Page> Page for SingleFlightPage<P> {
+    fn name(&self) -> &str {
+        self.upstream.name()
+    }
+
+    fn branch(&self) -> &str {
+        self.upstream.branch()
+    }
+
+    fn owner(&self) -> &str {
+        self.upstream.owner()
+    }
+
+    fn version(&self) -> &str {
+        self.upstream.version()
+    }
+}
+
+impl<P: Page> AssetSource for SingleFlightPage<P> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+        let key = (
+            self.owner().to_string(),
+            self.name().to_string(),
+            self.branch().to_string(),
+            self.version().to_string(),
+            path.to_string_lossy().to_string(),
+        );
+
+        let flight = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(&key) {
+                Some(v) => {
+                    debug!("Sharing in-flight fetch of asset {:?}", path);
+                    v.clone()
+                }
+                None => {
+                    let v = Flight::default();
+                    flights.insert(key.clone(), v.clone());
+                    v
+                }
+            }
+        };
+        let result = flight
+            .get_or_init(|| async {
+                self.upstream
+                    .get_asset(path)
+                    .await
+                    .map(MemoryAsset::from_asset)
+            })
+            .await
+            .clone();
+
+        // Only concurrent requests share a fetch; The next one should go upstream again
+        let mut flights = self.flights.lock().unwrap();
+        if flights.get(&key).is_some_and(|v| Arc::ptr_eq(v, &flight)) {
+            flights.remove(&key);
+        }
+
+        result
+    }
+
+    fn total_bytes(&self) -> Option<u32> {
+        self.upstream.total_bytes()
+    }
+
+    async fn asset_hash(&self, path: &Path) -> Option<String> {
+        self.upstream.asset_hash(path).await
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        self.upstream.asset_exists(path).await
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        self.upstream.asset_mime_type(path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        Asset, AssetError, AssetSource, Page, PageError, PageSource, PageSourceLayer,
+        provider::memory::MemoryAsset,
+    };
+
+    use super::SingleFlightLayer;
+
+    /// A page whose assets take a while to fetch, counting every fetch.
+    struct SlowPage {
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl Page for SlowPage {
+        fn name(&self) -> &str {
+            "pages"
+        }
+
+        fn branch(&self) -> &str {
+            "pages"
+        }
+
+        fn owner(&self) -> &str {
+            "owner"
+        }
+
+        fn version(&self) -> &str {
+            "abc"
+        }
+    }
+
+    impl AssetSource for SlowPage {
+        async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(MemoryAsset::from(path.to_string_lossy().to_string()))
+        }
+    }
+
+    struct SlowSource {
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl PageSource for SlowSource {
+        async fn page_at(
+            &self,
+            _owner: String,
+            _name: String,
+            _branch: String,
+        ) -> Result<impl Page, PageError> {
+            Ok(SlowPage {
+                fetches: self.fetches.clone(),
+            })
+        }
+
+        async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+            Ok(std::iter::once(SlowPage {
+                fetches: self.fetches.clone(),
+            }))
+        }
+    }
+
+    /// Concurrent requests for one asset should share a single upstream fetch
+    #[tokio::test]
+    async fn concurrent_fetches_coalesce() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let source = SingleFlightLayer::new().wrap(SlowSource {
+            fetches: fetches.clone(),
+        });
+        let page = source
+            .page_at(
+                "owner".to_string(),
+                "pages".to_string(),
+                "pages".to_string(),
+            )
+            .await
+            .unwrap();
+
+        let path = Path::new("/index.html");
+        let results = fetch_concurrently(&page, path).await;
+        assert!(results.iter().all(|v| v == "/index.html"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // Different assets don't share, and finished fetches aren't reused
+        page.get_asset(Path::new("/other.html")).await.unwrap();
+        page.get_asset(path).await.unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    async fn fetch_concurrently(page: &impl Page, path: &Path) -> Vec<String> {
+        let (a, b, c, d) = tokio::join!(
+            page.get_asset(path),
+            page.get_asset(path),
+            page.get_asset(path),
+            page.get_asset(path)
+        );
+        [a, b, c, d]
+            .into_iter()
+            .map(|v| v.unwrap().body().unwrap().to_string())
+            .collect()
+    }
+}
@@ -0,0 +1,224 @@
+/// A Layer that post-processes the content of every asset passed through it.
+///
+/// This allows embedders to minify, rewrite or otherwise alter assets (e.g. injecting nonces)
+/// without touching the providers or the frontend.
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+
+use log::debug;
+use mime_guess::Mime;
+use tokio::sync::broadcast;
+
+use crate::{
+    Asset, AssetError, AssetSource, DomainClaims, Page, PageError, PageLocation, PageSource,
+    PageSourceLayer, ScanStats, provider::memory::MemoryAsset,
+};
+
+/// Transforms the content of an asset.
+///
+/// Implemented for closures taking the same arguments, so simple transforms don't need a type.
+pub trait AssetTransform: Send + Sync {
+    /// Transforms the bytes of an asset, returning the bytes to serve instead.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` (`&Path`) - Where the asset is, relative to the page root.
+    /// - `mime` (`&Mime`) - The type of the asset, as guessed from its path.
+    /// - `bytes` (`Vec<u8>`) - The content of the asset.
+    fn transform(&self, path: &Path, mime: &Mime, bytes: Vec<u8>) -> Vec<u8>;
+}
+
+impl<F> AssetTransform for F
+where
+    F: Fn(&Path, &Mime, Vec<u8>) -> Vec<u8> + Send + Sync,
+{
+    fn transform(&self, path: &Path, mime: &Mime, bytes: Vec<u8>) -> Vec<u8> {
+        self(path, mime, bytes)
+    }
+}
+
+/// A Layer that passes every asset through a transform.
+#[derive(Clone)]
+pub struct TransformLayer {
+    transform: Arc<dyn AssetTransform>,
+}
+
+impl TransformLayer {
+    pub fn new(transform: impl AssetTransform + 'static) -> Self {
+        Self {
+            transform: Arc::new(transform),
+        }
+    }
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for TransformLayer {
+    type Source = TransformLayerSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        Self::Source {
+            upstream: page_source,
+            transform: self.transform.clone(),
+        }
+    }
+}
+
+pub struct TransformLayerSource<PS: PageSource> {
+    upstream: PS,
+    transform: Arc<dyn AssetTransform>,
+}
+
+impl<PS: PageSource> PageSource for TransformLayerSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        match self.upstream.page_at(owner, name, branch).await {
+            Ok(page) => Ok(TransformPage {
+                upstream: page,
+                transform: self.transform.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        match self.upstream.pages().await {
+            Ok(pages) => Ok(pages.map(|page| TransformPage {
+                upstream: page,
+                transform: self.transform.clone(),
+            })),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn default_branch(&self) -> &str {
+        self.upstream.default_branch()
+    }
+
+    fn subscribe_removed(&self) -> Option<broadcast::Receiver<PageLocation>> {
+        self.upstream.subscribe_removed()
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.upstream.retry_after()
+    }
+
+    async fn scan_stats(&self) -> Option<ScanStats> {
+        self.upstream.scan_stats().await
+    }
+
+    async fn domain_index(&self) -> Option<HashMap<String, DomainClaims>> {
+        self.upstream.domain_index().await
+    }
+
+    async fn owner_exists(&self, owner: &str) -> Result<bool, PageError> {
+        self.upstream.owner_exists(owner).await
+    }
+
+    async fn find_by_domains(&self, domains: Vec<String>) -> Result<impl Page, PageError> {
+        match self.upstream.find_by_domains(domains).await {
+            Ok(page) => Ok(TransformPage {
+                upstream: page,
+                transform: self.transform.clone(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+pub struct TransformPage<P: Page> {
+    upstream: P,
+    transform: Arc<dyn AssetTransform>,
+}
+
+impl<P: Page> Page for TransformPage<P> {
+    fn name(&self) -> &str {
+        self.upstream.name()
+    }
+
+    fn branch(&self) -> &str {
+        self.upstream.branch()
+    }
+
+    fn owner(&self) -> &str {
+        self.upstream.owner()
+    }
+
+    fn version(&self) -> &str {
+        self.upstream.version()
+    }
+}
+
+impl<P: Page> AssetSource for TransformPage<P> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+        let asset = self.upstream.get_asset(path).await?;
+        let provided = asset.mime_type().map(str::to_string);
+        let mime = provided
+            .as_deref()
+            .and_then(|v| v.parse::<Mime>().ok())
+            .unwrap_or_else(|| mime_guess::from_path(path).first_or_octet_stream());
+        debug!("Transforming asset {:?} ({})", path, mime);
+        let transformed =
+            MemoryAsset::from(self.transform.transform(path, &mime, asset.into_bytes()));
+        Ok(match provided {
+            Some(v) => transformed.with_mime_type(&v),
+            None => transformed,
+        })
+    }
+
+    fn total_bytes(&self) -> Option<u32> {
+        self.upstream.total_bytes()
+    }
+
+    async fn asset_exists(&self, path: &Path) -> Result<bool, AssetError> {
+        self.upstream.asset_exists(path).await
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        self.upstream.asset_mime_type(path).await
+    }
+
+    // The content hash isn't forwarded, since upstream's describes the untransformed content
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use mime_guess::Mime;
+
+    use crate::{
+        Asset, AssetSource, PageSource, PageSourceFactory,
+        provider::testing::create_example_provider_factory,
+    };
+
+    use super::TransformLayer;
+
+    /// Every asset should be passed through the transform, along with its type.
+    #[tokio::test]
+    async fn transform_assets() {
+        let layer = TransformLayer::new(|path: &Path, mime: &Mime, mut bytes: Vec<u8>| {
+            bytes.extend_from_slice(format!(" {:?} {}", path, mime).as_bytes());
+            bytes
+        });
+        let source = create_example_provider_factory().wrap(layer).build();
+
+        let page = source
+            .page_at(
+                "owner_1".to_string(),
+                "name_1".to_string(),
+                "pages".to_string(),
+            )
+            .await
+            .unwrap();
+
+        let asset = page.get_asset(Path::new("/asset_1")).await.unwrap();
+        assert_eq!(
+            asset.body().unwrap(),
+            "data_1 \"/asset_1\" application/octet-stream"
+        );
+
+        assert!(page.get_asset(Path::new("/missing.txt")).await.is_err());
+    }
+}
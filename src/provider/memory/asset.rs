@@ -14,17 +14,39 @@ use crate::{Asset, AssetError, AssetSource, AssetWritable};
 #[derive(Clone)]
 pub struct MemoryAsset {
     contents: Vec<u8>,
+    mime: Option<String>,
 }
 
 impl MemoryAsset {
     pub fn empty() -> Self {
-        Self { contents: vec![] }
+        Self {
+            contents: vec![],
+            mime: None,
+        }
+    }
+
+    /// Loads another asset into memory, keeping its MIME type.
+    pub fn from_asset(asset: impl Asset) -> Self {
+        let mime = asset.mime_type().map(str::to_string);
+        Self {
+            contents: asset.into_bytes(),
+            mime,
+        }
+    }
+
+    /// Sets the MIME type of the asset, rather than leaving it to be guessed from its path.
+    pub fn with_mime_type(mut self, mime: &str) -> Self {
+        self.mime = Some(mime.to_string());
+        self
     }
 }
 
 impl From<Vec<u8>> for MemoryAsset {
     fn from(value: Vec<u8>) -> Self {
-        Self { contents: value }
+        Self {
+            contents: value,
+            mime: None,
+        }
     }
 }
 
@@ -32,6 +54,7 @@ impl From<String> for MemoryAsset {
     fn from(value: String) -> Self {
         Self {
             contents: value.into_bytes(),
+            mime: None,
         }
     }
 }
@@ -40,11 +63,15 @@ impl From<&str> for MemoryAsset {
     fn from(value: &str) -> Self {
         Self {
             contents: value.to_string().into_bytes(),
+            mime: None,
         }
     }
 }
 
 impl Asset for MemoryAsset {
+    fn mime_type(&self) -> Option<&str> {
+        self.mime.as_deref()
+    }
     fn into_bytes(self) -> Vec<u8> {
         self.contents
     }
@@ -64,6 +91,9 @@ impl<'a, A: Asset> AssetRef<'a, A> {
 }
 
 impl<'a, A: Asset> Asset for AssetRef<'a, A> {
+    fn mime_type(&self) -> Option<&str> {
+        self.asset.mime_type()
+    }
     fn into_bytes(self) -> Vec<u8> {
         self.asset.bytes().to_vec()
     }
@@ -101,6 +131,15 @@ impl AssetSource for MemoryCache {
             None => Err(AssetError::NotFound),
         }
     }
+
+    fn total_bytes(&self) -> Option<u32> {
+        Some(self.data.values().map(|v| v.bytes().len() as u32).sum())
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        let buf = std::path::absolute(Path::new("/").join(path)).unwrap();
+        self.data.get(&buf)?.mime_type().map(str::to_string)
+    }
 }
 
 impl AssetWritable for MemoryCache {
@@ -117,6 +156,7 @@ impl AssetWritable for MemoryCache {
             path.to_path_buf(),
             MemoryAsset {
                 contents: asset.bytes().to_vec(),
+                mime: asset.mime_type().map(str::to_string),
             },
         );
 
@@ -156,4 +196,15 @@ mod tests {
 
         assert_eq!(asset.body().unwrap(), data)
     }
+
+    /// Is the MIME type kept when the asset is loaded from another one?
+    #[test]
+    fn memory_asset_mime() {
+        let asset = MemoryAsset::from("meow").with_mime_type("text/html");
+        assert_eq!(asset.mime_type(), Some("text/html"));
+
+        let loaded = MemoryAsset::from_asset(super::AssetRef::new(&asset));
+        assert_eq!(loaded.mime_type(), Some("text/html"));
+        assert_eq!(MemoryAsset::from("meow").mime_type(), None);
+    }
 }
@@ -6,7 +6,7 @@ mod asset;
 use std::{collections::HashMap, path::Path};
 
 use crate::{
-    {Asset, AssetError, AssetSource, AssetWritable},
+    DEFAULT_PAGE_BRANCH, {Asset, AssetError, AssetSource, AssetWritable},
     {Page, PageError, PageSource, PageSourceFactory},
 };
 pub use asset::{MemoryAsset, MemoryCache};
@@ -45,6 +45,14 @@ impl<'a> AssetSource for MemoryPage<'a> {
     async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
         self.data.get_asset(path).await
     }
+
+    fn total_bytes(&self) -> Option<u32> {
+        self.data.total_bytes()
+    }
+
+    async fn asset_mime_type(&self, path: &Path) -> Option<String> {
+        self.data.asset_mime_type(path).await
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -54,6 +62,8 @@ impl<'a> AssetSource for MemoryPage<'a> {
 #[derive(Clone)]
 pub struct MemoryPageProvider {
     pages: HashMap<(String, String, String), MemoryCache>,
+    default_branch: String,
+    lowercase_names: bool,
 }
 
 impl PageSource for MemoryPageProvider {
@@ -67,10 +77,18 @@ impl PageSource for MemoryPageProvider {
         let name = name.to_string();
         let channel = channel.to_string();
         let d = (owner.clone(), name.clone(), channel.clone());
-        match self.pages.get(&d) {
-            Some(v) => Ok(MemoryPage {
-                owner,
-                name,
+        let found = match self.lowercase_names {
+            true => self.pages.iter().find(|((o, n, b), _)| {
+                o.to_lowercase() == owner.to_lowercase()
+                    && n.to_lowercase() == name.to_lowercase()
+                    && *b == channel
+            }),
+            false => self.pages.get_key_value(&d),
+        };
+        match found {
+            Some(((owner, name, _), v)) => Ok(MemoryPage {
+                owner: owner.clone(),
+                name: name.clone(),
                 branch: channel,
                 data: v,
                 version: "".to_string(),
@@ -88,6 +106,10 @@ impl PageSource for MemoryPageProvider {
             data: f.1,
         }))
     }
+
+    fn default_branch(&self) -> &str {
+        &self.default_branch
+    }
 }
 
 #[derive(Clone)]
@@ -100,10 +122,24 @@ impl MemoryPageProviderFactory {
         Self {
             provider: MemoryPageProvider {
                 pages: HashMap::new(),
+                default_branch: DEFAULT_PAGE_BRANCH.to_string(),
+                lowercase_names: false,
             },
         }
     }
 
+    /// Sets the branch that's inferred when none is specified.
+    pub fn with_default_branch(mut self, branch: &str) -> Self {
+        self.provider.default_branch = branch.to_string();
+        self
+    }
+
+    /// Matches owner and repository names regardless of case, like forges that ignore it.
+    pub fn with_lowercase_names(mut self, lowercase_names: bool) -> Self {
+        self.provider.lowercase_names = lowercase_names;
+        self
+    }
+
     pub fn with_asset(
         mut self,
         owner: &str,
@@ -169,6 +205,92 @@ pub mod testing {
         test_example_source(&p).await;
     }
 
+    /// Asset hashes should follow the content, and be missing for missing assets.
+    #[tokio::test]
+    #[cfg(test)]
+    async fn asset_hash() {
+        let p = create_example_provider();
+        let page = p
+            .page_at(
+                OWNER_1.to_string(),
+                NAME_1.to_string(),
+                BRANCH_1.to_string(),
+            )
+            .await
+            .unwrap();
+
+        let hash = page.asset_hash(Path::new("/asset_1")).await.unwrap();
+        assert_eq!(hash, crate::content_hash(DATA_1.as_bytes()));
+        assert_ne!(hash, crate::content_hash(DATA_2.as_bytes()));
+        assert!(page.asset_hash(Path::new("/asset_2")).await.is_none());
+    }
+
+    /// The configured default branch should be used, including when resolving domains.
+    #[tokio::test]
+    #[cfg(test)]
+    async fn default_branch() {
+        use crate::provider::layers::single_flight::SingleFlightLayer;
+
+        let domain = MemoryAsset::from("example.domain");
+        let factory = MemoryPageProviderFactory::new()
+            .with_default_branch("main")
+            .with_asset(
+                OWNER_1,
+                NAME_1,
+                "pages",
+                Path::new("/.domain"),
+                domain.clone(),
+            )
+            .with_asset(OWNER_1, NAME_1, "main", Path::new("/.domain"), domain);
+        assert_eq!(
+            MemoryPageProviderFactory::new().build().default_branch(),
+            "pages"
+        );
+
+        let p = factory.wrap(SingleFlightLayer::new()).build();
+        assert_eq!(p.default_branch(), "main");
+        let page = p
+            .find_by_domains(vec!["example.domain".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(page.branch(), "main");
+    }
+
+    /// Names should only match regardless of case if enabled, and never for branches.
+    #[tokio::test]
+    #[cfg(test)]
+    async fn lowercase_names() {
+        let p = create_example_provider();
+        assert!(
+            p.page_at(
+                "Owner_1".to_string(),
+                NAME_1.to_string(),
+                BRANCH_1.to_string()
+            )
+            .await
+            .is_err()
+        );
+
+        let p = create_example_provider_factory()
+            .with_lowercase_names(true)
+            .build();
+        let page = p
+            .page_at(
+                "Owner_1".to_string(),
+                "NAME_1".to_string(),
+                BRANCH_1.to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.owner(), OWNER_1);
+        assert_eq!(page.name(), NAME_1);
+        assert!(
+            p.page_at(OWNER_1.to_string(), NAME_1.to_string(), "Pages".to_string())
+                .await
+                .is_err()
+        );
+    }
+
     const OWNER_1: &str = "owner_1";
     const OWNER_2: &str = "owner_2";
 
@@ -1,12 +1,15 @@
 pub mod cache;
 #[cfg(feature = "forgejo")]
 pub mod forgejo;
+#[cfg(feature = "git")]
+pub mod git;
 #[cfg(feature = "gitea")]
 pub mod gitea;
 #[cfg(feature = "gitlab")]
 pub mod gitlab;
 pub mod layers;
 pub mod memory;
+#[cfg(feature = "forgejo")]
 mod scanner;
 
 // Export specific types
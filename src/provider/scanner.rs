@@ -1,14 +1,557 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use tokio::sync::RwLock;
+use log::warn;
+use tokio::sync::{RwLock, broadcast};
 
-pub type RepoMap = HashMap<(String, String, String), ProviderScannedRepoData>;
+use crate::{DomainClaims, PageLocation, ScanStats, glob_matches};
 
+pub type RepoKey = (String, String, String);
+pub type RepoMap = HashMap<RepoKey, ProviderScannedRepoData>;
+/// Pages that were removed upstream, and when they were noticed to be gone.
+pub type RemovedMap = HashMap<RepoKey, Instant>;
+/// Custom domains, and the pages that declare each of them.
+pub type DomainMap = HashMap<String, DomainClaims>;
+
+/// How many removals may be waiting on a slow subscriber before it misses some.
+pub const REMOVED_EVENTS_CAPACITY: usize = 1024;
+
+/// Branches that are served as (ephemeral) preview pages.
+#[derive(Clone, Debug)]
+pub struct PreviewBranches {
+    /// Glob pattern that preview branch names match (e.g. `preview/*`).
+    pub pattern: String,
+    /// How long a preview branch may go without commits before it stops being served.
+    pub idle: Option<Duration>,
+}
+
+impl PreviewBranches {
+    pub fn matches(&self, branch: &str) -> bool {
+        glob_matches(&self.pattern, branch)
+    }
+
+    /// Whether or not a preview branch has been idle for too long to be served.
+    ///
+    /// # Arguments
+    ///
+    /// - `last_commit` (`Option<i64>`) - Unix timestamp of the branch's latest commit, if known.
+    /// - `now` (`i64`) - The current unix timestamp.
+    pub fn is_expired(&self, last_commit: Option<i64>, now: i64) -> bool {
+        match (self.idle, last_commit) {
+            (Some(idle), Some(last_commit)) => {
+                now.saturating_sub(last_commit) > idle.as_secs() as i64
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct ProviderScannerData {
     pub repos: Arc<RwLock<RepoMap>>,
+    /// Statistics about the latest completed scan, if there was one.
+    pub last_scan: Arc<RwLock<Option<ScanStats>>>,
+    pub removed: Arc<RwLock<RemovedMap>>,
+    /// Index of the custom domains declared by scanned pages.
+    pub domains: Arc<RwLock<DomainMap>>,
     pub target_branches: Vec<String>,
+    /// Preview branches to serve besides the target branches, if any.
+    pub preview: Option<PreviewBranches>,
+    /// The branch that's inferred when none is specified.
+    pub default_branch: String,
+    /// How long removed pages should be remembered for; None disables tracking.
+    pub gone_window: Option<Duration>,
+    /// Announces pages that disappeared between scans.
+    pub removed_events: broadcast::Sender<PageLocation>,
+}
+
+impl ProviderScannerData {
+    /// Whether or not pages may be served from a branch.
+    pub fn accepts_branch(&self, branch: &str) -> bool {
+        self.target_branches.iter().any(|v| glob_matches(v, branch))
+            || self.preview.as_ref().is_some_and(|v| v.matches(branch))
+    }
+
+    /// Whether or not a page was removed upstream within the configured window.
+    pub async fn is_gone(&self, key: &RepoKey) -> bool {
+        match self.gone_window {
+            Some(window) => self
+                .removed
+                .read()
+                .await
+                .get(key)
+                .is_some_and(|removed_at| removed_at.elapsed() < window),
+            None => false,
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct ProviderScannedRepoData {
     pub version: String,
 }
+
+/// The key a page is indexed by, with its owner and repository names lowercased if asked to
+/// (so lookups don't depend on how they're capitalized). Branches are always kept as they are.
+pub fn repo_key(owner: &str, name: &str, branch: &str, lowercase: bool) -> RepoKey {
+    match lowercase {
+        true => (
+            owner.to_lowercase(),
+            name.to_lowercase(),
+            branch.to_string(),
+        ),
+        false => (owner.to_string(), name.to_string(), branch.to_string()),
+    }
+}
+
+/// Inserts a scanned page, unless that would exceed the maximum number of pages.
+///
+/// Pages that are already present can always be updated.
+///
+/// # Returns
+///
+/// - `bool` - Whether or not the page was inserted.
+pub fn insert_capped(
+    repos: &mut RepoMap,
+    key: RepoKey,
+    data: ProviderScannedRepoData,
+    max_pages: Option<usize>,
+) -> bool {
+    if let Some(max) = max_pages
+        && repos.len() >= max
+        && !repos.contains_key(&key)
+    {
+        return false;
+    }
+    repos.insert(key, data);
+    true
+}
+
+/// Keeps pages of the previous scan that couldn't be looked up in the current one.
+///
+/// Only pages upstream says are missing should disappear; Failing to look them up (timeouts,
+/// server errors) would otherwise make them look removed until the next scan.
+///
+/// # Returns
+///
+/// - `usize` - How many of them were skipped because of the maximum number of pages.
+pub fn keep_previous(
+    repos: &mut RepoMap,
+    previous: &RepoMap,
+    keep: impl Fn(&RepoKey) -> bool,
+    max_pages: Option<usize>,
+) -> usize {
+    let mut skipped = 0;
+    for (key, data) in previous {
+        if !keep(key) || repos.contains_key(key) {
+            continue;
+        }
+        if !insert_capped(repos, key.clone(), data.clone(), max_pages) {
+            skipped += 1;
+        }
+    }
+    skipped
+}
+
+/// Whether or not a branch name is a glob pattern, rather than a literal name.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Announces every page that was in the previous scan, but not the current one.
+///
+/// # Returns
+///
+/// - `usize` - How many pages were removed.
+pub fn notify_removed(
+    previous: &[RepoKey],
+    current: &RepoMap,
+    events: &broadcast::Sender<PageLocation>,
+) -> usize {
+    let mut count = 0;
+    for (owner, name, branch) in previous.iter().filter(|v| !current.contains_key(*v)) {
+        count += 1;
+        // Nobody listening is fine; There's just nothing to clean up
+        let _ = events.send(PageLocation {
+            owner: owner.clone(),
+            name: name.clone(),
+            branch: branch.clone(),
+        });
+    }
+    count
+}
+
+/// Adds the domains a page declares to a domain index.
+///
+/// If several branches of a page declare a domain, the default branch takes precedence.
+/// If several pages declare it, that's a conflict; The page that comes first by owner and name
+/// keeps it, so which one serves it doesn't depend on the order pages were scanned in.
+///
+/// # Returns
+///
+/// - `usize` - How many of the domains another page had already claimed.
+pub fn index_domains<'a>(
+    index: &mut DomainMap,
+    page: &PageLocation,
+    domains: impl Iterator<Item = &'a str>,
+    default_branch: &str,
+) -> usize {
+    let mut conflicts = 0;
+    for domain in domains {
+        let Some(claims) = index.get_mut(domain) else {
+            index.insert(
+                domain.to_string(),
+                DomainClaims {
+                    serving: page.clone(),
+                    declared_by: vec![page.clone()],
+                },
+            );
+            continue;
+        };
+        claims.declared_by.push(page.clone());
+
+        let current = &claims.serving;
+        let replace =
+            domain_precedence(page, default_branch) < domain_precedence(current, default_branch);
+        if current.owner != page.owner || current.name != page.name {
+            conflicts += 1;
+            let (kept, ignored) = match replace {
+                true => (page, current),
+                false => (current, page),
+            };
+            warn!(
+                "Domain {} is declared by both {}/{} and {}/{}; Only {}/{} will serve it",
+                domain, kept.owner, kept.name, ignored.owner, ignored.name, kept.owner, kept.name
+            );
+        }
+        if replace {
+            claims.serving = page.clone();
+        }
+    }
+    conflicts
+}
+
+/// Orders the pages declaring the same domain; The lowest one serves it.
+fn domain_precedence<'a>(
+    page: &'a PageLocation,
+    default_branch: &str,
+) -> (&'a str, &'a str, bool, &'a str) {
+    (
+        &page.owner,
+        &page.name,
+        page.branch != default_branch,
+        &page.branch,
+    )
+}
+
+/// Updates the record of removed pages after a scan.
+///
+/// Pages that were in the previous scan but not the current one are recorded as removed,
+/// pages that came back are forgotten, and records older than the window expire.
+pub fn track_removed(
+    previous: impl Iterator<Item = RepoKey>,
+    current: &RepoMap,
+    removed: &mut RemovedMap,
+    window: Duration,
+    now: Instant,
+) {
+    for key in previous {
+        if !current.contains_key(&key) {
+            removed.insert(key, now);
+        }
+    }
+    removed.retain(|key, removed_at| {
+        !current.contains_key(key) && now.duration_since(*removed_at) < window
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use tokio::sync::{RwLock, broadcast};
+
+    use crate::PageLocation;
+
+    use super::{
+        DomainMap, PreviewBranches, ProviderScannedRepoData, ProviderScannerData, RemovedMap,
+        RepoKey, RepoMap, index_domains, insert_capped, is_glob, keep_previous, notify_removed,
+        repo_key, track_removed,
+    };
+
+    fn key(owner: &str) -> RepoKey {
+        (owner.to_string(), "pages".to_string(), "pages".to_string())
+    }
+
+    /// Removed pages should be remembered for the window, and forgotten if they return
+    #[test]
+    fn removed_tracking() {
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut removed = RemovedMap::new();
+
+        let mut current = RepoMap::new();
+        current.insert(
+            key("kept"),
+            ProviderScannedRepoData {
+                version: "".to_string(),
+            },
+        );
+
+        track_removed(
+            [key("kept"), key("deleted")].into_iter(),
+            &current,
+            &mut removed,
+            window,
+            start,
+        );
+        assert!(removed.contains_key(&key("deleted")));
+        assert!(!removed.contains_key(&key("kept")));
+
+        // Coming back means it's no longer gone
+        current.insert(
+            key("deleted"),
+            ProviderScannedRepoData {
+                version: "".to_string(),
+            },
+        );
+        track_removed(
+            [key("kept")].into_iter(),
+            &current,
+            &mut removed,
+            window,
+            start,
+        );
+        assert!(removed.is_empty());
+
+        // Records expire after the window
+        current.remove(&key("deleted"));
+        track_removed(
+            [key("kept"), key("deleted")].into_iter(),
+            &current,
+            &mut removed,
+            window,
+            start,
+        );
+        track_removed(
+            [key("kept")].into_iter(),
+            &current,
+            &mut removed,
+            window,
+            start + window,
+        );
+        assert!(removed.is_empty());
+    }
+
+    /// Preview branches without recent commits should expire
+    #[test]
+    fn preview_expiry() {
+        let preview = PreviewBranches {
+            pattern: "preview/*".to_string(),
+            idle: Some(Duration::from_secs(60)),
+        };
+        assert!(preview.matches("preview/feature"));
+        assert!(!preview.is_expired(Some(1000), 1030));
+        assert!(preview.is_expired(Some(1000), 1061));
+        // Without a known commit time, there's nothing to expire on
+        assert!(!preview.is_expired(None, 1061));
+
+        let forever = PreviewBranches {
+            idle: None,
+            ..preview
+        };
+        assert!(!forever.is_expired(Some(0), 1_000_000));
+    }
+
+    /// Target branches may be patterns, which should accept any matching branch
+    #[test]
+    fn accepts_branch() {
+        let mut data = ProviderScannerData {
+            repos: Arc::new(RwLock::new(RepoMap::new())),
+            last_scan: Arc::new(RwLock::new(None)),
+            removed: Arc::new(RwLock::new(RemovedMap::new())),
+            domains: Arc::new(RwLock::new(DomainMap::new())),
+            target_branches: vec!["pages".to_string(), "release/*".to_string()],
+            preview: None,
+            default_branch: "pages".to_string(),
+            gone_window: None,
+            removed_events: broadcast::channel(1).0,
+        };
+
+        assert!(!is_glob("pages"));
+        assert!(is_glob("release/*"));
+
+        assert!(data.accepts_branch("pages"));
+        assert!(data.accepts_branch("release/1.0"));
+        assert!(!data.accepts_branch("main"));
+        assert!(!data.accepts_branch("preview/feature"));
+
+        data.preview = Some(PreviewBranches {
+            pattern: "preview/*".to_string(),
+            idle: None,
+        });
+        assert!(data.accepts_branch("preview/feature"));
+    }
+
+    /// Pages past the cap should be skipped
+    #[test]
+    fn page_cap() {
+        let mut repos = RepoMap::new();
+        let data = || ProviderScannedRepoData {
+            version: "".to_string(),
+        };
+
+        assert!(insert_capped(&mut repos, key("a"), data(), Some(2)));
+        assert!(insert_capped(&mut repos, key("b"), data(), Some(2)));
+        assert!(!insert_capped(&mut repos, key("c"), data(), Some(2)));
+        assert!(insert_capped(&mut repos, key("a"), data(), Some(2)));
+        assert_eq!(repos.len(), 2);
+
+        assert!(insert_capped(&mut repos, key("c"), data(), None));
+        assert_eq!(repos.len(), 3);
+    }
+
+    /// Only pages missing from the new scan should be announced as removed
+    #[test]
+    fn removed_events() {
+        let (events, mut rx) = broadcast::channel(16);
+        let mut current = RepoMap::new();
+        current.insert(
+            key("kept"),
+            ProviderScannedRepoData {
+                version: "".to_string(),
+            },
+        );
+
+        assert_eq!(
+            notify_removed(&[key("kept"), key("deleted")], &current, &events),
+            1
+        );
+        let removed = rx.try_recv().unwrap();
+        assert_eq!(removed.owner, "deleted");
+        assert!(rx.try_recv().is_err());
+    }
+
+    /// Pages whose lookup failed temporarily should be kept, rather than announced as removed
+    #[test]
+    fn lookup_failure() {
+        let data = |version: &str| ProviderScannedRepoData {
+            version: version.to_string(),
+        };
+        let mut previous = RepoMap::new();
+        previous.insert(key("failed"), data("v1"));
+        previous.insert(key("deleted"), data("v1"));
+        previous.insert(key("updated"), data("v1"));
+
+        // The branch lookup of "failed" failed, "deleted" is missing upstream
+        let mut current = RepoMap::new();
+        current.insert(key("updated"), data("v2"));
+        let failed = key("failed");
+        assert_eq!(
+            keep_previous(&mut current, &previous, |v| *v == failed, None),
+            0
+        );
+        assert_eq!(current[&key("failed")].version, "v1");
+
+        let (events, mut rx) = broadcast::channel(16);
+        let keys: Vec<_> = previous.keys().cloned().collect();
+        assert_eq!(notify_removed(&keys, &current, &events), 1);
+        assert_eq!(rx.try_recv().unwrap().owner, "deleted");
+
+        let mut removed = RemovedMap::new();
+        track_removed(
+            keys.into_iter(),
+            &current,
+            &mut removed,
+            Duration::from_secs(60),
+            Instant::now(),
+        );
+        assert_eq!(removed.keys().collect::<Vec<_>>(), vec![&key("deleted")]);
+
+        // Newer versions from the current scan are never replaced
+        assert_eq!(keep_previous(&mut current, &previous, |_| true, None), 0);
+        assert_eq!(current[&key("updated")].version, "v2");
+
+        // Kept pages still count towards the cap
+        let mut current = RepoMap::new();
+        current.insert(key("updated"), data("v2"));
+        assert_eq!(keep_previous(&mut current, &previous, |_| true, Some(2)), 1);
+    }
+
+    /// Domains should point at the page declaring them, preferring its default branch
+    #[test]
+    fn domain_index() {
+        let page = |branch: &str| PageLocation {
+            owner: "owner".to_string(),
+            name: "name".to_string(),
+            branch: branch.to_string(),
+        };
+        let mut index = DomainMap::new();
+
+        index_domains(
+            &mut index,
+            &page("preview"),
+            ["a.domain", "b.domain"].into_iter(),
+            "pages",
+        );
+        index_domains(
+            &mut index,
+            &page("pages"),
+            ["a.domain"].into_iter(),
+            "pages",
+        );
+        index_domains(
+            &mut index,
+            &page("other"),
+            ["a.domain"].into_iter(),
+            "pages",
+        );
+
+        assert_eq!(index["a.domain"].serving.branch, "pages");
+        assert_eq!(index["a.domain"].declared_by.len(), 3);
+        assert_eq!(index["b.domain"].serving.branch, "preview");
+        assert!(!index.contains_key("c.domain"));
+    }
+
+    /// Domains declared by several pages should go to the same one regardless of scan order
+    #[test]
+    fn domain_conflicts() {
+        let page = |owner: &str| PageLocation {
+            owner: owner.to_string(),
+            name: "name".to_string(),
+            branch: "pages".to_string(),
+        };
+
+        for order in [["b", "a"], ["a", "b"]] {
+            let mut index = DomainMap::new();
+            let conflicts: usize = order
+                .iter()
+                .map(|v| index_domains(&mut index, &page(v), ["a.domain"].into_iter(), "pages"))
+                .sum();
+            assert_eq!(conflicts, 1);
+            assert_eq!(index["a.domain"].serving.owner, "a");
+        }
+    }
+
+    /// Only owner and repository names should be lowercased
+    #[test]
+    fn lowercase_keys() {
+        let key = |owner: &str, name: &str, branch: &str| -> RepoKey {
+            (owner.to_string(), name.to_string(), branch.to_string())
+        };
+        assert_eq!(
+            repo_key("Owner", "Site", "Preview/A", true),
+            key("owner", "site", "Preview/A")
+        );
+        assert_eq!(
+            repo_key("Owner", "Site", "Preview/A", false),
+            key("Owner", "Site", "Preview/A")
+        );
+    }
+}
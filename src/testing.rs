@@ -0,0 +1,76 @@
+//! Utilities for testing against Pageshelf, such as example pages and a configured service.
+
+pub use crate::provider::testing::*;
+
+#[cfg(feature = "server")]
+pub use app::test_app;
+
+#[cfg(feature = "server")]
+mod app {
+    use std::sync::Arc;
+
+    use actix_http::Request;
+    use actix_web::{
+        App, Error,
+        body::MessageBody,
+        dev::{Service, ServiceResponse},
+        middleware::{self, NormalizePath},
+        test,
+    };
+
+    use crate::{
+        PageSourceFactory,
+        conf::ServerConfig,
+        frontend::{cors, request_id, setup_service_config},
+    };
+
+    /// Creates an initialized Actix test service, serving the pages of a factory the way the
+    /// server binary would (with the same path normalization, CORS and request IDs).
+    ///
+    /// # Arguments
+    ///
+    /// - `config` (`&ServerConfig`) - The configuration to serve with.
+    /// - `factory` (`F`) - Builds the page source to serve from.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use actix_web::test;
+    /// use pageshelf::{
+    ///     conf::ServerConfig,
+    ///     testing::{create_example_provider_factory, test_app},
+    /// };
+    ///
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let app = test_app(&ServerConfig::default(), create_example_provider_factory()).await;
+    ///
+    /// let req = test::TestRequest::get()
+    ///     .uri("/owner_1/name_1/asset_1")
+    ///     .to_request();
+    /// let resp = test::call_service(&app, req).await;
+    /// assert_eq!(resp.status().as_u16(), 200);
+    /// # });
+    /// ```
+    pub async fn test_app<F>(
+        config: &ServerConfig,
+        factory: F,
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody + use<F>>, Error = Error>
+    + use<F>
+    where
+        F: PageSourceFactory + 'static,
+        F::Source: Sync + Send + 'static,
+    {
+        let config = config.clone();
+        test::init_service(
+            App::new()
+                .wrap(NormalizePath::trim())
+                .wrap(cors::middleware(&config.cors))
+                .wrap(middleware::from_fn(request_id::middleware))
+                .configure(move |f| {
+                    let provider = Arc::new(factory.build());
+                    setup_service_config(f, &config, provider, config.url_resolver(), None);
+                }),
+        )
+        .await
+    }
+}
@@ -1,10 +1,15 @@
-use std::{path::Path, sync::Arc};
+#![cfg(feature = "server")]
 
-use actix_web::{App, http::header::ContentType, middleware::NormalizePath, test};
+use std::path::Path;
+
+use actix_web::{
+    http::header::{self, ContentType},
+    test,
+};
 use pageshelf::{
     conf::ServerConfig,
-    frontend::setup_service_config,
-    provider::{memory::MemoryAsset, testing::create_example_provider_factory},
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
     {Asset, AssetSource}, {PageSource, PageSourceFactory},
 };
 
@@ -19,11 +24,7 @@ async fn page_server_404() {
 
     let config = ServerConfig::default();
 
-    let app = test::init_service(App::new().configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     let req = test::TestRequest::get()
         .uri("/")
@@ -76,11 +77,7 @@ async fn page_custom_404() {
             .is_ok()
     );
 
-    let app = test::init_service(App::new().wrap(NormalizePath::trim()).configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     let req = test::TestRequest::get()
         .uri("/owner_1/name_1")
@@ -118,3 +115,221 @@ async fn page_custom_404() {
     let body = test::read_body(resp).await;
     assert_eq!(body, asset_1.body().unwrap());
 }
+
+/// Verify that owners without a page get a "coming soon" page, if enabled
+#[tokio::test]
+async fn page_coming_soon() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        coming_soon: true,
+        ..ServerConfig::default()
+    };
+    let factory = create_example_provider_factory();
+
+    let app = test_app(&config, factory).await;
+
+    // owner_1 exists (they have name_1), but has no default page
+    let req = test::TestRequest::get()
+        .uri("/owner_1")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let body = test::read_body(resp).await;
+    assert!(String::from_utf8_lossy(&body).contains("Coming soon"));
+
+    // owner_3 doesn't exist at all
+    let req = test::TestRequest::get()
+        .uri("/owner_3")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let body = test::read_body(resp).await;
+    assert!(!String::from_utf8_lossy(&body).contains("Coming soon"));
+}
+
+/// Verify that the root asset is only used at the root of a page
+#[tokio::test]
+async fn page_root_asset() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        root_asset: Some("home.html".to_string()),
+        ..ServerConfig::default()
+    };
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/home.html"),
+            MemoryAsset::from("home"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/blog/home.html"),
+            MemoryAsset::from("blog home"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "home");
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/blog/")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+/// Verify that single-page apps serve their index for unknown routes, but not for missing files
+#[tokio::test]
+async fn page_spa_fallback() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.spa.marker = true;
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/index.html"),
+            MemoryAsset::from("app"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/.spa"),
+            MemoryAsset::from(""),
+        )
+        .with_asset(
+            "owner_2",
+            "name_2",
+            "pages",
+            Path::new("/index.html"),
+            MemoryAsset::from("not an app"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/some/route")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "app");
+
+    // Looks like a file, so it should still 404
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/missing.js")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    // No marker, so it isn't an app
+    let req = test::TestRequest::get()
+        .uri("/owner_2/name_2/some/route")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+/// Verify that custom 404s can be served as soft 404s
+#[tokio::test]
+async fn page_soft_404() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        soft_404: true,
+        ..ServerConfig::default()
+    };
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/404.html"),
+        MemoryAsset::from("meow"),
+    );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/missing.html")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    // Not a validator of the missing path, so it can't be revalidated as it
+    assert!(resp.headers().get(header::ETAG).is_none());
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "meow");
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/missing.html")
+        .insert_header((header::IF_NONE_MATCH, "*"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    // Without a custom 404, it's still a real 404
+    let req = test::TestRequest::get()
+        .uri("/owner_2/name_2/missing.html")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+/// Verify that the server's 404 page names the site when the page has no custom 404
+#[tokio::test]
+async fn page_fallback_404_context() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory();
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/missing.html")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let body = test::read_body(resp).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains("owner_1/name_1"));
+}
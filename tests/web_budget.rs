@@ -0,0 +1,80 @@
+#![cfg(feature = "server")]
+
+use std::time::Duration;
+
+use actix_web::test;
+use pageshelf::{
+    Page, PageError, PageSource, PageSourceFactory, PageSourceLayer,
+    conf::ServerConfig,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Takes a while to look up pages of `owner_1`, like an upstream that's slow to respond.
+#[derive(Clone)]
+struct SlowLayer;
+
+struct SlowSource<PS: PageSource> {
+    upstream: PS,
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for SlowLayer {
+    type Source = SlowSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        SlowSource {
+            upstream: page_source,
+        }
+    }
+}
+
+impl<PS: PageSource> PageSource for SlowSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        if owner == "owner_1" {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        self.upstream.page_at(owner, name, branch).await
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        self.upstream.pages().await
+    }
+}
+
+/// Ensure requests give up once upstream used up their budget
+#[tokio::test]
+async fn page_request_budget() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.upstream.request_budget = Some(100);
+    let factory = create_example_provider_factory().wrap(SlowLayer);
+    let app = test_app(&config, factory.clone()).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 504);
+
+    let req = test::TestRequest::get()
+        .uri("/owner_2/name_2/asset_2")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    // Without a budget, slow requests are waited for
+    let app = test_app(&ServerConfig::default(), factory).await;
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure validators follow the asset that's actually served, including directory indexes
+#[tokio::test]
+async fn page_conditional_index() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/blog/index.html"),
+            MemoryAsset::from("blog"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/blog.html"),
+            MemoryAsset::from("not the blog"),
+        );
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/blog")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let etag = resp
+        .headers()
+        .get(header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    // The directory and its index are the same asset
+    for uri in ["/owner_1/name_1/blog", "/owner_1/name_1/blog/index.html"] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header((header::IF_NONE_MATCH, etag.as_str()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 304, "Requesting {}", uri);
+        let body = test::read_body(resp).await;
+        assert!(body.is_empty());
+    }
+
+    // A different file doesn't share the validator
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/blog.html")
+        .insert_header((header::IF_NONE_MATCH, format!("W/{}", etag)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "not the blog");
+}
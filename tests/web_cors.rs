@@ -0,0 +1,91 @@
+#![cfg(feature = "server")]
+
+use actix_web::{
+    http::{Method, header},
+    test,
+};
+use pageshelf::{
+    conf::ServerConfig,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure CORS headers are only sent (and preflights answered) when enabled
+#[tokio::test]
+async fn page_cors() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.cors.allowed_origins = vec!["https://app.domain".to_string()];
+    config.cors.allowed_headers = vec!["Content-Type".to_string()];
+    config.cors.max_age = Some(3600);
+
+    // Disabled by default
+    let app = test_app(&config, create_example_provider_factory()).await;
+    let req = test::TestRequest::default()
+        .method(Method::OPTIONS)
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header((header::ORIGIN, "https://app.domain"))
+        .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 405);
+    assert!(
+        resp.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none()
+    );
+
+    config.cors.enabled = true;
+    let app = test_app(&config, create_example_provider_factory()).await;
+
+    // Preflight
+    let req = test::TestRequest::default()
+        .method(Method::OPTIONS)
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header((header::ORIGIN, "https://app.domain"))
+        .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+        .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    assert_eq!(
+        resp.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .unwrap(),
+        "https://app.domain"
+    );
+    assert_eq!(
+        resp.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(),
+        "3600"
+    );
+
+    // Simple request from an allowed origin
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header((header::ORIGIN, "https://app.domain"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        resp.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .unwrap(),
+        "https://app.domain"
+    );
+
+    // Other origins are still served, just without CORS headers
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header((header::ORIGIN, "https://other.domain"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(
+        resp.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none()
+    );
+}
@@ -0,0 +1,102 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::{http::header::ContentType, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Verify that sensitive files are denied by default, while everything else is served
+#[tokio::test]
+async fn page_deny_defaults() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let mut factory = create_example_provider_factory();
+    for path in [
+        "/.domain",
+        "/.env",
+        "/.git/config",
+        "/.git/refs/heads/pages",
+        "/docs/.env",
+        "/sub/.git/config",
+    ] {
+        factory = factory.with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new(path),
+            MemoryAsset::from("secret"),
+        );
+    }
+
+    let app = test_app(&config, factory).await;
+
+    for uri in [
+        "/owner_1/name_1/.domain",
+        "/owner_1/name_1/.env",
+        "/owner_1/name_1/.git/config",
+        "/owner_1/name_1/.git/refs/heads/pages",
+        "/owner_1/name_1/docs/.env",
+        "/owner_1/name_1/sub/.git/config",
+    ] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 404, "Requesting {}", uri);
+        let body = test::read_body(resp).await;
+        assert_ne!(body, "secret", "Requesting {}", uri);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+/// Verify that the denied paths can be configured
+#[tokio::test]
+async fn page_deny_custom() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        deny_paths: vec!["*_1".to_string()],
+        ..Default::default()
+    };
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/.env"),
+        MemoryAsset::from("NOT_SECRET=1"),
+    );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/.env")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
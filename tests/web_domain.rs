@@ -1,11 +1,13 @@
-use std::{path::Path, str::FromStr, sync::Arc};
+#![cfg(feature = "server")]
 
-use actix_web::{App, http::header::ContentType, test};
+use std::{path::Path, str::FromStr};
+
+use actix_web::{http::header::ContentType, test};
 use pageshelf::{
-    Asset, PageSourceFactory,
+    Asset,
     conf::ServerConfig,
-    frontend::setup_service_config,
-    provider::{memory::MemoryAsset, testing::create_example_provider_factory},
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
 };
 use url::Url;
 
@@ -61,11 +63,7 @@ async fn exec_domain_custom(config: &ServerConfig) {
             asset_index.clone(),
         );
 
-    let app = test::init_service(App::new().configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(config, factory).await;
 
     // Owner 1 has a domain page, should succeed
     let req = test::TestRequest::get()
@@ -136,3 +134,158 @@ async fn exec_domain_custom(config: &ServerConfig) {
     let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status().as_u16(), 404);
 }
+
+/// Verify that the domain file isn't served directly, while domain resolution still uses it
+#[tokio::test]
+async fn page_domain_control_file() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        allow_domains: true,
+        pages_urls: Some(vec![Url::from_str("https://example.domain").unwrap()]),
+        // Only the control file list should hide the domain file here
+        deny_paths: Vec::new(),
+        ..ServerConfig::default()
+    };
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "pages",
+            "pages",
+            Path::new("/.domain"),
+            MemoryAsset::from("example_custom.domain"),
+        )
+        .with_asset(
+            "owner_1",
+            "pages",
+            "pages",
+            Path::new("/index.html"),
+            MemoryAsset::from("meow"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("Host", "example_custom.domain"))
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "meow");
+
+    for (host, uri) in [
+        ("example_custom.domain", "/.domain"),
+        ("owner_1.example.domain", "/.domain"),
+        ("example.domain", "/owner_1/pages/.domain"),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Host", host))
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 404, "Requesting {}{}", host, uri);
+    }
+}
+
+/// Verify a domain declared on the default branch can serve other branches as subdomains
+#[tokio::test]
+async fn page_domain_branches() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        allow_domains: true,
+        domain_branches: true,
+        pages_urls: Some(vec![Url::from_str("https://example.domain").unwrap()]),
+        ..ServerConfig::default()
+    };
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "pages",
+            "pages",
+            Path::new("/.domain"),
+            MemoryAsset::from("example_custom.domain"),
+        )
+        .with_asset(
+            "owner_1",
+            "pages",
+            "pages",
+            Path::new("/index.html"),
+            MemoryAsset::from("default"),
+        )
+        .with_asset(
+            "owner_1",
+            "pages",
+            "preview",
+            Path::new("/index.html"),
+            MemoryAsset::from("preview"),
+        )
+        // Also declares the domain, but the default branch takes precedence
+        .with_asset(
+            "owner_1",
+            "pages",
+            "old",
+            Path::new("/.domain"),
+            MemoryAsset::from("example_custom.domain"),
+        )
+        .with_asset(
+            "owner_1",
+            "pages",
+            "old",
+            Path::new("/index.html"),
+            MemoryAsset::from("old"),
+        )
+        // Declared directly, so it's not read as the "blog" branch
+        .with_asset(
+            "owner_2",
+            "blog",
+            "pages",
+            Path::new("/.domain"),
+            MemoryAsset::from("blog.example_custom.domain"),
+        )
+        .with_asset(
+            "owner_2",
+            "blog",
+            "pages",
+            Path::new("/index.html"),
+            MemoryAsset::from("blog"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    for (host, expected) in [
+        ("example_custom.domain", "default"),
+        ("preview.example_custom.domain", "preview"),
+        ("blog.example_custom.domain", "blog"),
+    ] {
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header(("Host", host))
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "Requesting {}", host);
+        let body = test::read_body(resp).await;
+        assert_eq!(body, expected, "Requesting {}", host);
+    }
+
+    // Branches that don't exist aren't found
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("Host", "missing.example_custom.domain"))
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "server")]
+
+use std::{path::Path, str::FromStr};
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    frontend::routes::domains::{DOMAINS_PATH, DomainPage, Domains},
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+use url::Url;
+
+/// Ensure domains are only listed with the admin token, along with who declares them
+#[tokio::test]
+async fn page_domains() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/.domain"),
+            MemoryAsset::from("custom.domain\nshared.domain\n"),
+        )
+        .with_asset(
+            "owner_2",
+            "name_2",
+            "pages",
+            Path::new("/.domain"),
+            MemoryAsset::from("shared.domain"),
+        );
+
+    // Disabled without a token
+    let app = test_app(&ServerConfig::default(), factory.clone()).await;
+    let req = test::TestRequest::get().uri(DOMAINS_PATH).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let config = ServerConfig {
+        admin_token: Some("meow".to_string()),
+        url: Some(Url::from_str("http://example.domain").unwrap()),
+        pages_urls: Some(vec![Url::from_str("http://pages.example.domain").unwrap()]),
+        allow_domains: true,
+        ..ServerConfig::default()
+    };
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get().uri(DOMAINS_PATH).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 401);
+
+    let req = test::TestRequest::get()
+        .uri(DOMAINS_PATH)
+        .insert_header((header::AUTHORIZATION, "Bearer meow"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let domains: Domains = test::read_body_json(resp).await;
+    assert_eq!(domains.home_domain.as_deref(), Some("example.domain"));
+    assert_eq!(domains.page_domains, vec!["pages.example.domain"]);
+    assert!(domains.custom_domains_enabled);
+
+    let page = |owner: &str, repo: &str| DomainPage {
+        owner: owner.to_string(),
+        repo: repo.to_string(),
+        branch: "pages".to_string(),
+    };
+    let custom = &domains.custom_domains["custom.domain"];
+    assert_eq!(custom.serving, Some(page("owner_1", "name_1")));
+    assert_eq!(custom.declared_by, vec![page("owner_1", "name_1")]);
+
+    // Conflicts list every page, but only one of them serves the domain
+    let shared = &domains.custom_domains["shared.domain"];
+    assert_eq!(shared.declared_by.len(), 2);
+    assert!(
+        shared
+            .declared_by
+            .contains(shared.serving.as_ref().unwrap())
+    );
+}
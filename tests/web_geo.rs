@@ -0,0 +1,62 @@
+#![cfg(feature = "geo")]
+
+use std::path::Path;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure pages serve the variant their config picks for the client's country, if there is one
+#[tokio::test]
+async fn page_country_variant() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig {
+        geo_header: Some("CF-IPCountry".to_string()),
+        ..ServerConfig::default()
+    };
+    config.page_config.enabled = true;
+    let asset =
+        |path: &'static str, content: &'static str| (Path::new(path), MemoryAsset::from(content));
+    let mut factory = create_example_provider_factory();
+    for (path, content) in [
+        asset(
+            "/.pageshelf.toml",
+            "[countries]\nDE = \"de\"\nAT = \"de\"\nFR = \"fr\"",
+        ),
+        asset("/index.html", "hello"),
+        asset("/index.de.html", "hallo"),
+    ] {
+        factory = factory.with_asset("owner_1", "name_1", "pages", path, content);
+    }
+    let app = test_app(&config, factory).await;
+
+    for (country, expected) in [
+        (Some("DE"), "hallo"),
+        (Some("at"), "hallo"),
+        // No variant for this country, so the asset itself is served
+        (Some("FR"), "hello"),
+        (Some("US"), "hello"),
+        (None, "hello"),
+    ] {
+        let mut req = test::TestRequest::get().uri("/owner_1/name_1/");
+        if let Some(country) = country {
+            req = req.insert_header(("CF-IPCountry", country));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(
+            resp.headers()
+                .get_all(header::VARY)
+                .any(|v| v == "CF-IPCountry")
+        );
+        let body = test::read_body(resp).await;
+        assert_eq!(body, expected, "Country {:?}", country);
+    }
+}
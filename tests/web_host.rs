@@ -0,0 +1,282 @@
+#![cfg(feature = "server")]
+
+use std::{path::Path, str::FromStr};
+
+use actix_web::{http::header::ContentType, test};
+use pageshelf::{
+    Asset,
+    conf::{ServerConfig, ServerConfigMissingHost},
+    frontend::routes::server::request_to_url,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+use url::Url;
+
+/// Ensure requests without a Host header are handled as configured
+#[tokio::test]
+async fn page_missing_host() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let path_index = Path::new("/index.html");
+    let asset_default = MemoryAsset::from("default");
+    let asset_owner = MemoryAsset::from("owner");
+
+    let factory = create_example_provider_factory()
+        .with_asset("user", "pages", "pages", path_index, asset_default.clone())
+        .with_asset("owner_1", "pages", "pages", path_index, asset_owner.clone());
+
+    let base = ServerConfig {
+        default_user: "user".to_string(),
+        url: Some(Url::from_str("https://example.domain").unwrap()),
+        pages_urls: Some(vec![Url::from_str("https://pages.domain").unwrap()]),
+        ..ServerConfig::default()
+    };
+
+    let params = [
+        (ServerConfigMissingHost::Root, 200, Some(&asset_owner)),
+        (
+            ServerConfigMissingHost::DefaultUser,
+            200,
+            Some(&asset_default),
+        ),
+        (ServerConfigMissingHost::Reject, 400, None),
+    ];
+
+    for (missing_host, status, asset) in params {
+        let config = ServerConfig {
+            missing_host: missing_host.clone(),
+            ..base.clone()
+        };
+        let factory = factory.clone();
+        let app = test_app(&config, factory).await;
+
+        // Test requests have no Host header unless one is inserted
+        let req = test::TestRequest::get()
+            .uri(match missing_host {
+                ServerConfigMissingHost::Root => "/owner_1/pages/index.html",
+                _ => "/index.html",
+            })
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), status, "{:?}", missing_host);
+        if let Some(asset) = asset {
+            let body = test::read_body(resp).await;
+            assert_eq!(body, asset.body().unwrap(), "{:?}", missing_host);
+        }
+    }
+
+    // The default user's page must refuse unsafe paths like any other
+    let config = ServerConfig {
+        missing_host: ServerConfigMissingHost::DefaultUser,
+        ..base
+    };
+    let factory = factory.with_asset(
+        "user",
+        "pages",
+        "pages",
+        Path::new("/sub/index.html"),
+        asset_default,
+    );
+    let app = test_app(&config, factory).await;
+    for uri in ["/sub%2Findex.html", "/sub/..%2Findex.html"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 404, "{}", uri);
+    }
+}
+
+/// Ensure forwarded hosts are only used when they come from a trusted proxy
+#[tokio::test]
+async fn page_forwarded_host() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let path_index = Path::new("/index.html");
+    let asset_index = MemoryAsset::from("meow");
+
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "pages",
+        "pages",
+        path_index,
+        asset_index.clone(),
+    );
+
+    let config = ServerConfig {
+        pages_urls: Some(vec![Url::from_str("https://example.domain").unwrap()]),
+        trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+        ..ServerConfig::default()
+    };
+
+    let app = test_app(&config, factory).await;
+
+    // From the trusted proxy, the forwarded host is used
+    let req = test::TestRequest::get()
+        .uri("/")
+        .peer_addr("10.0.0.1:4000".parse().unwrap())
+        .insert_header(("Host", "internal.host"))
+        .insert_header(("X-Forwarded-Host", "owner_1.example.domain"))
+        .insert_header(("X-Forwarded-Proto", "https"))
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, asset_index.body().unwrap());
+
+    // From anyone else, it must be ignored
+    let req = test::TestRequest::get()
+        .uri("/")
+        .peer_addr("10.0.0.2:4000".parse().unwrap())
+        .insert_header(("Host", "internal.host"))
+        .insert_header(("X-Forwarded-Host", "owner_1.example.domain"))
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body = test::read_body(resp).await;
+    assert_ne!(body, asset_index.body().unwrap());
+}
+
+/// Ensure request URLs are composed from the Host header and the full request target
+#[tokio::test]
+async fn request_url_composition() {
+    let req = test::TestRequest::get()
+        .uri("/my/long/path/index.html?query=nya")
+        .insert_header(("Host", "other_thing.owner_2.example.domain"))
+        .to_http_request();
+
+    assert_eq!(
+        request_to_url(&req, None).unwrap().as_str(),
+        "http://other_thing.owner_2.example.domain/my/long/path/index.html?query=nya"
+    );
+    assert_eq!(
+        request_to_url(&req, Some("https")).unwrap().as_str(),
+        "https://other_thing.owner_2.example.domain/my/long/path/index.html?query=nya"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("Host", "not a host"))
+        .to_http_request();
+    assert!(request_to_url(&req, None).is_err());
+}
+
+/// Ensure strict hosts only serve configured domains and domains claimed by pages
+#[tokio::test]
+async fn page_strict_host() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let path_index = Path::new("/index.html");
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "pages",
+            "pages",
+            path_index,
+            MemoryAsset::from("meow"),
+        )
+        .with_asset(
+            "owner_1",
+            "pages",
+            "pages",
+            Path::new("/.domain"),
+            MemoryAsset::from("custom.domain"),
+        );
+
+    for allow_domains in [false, true] {
+        let config = ServerConfig {
+            url: Some(Url::from_str("https://example.domain").unwrap()),
+            pages_urls: Some(vec![Url::from_str("https://pages.domain").unwrap()]),
+            builtin_hosts: vec!["pageshelf.internal".to_string()],
+            strict_host: true,
+            allow_domains,
+            ..ServerConfig::default()
+        };
+        let app = test_app(&config, factory.clone()).await;
+
+        let params = [
+            ("example.domain", 200),
+            ("owner_1.pages.domain", 200),
+            ("pageshelf.internal", 200),
+            ("custom.domain", if allow_domains { 200 } else { 421 }),
+            ("other.domain", 421),
+            ("127.0.0.1", 421),
+        ];
+        for (host, status) in params {
+            let req = test::TestRequest::get()
+                .uri("/")
+                .insert_header(("Host", host))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(
+                resp.status().as_u16(),
+                status,
+                "Requesting {} with allow_domains = {}",
+                host,
+                allow_domains
+            );
+        }
+    }
+}
+
+/// Ensure custom domains that no page claims are served the configured fallback page
+#[tokio::test]
+async fn page_domain_fallback() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/.domain"),
+        MemoryAsset::from("custom.domain"),
+    );
+
+    for domain_fallback in [None, Some("owner_2/name_2:pages".to_string())] {
+        let config = ServerConfig {
+            url: Some(Url::from_str("https://example.domain").unwrap()),
+            allow_domains: true,
+            domain_fallback: domain_fallback.clone(),
+            ..ServerConfig::default()
+        };
+        let app = test_app(&config, factory.clone()).await;
+
+        let params = [
+            ("custom.domain", "/asset_1", 200),
+            ("custom.domain", "/asset_2", 404),
+            (
+                "other.domain",
+                "/asset_2",
+                domain_fallback.as_ref().map_or(404, |_| 200),
+            ),
+        ];
+        for (host, uri, status) in params {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("Host", host))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(
+                resp.status().as_u16(),
+                status,
+                "Requesting {}{} with fallback {:?}",
+                host,
+                uri,
+                domain_fallback
+            );
+        }
+    }
+}
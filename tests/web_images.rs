@@ -0,0 +1,88 @@
+#![cfg(feature = "images")]
+
+use std::{io::Cursor, path::Path};
+
+use actix_web::{http::header, test};
+use image::{ImageFormat, RgbImage};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure PNGs are only served as WebP to clients that accept it, and vary by Accept
+#[tokio::test]
+async fn page_image_webp() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.images.webp = true;
+
+    let mut png = Cursor::new(Vec::new());
+    RgbImage::from_pixel(64, 64, image::Rgb([255, 128, 0]))
+        .write_to(&mut png, ImageFormat::Png)
+        .unwrap();
+    let png = png.into_inner();
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/cat.png"),
+            MemoryAsset::from(png.clone()),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/cat.webp"),
+            MemoryAsset::from("already webp"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/cat.png")
+        .insert_header((header::ACCEPT, "image/avif,image/webp,*/*;q=0.8"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "image/webp"
+    );
+    assert_eq!(resp.headers().get(header::VARY).unwrap(), "Accept");
+    let body = test::read_body(resp).await;
+    assert_eq!(
+        image::guess_format(&body).unwrap(),
+        ImageFormat::WebP,
+        "The body should be WebP"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/cat.png")
+        .insert_header((header::ACCEPT, "image/*,*/*;q=0.8"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "image/png"
+    );
+    assert_eq!(resp.headers().get(header::VARY).unwrap(), "Accept");
+    let body = test::read_body(resp).await;
+    assert_eq!(body, png);
+
+    // Modern formats are left alone
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/cat.webp")
+        .insert_header((header::ACCEPT, "image/webp"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get(header::VARY).is_none());
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "already webp");
+}
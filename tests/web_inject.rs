@@ -0,0 +1,170 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::{http::header::ContentType, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure the HTML snippet is only injected into HTML assets
+#[tokio::test]
+async fn page_inject_html_snippet() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        html_snippet: Some("<script>nya</script>".to_string()),
+        ..ServerConfig::default()
+    };
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/index.html"),
+            MemoryAsset::from("<html><BODY>meow</BODY></html>"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/partial.html"),
+            MemoryAsset::from("meow"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/style.css"),
+            MemoryAsset::from("body {}</body>"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/index.html")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "<html><BODY>meow<script>nya</script></BODY></html>");
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/partial.html")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "meow<script>nya</script>");
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/style.css")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "body {}</body>");
+}
+
+/// Ensure root-relative links are rewritten for pages served under a path, and only then
+#[tokio::test]
+async fn page_rewrite_path_links() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        rewrite_path_links: true,
+        url: Some(url::Url::parse("https://example.domain").unwrap()),
+        pages_urls: Some(vec![url::Url::parse("https://example.domain").unwrap()]),
+        ..ServerConfig::default()
+    };
+
+    let html = "<a href=\"/about.html\"><img SRC='/cat.png'><a href=\"//cdn.domain/x\">\
+                <a href=\"rel.html\"><div data-x=\"/kept\">";
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/index.html"),
+        MemoryAsset::from(html),
+    );
+
+    let app = test_app(&config, factory).await;
+
+    for uri in ["/owner_1/name_1/index.html", "/owner_1/name_1"] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Host", "example.domain"))
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200, "Requesting {}", uri);
+        let body = test::read_body(resp).await;
+        assert_eq!(
+            body,
+            "<a href=\"/owner_1/name_1/about.html\"><img SRC='/owner_1/name_1/cat.png'>\
+             <a href=\"//cdn.domain/x\"><a href=\"rel.html\"><div data-x=\"/kept\">",
+            "Requesting {}",
+            uri
+        );
+    }
+
+    // Subdomains serve the page at the root, so links already work
+    let req = test::TestRequest::get()
+        .uri("/index.html")
+        .insert_header(("Host", "name_1.owner_1.example.domain"))
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, html);
+}
+
+/// Ensure assets are served with `nosniff` by default, and without it if disabled
+#[tokio::test]
+async fn page_nosniff_header() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    for nosniff in [true, false] {
+        let config = ServerConfig {
+            nosniff,
+            ..ServerConfig::default()
+        };
+
+        let factory = create_example_provider_factory().with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/upload.txt"),
+            MemoryAsset::from("<script>nya</script>"),
+        );
+
+        let app = test_app(&config, factory).await;
+
+        let req = test::TestRequest::get()
+            .uri("/owner_1/name_1/upload.txt")
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            resp.headers()
+                .get("X-Content-Type-Options")
+                .map(|v| v.to_str().unwrap()),
+            nosniff.then_some("nosniff")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure pages can set their index files, SPA mode and cache hints, within the server's bounds
+#[tokio::test]
+async fn page_config_file() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.page_config.enabled = true;
+    config.page_config.max_age = 600;
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/.pageshelf.toml"),
+            MemoryAsset::from("index = [\"home.html\"]\nspa = true\nmax_age = 3600"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/home.html"),
+            MemoryAsset::from("home"),
+        )
+        .with_asset(
+            "owner_2",
+            "name_2",
+            "pages",
+            Path::new("/home.html"),
+            MemoryAsset::from("home"),
+        );
+    let app = test_app(&config, factory).await;
+
+    for uri in ["/owner_1/name_1", "/owner_1/name_1/some/route"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200, "Requesting {}", uri);
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=600"
+        );
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "home");
+    }
+
+    // The config file itself isn't served
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/.pageshelf.toml")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    // Pages without one are served as usual
+    for uri in ["/owner_2/name_2", "/owner_2/name_2/some/route"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 404, "Requesting {}", uri);
+    }
+    let req = test::TestRequest::get()
+        .uri("/owner_2/name_2/asset_2")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get(header::CACHE_CONTROL).is_none());
+}
+
+/// Ensure pages can limit which of their branches are served
+#[tokio::test]
+async fn page_config_branches() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.page_config.enabled = true;
+    let mut factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/.pageshelf.toml"),
+        MemoryAsset::from("branches = [\"preview-*\"]"),
+    );
+    for (owner, name) in [("owner_1", "name_1"), ("owner_2", "name_2")] {
+        for branch in ["preview-1", "secret"] {
+            factory = factory.with_asset(
+                owner,
+                name,
+                branch,
+                Path::new("/index.html"),
+                MemoryAsset::from(branch),
+            );
+        }
+    }
+    let app = test_app(&config, factory.clone()).await;
+
+    for (uri, status) in [
+        ("/owner_1/name_1/asset_1", 200),
+        ("/owner_1/name_1:preview-1", 200),
+        ("/owner_1/name_1:secret", 404),
+        ("/owner_1/name_1:secret/index.html", 404),
+        // Pages without an allowlist serve every branch
+        ("/owner_2/name_2:secret", 200),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), status, "Requesting {}", uri);
+    }
+
+    // Only enforced if page configs are
+    config.page_config.enabled = false;
+    let app = test_app(&config, factory).await;
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1:secret")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
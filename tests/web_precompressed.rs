@@ -0,0 +1,155 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure precompressed variants are served in the encoding the client prefers most
+#[tokio::test]
+async fn page_precompressed() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        precompressed: true,
+        ..ServerConfig::default()
+    };
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/app.js"),
+            MemoryAsset::from("plain"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/app.js.zst"),
+            MemoryAsset::from("zstd"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/app.js.gz"),
+            MemoryAsset::from("gzip"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    for (accept_encoding, expected, encoding) in [
+        (Some("gzip, deflate, br, zstd"), "zstd", Some("zstd")),
+        (Some("zstd;q=0.5, gzip"), "gzip", Some("gzip")),
+        (Some("br"), "plain", None),
+        (Some("zstd;q=0"), "plain", None),
+        (None, "plain", None),
+    ] {
+        let mut req = test::TestRequest::get().uri("/owner_1/name_1/app.js");
+        if let Some(v) = accept_encoding {
+            req = req.insert_header((header::ACCEPT_ENCODING, v));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            resp.headers()
+                .get(header::CONTENT_ENCODING)
+                .map(|v| v.to_str().unwrap()),
+            encoding
+        );
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/javascript"
+        );
+        assert!(
+            resp.headers()
+                .get_all(header::VARY)
+                .any(|v| v == "Accept-Encoding")
+        );
+        assert_eq!(test::read_body(resp).await, expected);
+    }
+}
+
+/// Ensure precompressed variants aren't served unless enabled
+#[tokio::test]
+async fn page_precompressed_disabled() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/asset_1.zst"),
+        MemoryAsset::from("zstd"),
+    );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header((header::ACCEPT_ENCODING, "zstd"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(test::read_body(resp).await, "data_1");
+}
+
+/// Ensure precompressed variants are served as the type the provider gives the asset
+#[tokio::test]
+async fn page_precompressed_mime_type() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        precompressed: true,
+        ..ServerConfig::default()
+    };
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/data"),
+            MemoryAsset::from("{}").with_mime_type("application/json"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/data.gz"),
+            MemoryAsset::from("gzip"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/data")
+        .insert_header((header::ACCEPT_ENCODING, "gzip"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_ENCODING).unwrap(),
+        "gzip"
+    );
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/json"
+    );
+    assert_eq!(test::read_body(resp).await, "gzip");
+}
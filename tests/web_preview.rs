@@ -0,0 +1,81 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure the branch header only picks a branch along with the preview token
+#[tokio::test]
+async fn page_preview_header() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let path = Path::new("/index.html");
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            path,
+            MemoryAsset::from("live"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "second",
+            path,
+            MemoryAsset::from("preview"),
+        );
+
+    // Disabled unless a token is configured
+    let config = ServerConfig::default();
+    let app = test_app(&config, factory.clone()).await;
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1")
+        .insert_header(("X-Pageshelf-Branch", "second"))
+        .insert_header(("X-Pageshelf-Token", ""))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get(header::VARY).is_none());
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "live");
+
+    let config = ServerConfig {
+        preview_token: Some("nya".to_string()),
+        ..ServerConfig::default()
+    };
+    let app = test_app(&config, factory).await;
+    for (token, expected) in [
+        (None, "live"),
+        (Some("meow"), "live"),
+        (Some("nya"), "preview"),
+    ] {
+        let mut req = test::TestRequest::get()
+            .uri("/owner_1/name_1")
+            .insert_header(("X-Pageshelf-Branch", "second"));
+        if let Some(token) = token {
+            req = req.insert_header(("X-Pageshelf-Token", token));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            resp.headers().get(header::VARY).unwrap(),
+            "x-pageshelf-branch"
+        );
+        if expected == "preview" {
+            assert_eq!(
+                resp.headers().get(header::CACHE_CONTROL).unwrap(),
+                "private, no-store"
+            );
+        }
+        let body = test::read_body(resp).await;
+        assert_eq!(body, expected, "Token {:?}", token);
+    }
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "server")]
+
+use std::time::Duration;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    Page, PageError, PageSource, PageSourceFactory, PageSourceLayer,
+    conf::ServerConfig,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Fails every page lookup, as a provider does while it's holding off a rate limited upstream.
+#[derive(Clone)]
+struct RateLimitedLayer;
+
+struct RateLimitedSource<PS: PageSource> {
+    upstream: PS,
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for RateLimitedLayer {
+    type Source = RateLimitedSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        RateLimitedSource {
+            upstream: page_source,
+        }
+    }
+}
+
+impl<PS: PageSource> PageSource for RateLimitedSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        if owner == "owner_1" {
+            return Err(PageError::ProviderError);
+        }
+        self.upstream.page_at(owner, name, branch).await
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        self.upstream.pages().await
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        Some(Duration::from_millis(29_500))
+    }
+}
+
+/// Ensure clients are told when to retry while upstream is rate limiting
+#[tokio::test]
+async fn page_rate_limited() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory().wrap(RateLimitedLayer);
+    assert!(factory.build().retry_after().is_some());
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+    // Rounded up, so clients don't come back early
+    assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+    // Only failed requests carry it
+    let req = test::TestRequest::get()
+        .uri("/owner_2/name_2/asset_2")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp.headers().get(header::RETRY_AFTER).is_none());
+}
@@ -0,0 +1,103 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure `_redirects` rules redirect and rewrite paths that don't match an asset
+#[tokio::test]
+async fn page_redirects() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.redirects.enabled = true;
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/_redirects"),
+        MemoryAsset::from(
+            "/old /asset_1\n/app /asset_1 200\n/asset_1 /nowhere 302\n\
+             /files/* /:splat 200\n/moved/:name /:name 308",
+        ),
+    );
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/old")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 301);
+    assert_eq!(
+        resp.headers().get(header::LOCATION).unwrap(),
+        "/owner_1/name_1/asset_1"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/app")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "data_1");
+
+    // Placeholders are filled in from the path
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/files/asset_1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "data_1");
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/moved/asset_1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 308);
+    assert_eq!(
+        resp.headers().get(header::LOCATION).unwrap(),
+        "/owner_1/name_1/asset_1"
+    );
+
+    // Assets take priority over rules
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+/// Ensure rewrite loops stop with 508 Loop Detected
+#[tokio::test]
+async fn page_redirects_loop() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig::default();
+    config.redirects.enabled = true;
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/_redirects"),
+        MemoryAsset::from("/loop /loop 200\n/ping /pong 200\n/pong /ping 200"),
+    );
+    let app = test_app(&config, factory).await;
+
+    for uri in ["/owner_1/name_1/loop", "/owner_1/name_1/ping"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 508, "Requesting {}", uri);
+    }
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "server")]
+
+use std::{path::Path, sync::Arc};
+
+use actix_web::{App, http::header::ContentType, test, web};
+use arc_swap::ArcSwap;
+use pageshelf::{
+    PageSourceFactory,
+    conf::ServerConfig,
+    frontend::{routes::RoutingState, setup_shared_service_config},
+    provider::memory::MemoryAsset,
+    testing::create_example_provider_factory,
+};
+
+/// Ensure swapping the routing state applies the new config to requests that follow
+#[tokio::test]
+async fn page_reload_config() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let provider = Arc::new(
+        create_example_provider_factory()
+            .with_asset(
+                "owner_1",
+                "name_1",
+                "pages",
+                Path::new("/secret.txt"),
+                MemoryAsset::from("meow"),
+            )
+            .build(),
+    );
+
+    let state = web::Data::new(ArcSwap::from_pointee(
+        RoutingState::new(&config, provider.clone(), config.url_resolver(), None).unwrap(),
+    ));
+    let app = test::init_service(App::new().configure({
+        let state = state.clone();
+        move |f| {
+            setup_shared_service_config(f, state);
+        }
+    }))
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/secret.txt")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let reloaded = ServerConfig {
+        deny_paths: vec!["secret.txt".to_string()],
+        ..config
+    };
+    state.store(Arc::new(
+        RoutingState::new(&reloaded, provider, reloaded.url_resolver(), None).unwrap(),
+    ));
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/secret.txt")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "server")]
+
+use actix_web::{http::header::ContentType, test};
+use pageshelf::{
+    conf::ServerConfig,
+    frontend::request_id::REQUEST_ID_HEADER,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure request IDs are echoed back, or generated if missing or unusable
+#[tokio::test]
+async fn request_id_echo() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let factory = create_example_provider_factory();
+    let config = ServerConfig::default();
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header(ContentType::plaintext())
+        .insert_header((REQUEST_ID_HEADER, "meow-123"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "meow-123");
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get(REQUEST_ID_HEADER).is_some());
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/missing")
+        .insert_header(ContentType::plaintext())
+        .insert_header((REQUEST_ID_HEADER, "not valid"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_ne!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "not valid");
+}
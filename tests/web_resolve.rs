@@ -0,0 +1,176 @@
+#![cfg(feature = "server")]
+
+use std::{path::Path, str::FromStr};
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    frontend::routes::{
+        errors::JsonError,
+        resolve::{RESOLVE_PATH, Resolution},
+    },
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+use url::Url;
+
+/// Ensure resolutions are only explained with the admin token, and never serve content
+#[tokio::test]
+async fn page_resolve() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let uri = |url: &str| format!("{}?url={}", RESOLVE_PATH, url);
+
+    // Disabled without a token
+    let app = test_app(&ServerConfig::default(), create_example_provider_factory()).await;
+    let req = test::TestRequest::get()
+        .uri(&uri("http://localhost/"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let config = ServerConfig {
+        admin_token: Some("meow".to_string()),
+        url: Some(Url::from_str("http://example.domain").unwrap()),
+        pages_urls: Some(vec![Url::from_str("http://example.domain").unwrap()]),
+        allow_domains: true,
+        ..ServerConfig::default()
+    };
+    let app = test_app(&config, create_example_provider_factory()).await;
+
+    let req = test::TestRequest::get()
+        .uri(&uri("http://name_1.owner_1.example.domain/asset_1"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 401);
+
+    // Errors are JSON, like the resolutions
+    let req = test::TestRequest::get()
+        .uri(&uri("nya"))
+        .insert_header((header::AUTHORIZATION, "Bearer meow"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let error: JsonError = test::read_body_json(resp).await;
+    assert_eq!(error.code, 400);
+    assert!(error.error.starts_with("Invalid URL"));
+
+    let resolve = async |url: &str| -> Resolution {
+        let req = test::TestRequest::get()
+            .uri(&uri(url))
+            .insert_header((header::AUTHORIZATION, "Bearer meow"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200, "Resolving {}", url);
+        test::read_body_json(resp).await
+    };
+
+    let resolution = resolve("http://name_1.owner_1.example.domain/asset_1").await;
+    assert_eq!(
+        resolution,
+        Resolution {
+            resolution: "page".to_string(),
+            page_domain: Some("example.domain".to_string()),
+            owner: Some("owner_1".to_string()),
+            repo: Some("name_1".to_string()),
+            branch: Some("pages".to_string()),
+            asset: Some("/asset_1".to_string()),
+            page_exists: Some(true),
+            asset_exists: Some(true),
+            ..Resolution::default()
+        }
+    );
+
+    let resolution = resolve("http://name_1.owner_1.example.domain/missing").await;
+    assert_eq!(resolution.page_exists, Some(true));
+    assert_eq!(resolution.asset_exists, Some(false));
+
+    let resolution = resolve("http://name_1.owner_9.example.domain/").await;
+    assert_eq!(resolution.page_exists, Some(false));
+    assert_eq!(resolution.asset_exists, None);
+
+    let resolution = resolve("http://example.domain/").await;
+    assert_eq!(resolution.resolution, "builtin");
+    assert_eq!(resolution.owner, None);
+
+    // Custom domains are looked up like they're served
+    let resolution = resolve("http://other.domain/asset_1").await;
+    assert_eq!(resolution.resolution, "external");
+    assert_eq!(resolution.page_domain, None);
+    assert_eq!(resolution.via, None);
+    assert_eq!(resolution.page_exists, None);
+}
+
+/// Ensure resolutions follow the page's config, and strict hosts, like serving does
+#[tokio::test]
+async fn page_resolve_served() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let mut config = ServerConfig {
+        admin_token: Some("meow".to_string()),
+        url: Some(Url::from_str("http://example.domain").unwrap()),
+        pages_urls: Some(vec![Url::from_str("http://pages.domain").unwrap()]),
+        allow_domains: true,
+        strict_host: true,
+        ..ServerConfig::default()
+    };
+    config.page_config.enabled = true;
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/.pageshelf.toml"),
+            MemoryAsset::from("index = [\"home.html\"]\nbranches = [\"preview-*\"]"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/docs/home.html"),
+            MemoryAsset::from("home"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "secret",
+            Path::new("/index.html"),
+            MemoryAsset::from("secret"),
+        );
+    let app = test_app(&config, factory).await;
+
+    let resolve = async |url: &str| -> Resolution {
+        let req = test::TestRequest::get()
+            .uri(&format!("{}?url={}", RESOLVE_PATH, url))
+            .insert_header((header::AUTHORIZATION, "Bearer meow"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200, "Resolving {}", url);
+        test::read_body_json(resp).await
+    };
+
+    // The page's own index files
+    let resolution = resolve("http://name_1.owner_1.pages.domain/docs/").await;
+    assert_eq!(resolution.page_domain, Some("pages.domain".to_string()));
+    assert_eq!(resolution.asset_exists, Some(true));
+
+    // Denied assets aren't served, even if they exist
+    let resolution = resolve("http://name_1.owner_1.pages.domain/.pageshelf.toml").await;
+    assert_eq!(resolution.asset_exists, Some(false));
+
+    // Branches the page doesn't allow
+    let resolution = resolve("http://example.domain/owner_1/name_1:secret/").await;
+    assert_eq!(resolution.branch, Some("secret".to_string()));
+    assert_eq!(resolution.page_exists, Some(false));
+
+    // Unclaimed domains are misdirected in strict mode
+    let resolution = resolve("http://other.domain/").await;
+    assert_eq!(resolution.resolution, "misdirected");
+    assert_eq!(resolution.detail, Some("other.domain".to_string()));
+}
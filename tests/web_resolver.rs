@@ -0,0 +1,69 @@
+#![cfg(feature = "server")]
+
+use std::{str::FromStr, sync::Arc};
+
+use actix_web::{App, test};
+use pageshelf::{
+    PageAssetLocation, PageLocation, PageSourceFactory,
+    conf::ServerConfig,
+    frontend::setup_service_config,
+    resolver::{BoxedUrlResolver, ChainResolver, UrlResolution, UrlResolver},
+    testing::create_example_provider_factory,
+};
+use url::Url;
+
+/// Serves `alias.domain` from `owner_2/name_2`, deferring anything else.
+struct AliasResolver;
+
+impl UrlResolver for AliasResolver {
+    fn resolve(&self, url: Url) -> UrlResolution {
+        match url.host_str() {
+            Some("alias.domain") => UrlResolution::Page(PageAssetLocation {
+                page: PageLocation {
+                    owner: "owner_2".to_string(),
+                    name: "name_2".to_string(),
+                    branch: "pages".to_string(),
+                },
+                asset: url.path().to_string(),
+            }),
+            _ => UrlResolution::BuiltIn,
+        }
+    }
+}
+
+/// Ensure services can be set up with a resolver picked at runtime
+#[tokio::test]
+async fn page_boxed_resolver() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        url: Some(Url::from_str("http://example.domain").unwrap()),
+        ..ServerConfig::default()
+    };
+    let app = test::init_service(App::new().configure(|f| {
+        let provider = Arc::new(create_example_provider_factory().build());
+        let resolver: BoxedUrlResolver = Box::new(
+            ChainResolver::new()
+                .with(AliasResolver)
+                .with(config.url_resolver()),
+        );
+        setup_service_config(f, &config, provider, resolver, None);
+    }))
+    .await;
+
+    for (host, uri, expected) in [
+        ("alias.domain", "/asset_2", 200),
+        ("alias.domain", "/asset_1", 404),
+        ("example.domain", "/owner_1/name_1/asset_1", 200),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Host", host))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), expected, "{}{}", host, uri);
+    }
+}
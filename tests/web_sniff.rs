@@ -0,0 +1,72 @@
+#![cfg(feature = "sniff")]
+
+use std::path::Path;
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+const HTML: &str = "<!DOCTYPE html><html><body>meow</body></html>";
+
+/// Ensure extensionless assets are only sniffed if enabled, and neither the provider
+/// nor the file name tells their type
+#[tokio::test]
+async fn page_sniff_extensionless() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/about"),
+            MemoryAsset::from(HTML),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/about.nya"),
+            MemoryAsset::from(HTML),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/plain"),
+            MemoryAsset::from(HTML).with_mime_type("text/plain"),
+        );
+
+    for (sniff, uri, expected) in [
+        (false, "/owner_1/name_1/about", "application/octet-stream"),
+        (true, "/owner_1/name_1/about", "text/html"),
+        (
+            true,
+            "/owner_1/name_1/about.nya",
+            "application/octet-stream",
+        ),
+        (true, "/owner_1/name_1/plain", "text/plain"),
+    ] {
+        let config = ServerConfig {
+            sniff,
+            ..ServerConfig::default()
+        };
+        let app = test_app(&config, factory.clone()).await;
+
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), expected);
+        // The type is still decided by the server, so browsers still shouldn't sniff
+        assert_eq!(
+            resp.headers().get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+            "nosniff"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "server")]
+
+use actix_web::{http::header, test};
+use pageshelf::{
+    conf::ServerConfig,
+    frontend::routes::{
+        errors::JsonError,
+        stats::{STATS_PATH, Stats},
+    },
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// Ensure statistics are only available with the admin token, and add up
+#[tokio::test]
+async fn page_stats() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    // Disabled without a token
+    let config = ServerConfig::default();
+    let app = test_app(&config, create_example_provider_factory()).await;
+    let req = test::TestRequest::get()
+        .uri(STATS_PATH)
+        .insert_header((header::AUTHORIZATION, "Bearer "))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let config = ServerConfig {
+        admin_token: Some("meow".to_string()),
+        ..ServerConfig::default()
+    };
+    let app = test_app(&config, create_example_provider_factory()).await;
+
+    for auth in [None, Some("Bearer nya"), Some("meow")] {
+        let mut req = test::TestRequest::get().uri(STATS_PATH);
+        if let Some(auth) = auth {
+            req = req.insert_header((header::AUTHORIZATION, auth));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401, "Authorization: {:?}", auth);
+        assert_eq!(
+            resp.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Bearer"
+        );
+        let error: JsonError = test::read_body_json(resp).await;
+        assert_eq!(error.code, 401);
+    }
+
+    let req = test::TestRequest::get()
+        .uri(STATS_PATH)
+        .insert_header((header::AUTHORIZATION, "Bearer meow"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let stats: Stats = test::read_body_json(resp).await;
+    assert_eq!(stats.pages, 2);
+    assert_eq!(stats.owners, 2);
+    assert_eq!(stats.total_bytes, ("data_1".len() + "data_2".len()) as u64);
+    assert_eq!(stats.pages_unsized, 0);
+    // The memory provider doesn't scan anything.
+    assert!(stats.last_scan.is_none());
+}
@@ -1,11 +1,13 @@
-use std::{path::Path, str::FromStr, sync::Arc};
+#![cfg(feature = "server")]
 
-use actix_web::{App, http::header::ContentType, test};
+use std::{path::Path, str::FromStr};
+
+use actix_web::{http::header::ContentType, test};
 use pageshelf::{
-    Asset, PageSourceFactory,
+    Asset,
     conf::ServerConfig,
-    frontend::setup_service_config,
-    provider::{memory::MemoryAsset, testing::create_example_provider_factory},
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
 };
 use url::Url;
 
@@ -44,11 +46,7 @@ async fn exec_subdomain_default_user(config: &ServerConfig) {
         )
         .with_asset("owner_1", "pages", "pages", path_long, asset_index.clone());
 
-    let app = test::init_service(App::new().configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(config, factory).await;
 
     // Owner 1 has a default page, should succeed
     let req = test::TestRequest::get()
@@ -138,11 +136,7 @@ async fn page_subdomain_specific() {
             asset_2.clone(),
         );
 
-    let app = test::init_service(App::new().configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     // Owner 1 has a default page, should succeed
     let req = test::TestRequest::get()
@@ -195,6 +189,8 @@ async fn page_subdomain_specific() {
     assert_eq!(resp.status().as_u16(), 404);
 }
 
+/// Ensure the built-in index is preferred at the root by default (`root_prefers_builtin`),
+/// while the default user's page is still served on its subdomain
 #[tokio::test]
 async fn page_base_priority() {
     let _ = env_logger::builder()
@@ -212,6 +208,53 @@ async fn page_base_priority() {
     exec_base_priority(&config).await;
 }
 
+/// Ensure the root serves the default user's page if it doesn't prefer the built-in index
+#[tokio::test]
+async fn page_base_priority_default_user() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig {
+        default_user: "user".to_string(),
+        root_prefers_builtin: false,
+        url: Some(Url::from_str("https://example.domain").unwrap()),
+        pages_urls: Some(vec![Url::from_str("https://example.domain").unwrap()]),
+        ..ServerConfig::default()
+    };
+
+    let asset = MemoryAsset::from("meow");
+    let factory = create_example_provider_factory().with_asset(
+        "user",
+        "pages",
+        "pages",
+        Path::new("/index.html"),
+        asset.clone(),
+    );
+    let app = test_app(&config, factory).await;
+
+    for host in ["example.domain", "user.example.domain"] {
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header(("Host", host))
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "Requesting {}", host);
+        let body = test::read_body(resp).await;
+        assert_eq!(body, asset.body().unwrap(), "Requesting {}", host);
+    }
+
+    // Other owners are still served at their own paths
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/asset_1")
+        .insert_header(("Host", "example.domain"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
 async fn exec_base_priority(config: &ServerConfig) {
     let _ = env_logger::builder()
         .is_test(true)
@@ -226,11 +269,7 @@ async fn exec_base_priority(config: &ServerConfig) {
         .with_asset("user", "pages", "pages", path, asset.clone())
         .with_asset("user", "pages", "pages", path_long, asset.clone());
 
-    let app = test::init_service(App::new().configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(config, factory).await;
 
     // Should respond with the built-in index over a pages index
     let req = test::TestRequest::get()
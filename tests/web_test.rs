@@ -1,11 +1,19 @@
-use std::{path::Path, sync::Arc};
+#![cfg(feature = "server")]
 
-use actix_web::{App, http::header::ContentType, middleware::NormalizePath, test};
+use std::path::Path;
+
+use actix_web::{
+    http::{
+        Method,
+        header::{self, ContentType},
+    },
+    test,
+};
 use pageshelf::{
     AssetSource,
     conf::ServerConfig,
-    frontend::setup_service_config,
-    provider::{memory::MemoryAsset, testing::create_example_provider_factory},
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
     {PageSource, PageSourceFactory},
 };
 
@@ -24,11 +32,7 @@ async fn page_server_index() {
 
     let config = ServerConfig::default();
 
-    let app = test::init_service(App::new().configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     let req = test::TestRequest::get()
         .uri("/")
@@ -53,11 +57,7 @@ async fn page_access_owner_name_asset() {
     let config = ServerConfig::default();
     let factory = create_example_provider_factory();
 
-    let app = test::init_service(App::new().wrap(NormalizePath::trim()).configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     let req = test::TestRequest::get()
         .uri("/owner_1/name_1/asset_1")
@@ -101,11 +101,7 @@ async fn page_access_index() {
             .is_ok()
     );
 
-    let app = test::init_service(App::new().wrap(NormalizePath::trim()).configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     let req = test::TestRequest::get()
         .uri("/owner_1/name_1")
@@ -133,11 +129,7 @@ async fn page_access_no_index() {
     let config = ServerConfig::default();
     let factory = create_example_provider_factory();
 
-    let app = test::init_service(App::new().wrap(NormalizePath::trim()).configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     let req = test::TestRequest::get()
         .uri("/owner_1/name_1")
@@ -154,6 +146,224 @@ async fn page_access_no_index() {
     assert_eq!(resp.status().as_u16(), 404);
 }
 
+/// Ensure directories are inferred from the page, not the local filesystem
+#[tokio::test]
+async fn page_access_local_dir() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    // A directory on the server, but a file in the page
+    let local = std::env::temp_dir();
+    assert!(local.is_dir());
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            &local,
+            MemoryAsset::from("file"),
+        )
+        // A directory in the page, but nowhere on the server
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/nowhere-local/index.html"),
+            MemoryAsset::from("index"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/owner_1/name_1{}", local.display()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(test::read_body(resp).await, "file");
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/nowhere-local")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(test::read_body(resp).await, "index");
+}
+
+/// Ensure indexes are served (only) when the page has them, whatever exists on the server
+#[tokio::test]
+async fn page_access_local_dir_index() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let local = Path::new(env!("CARGO_MANIFEST_DIR"));
+    assert!(local.join("src").is_dir());
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        &local.join("index.html"),
+        MemoryAsset::from("index"),
+    );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/owner_1/name_1{}", local.display()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(test::read_body(resp).await, "index");
+
+    // Local directories aren't anything to the page
+    let req = test::TestRequest::get()
+        .uri(&format!("/owner_1/name_1{}/src", local.display()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+/// Ensure paths that are both a file and a directory serve what the server prefers
+#[tokio::test]
+async fn page_access_file_and_dir() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/docs"),
+            MemoryAsset::from("file"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/docs/index.html"),
+            MemoryAsset::from("index"),
+        );
+
+    for (prefer_index, expected) in [(false, "file"), (true, "index")] {
+        let config = ServerConfig {
+            prefer_index,
+            ..ServerConfig::default()
+        };
+        let app = test_app(&config, factory.clone()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/owner_1/name_1/docs")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(test::read_body(resp).await, expected);
+    }
+}
+
+/// Ensure directory indexes are only fallen back to if enabled, except at the page root
+#[tokio::test]
+async fn page_access_index_fallback() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/index.html"),
+            MemoryAsset::from("root"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/docs/index.html"),
+            MemoryAsset::from("index"),
+        );
+
+    for (index_fallback, prefer_index, docs) in [
+        (true, false, 200),
+        (true, true, 200),
+        (false, false, 404),
+        (false, true, 404),
+    ] {
+        let config = ServerConfig {
+            index_fallback,
+            prefer_index,
+            ..ServerConfig::default()
+        };
+        let app = test_app(&config, factory.clone()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/owner_1/name_1/docs")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), docs);
+
+        let req = test::TestRequest::get().uri("/owner_1/name_1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(test::read_body(resp).await, "root");
+
+        // Assets are served either way
+        let req = test::TestRequest::get()
+            .uri("/owner_1/name_1/asset_1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+}
+
+/// Ensure the MIME type a provider gives an asset wins over guessing from its name
+#[tokio::test]
+async fn page_access_provided_mime() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/page.txt"),
+            MemoryAsset::from("<p>meow</p>").with_mime_type("text/html"),
+        )
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/notes.txt"),
+            MemoryAsset::from("meow"),
+        );
+
+    let app = test_app(&config, factory).await;
+
+    for (uri, expected) in [
+        ("/owner_1/name_1/page.txt", "text/html"),
+        ("/owner_1/name_1/notes.txt", "text/plain"),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let content_type = resp.headers().get(header::CONTENT_TYPE).unwrap();
+        assert_eq!(content_type, expected);
+    }
+}
+
 /// Ensure that specific branches of a Page are accessible
 #[tokio::test]
 async fn page_access_branch() {
@@ -206,11 +416,7 @@ async fn page_access_branch() {
             .is_ok()
     );
 
-    let app = test::init_service(App::new().wrap(NormalizePath::trim()).configure(move |f| {
-        let provider = Arc::new(factory.build());
-        setup_service_config(f, &config, provider, config.url_resolver(), None);
-    }))
-    .await;
+    let app = test_app(&config, factory).await;
 
     let req = test::TestRequest::get()
         .uri("/owner_1/name_1/index.html")
@@ -254,3 +460,72 @@ async fn page_access_branch() {
     let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status().as_u16(), 404);
 }
+
+/// Ensure percent-encoded asset paths are decoded (once) before lookup
+#[tokio::test]
+async fn page_access_percent_encoded() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory().with_asset(
+        "owner_1",
+        "name_1",
+        "pages",
+        Path::new("/My File.html"),
+        MemoryAsset::from("meow"),
+    );
+
+    let app = test_app(&config, factory).await;
+
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/My%20File.html")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    // Doubly-encoded paths must not be decoded twice
+    let req = test::TestRequest::get()
+        .uri("/owner_1/name_1/My%2520File.html")
+        .insert_header(ContentType::plaintext())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+/// Ensure unsupported methods are rejected, pointing to the supported ones
+#[tokio::test]
+async fn page_access_methods() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory();
+
+    let app = test_app(&config, factory).await;
+
+    for (method, status) in [
+        (Method::GET, 200),
+        (Method::HEAD, 200),
+        (Method::POST, 405),
+        (Method::PUT, 405),
+        (Method::DELETE, 405),
+    ] {
+        for uri in ["/owner_1/name_1/asset_1", "/pages_favicon.webp"] {
+            let req = test::TestRequest::default()
+                .method(method.clone())
+                .uri(uri)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), status, "{} {}", method, uri);
+            if status == 405 {
+                assert_eq!(resp.headers().get(header::ALLOW).unwrap(), "GET, HEAD");
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+#![cfg(feature = "server")]
+
+use std::path::Path;
+
+use actix_web::test;
+use pageshelf::{
+    Asset, AssetError, AssetSource, Page, PageError, PageSource, PageSourceFactory,
+    PageSourceLayer,
+    conf::ServerConfig,
+    provider::memory::MemoryAsset,
+    testing::{create_example_provider_factory, test_app},
+};
+
+/// The largest asset (in bytes) the capped pages serve.
+const MAX_SIZE: usize = 8;
+
+/// Refuses to serve assets over [`MAX_SIZE`], like a size-capped provider would.
+#[derive(Clone)]
+struct SizeCapLayer;
+
+struct SizeCapSource<PS: PageSource> {
+    upstream: PS,
+}
+
+struct SizeCapPage<P: Page> {
+    upstream: P,
+}
+
+impl<PS: PageSource> PageSourceLayer<PS> for SizeCapLayer {
+    type Source = SizeCapSource<PS>;
+
+    fn wrap(&self, page_source: PS) -> Self::Source {
+        SizeCapSource {
+            upstream: page_source,
+        }
+    }
+}
+
+impl<PS: PageSource> PageSource for SizeCapSource<PS> {
+    async fn page_at(
+        &self,
+        owner: String,
+        name: String,
+        branch: String,
+    ) -> Result<impl Page, PageError> {
+        let upstream = self.upstream.page_at(owner, name, branch).await?;
+        Ok(SizeCapPage { upstream })
+    }
+
+    async fn pages(&self) -> Result<impl Iterator<Item = impl Page>, PageError> {
+        let pages = self.upstream.pages().await?;
+        Ok(pages.map(|upstream| SizeCapPage { upstream }))
+    }
+}
+
+impl<P: Page> Page for SizeCapPage<P> {
+    fn name(&self) -> &str {
+        self.upstream.name()
+    }
+
+    fn branch(&self) -> &str {
+        self.upstream.branch()
+    }
+
+    fn owner(&self) -> &str {
+        self.upstream.owner()
+    }
+
+    fn version(&self) -> &str {
+        self.upstream.version()
+    }
+}
+
+impl<P: Page> AssetSource for SizeCapPage<P> {
+    async fn get_asset(&self, path: &Path) -> Result<impl Asset, AssetError> {
+        let asset = self.upstream.get_asset(path).await?;
+        if asset.bytes().len() > MAX_SIZE {
+            return Err(AssetError::TooLarge);
+        }
+        Ok(asset)
+    }
+}
+
+/// Ensure assets over a size cap respond 413, rather than looking missing
+#[tokio::test]
+async fn page_too_large() {
+    let _ = env_logger::builder()
+        .is_test(true)
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+
+    let config = ServerConfig::default();
+    let factory = create_example_provider_factory()
+        .with_asset(
+            "owner_1",
+            "name_1",
+            "pages",
+            Path::new("/big.bin"),
+            MemoryAsset::from("a".repeat(MAX_SIZE + 1)),
+        )
+        .wrap(SizeCapLayer);
+    let app = test_app(&config, factory).await;
+
+    for (uri, status) in [
+        ("/owner_1/name_1/asset_1", 200),
+        ("/owner_1/name_1/big.bin", 413),
+        ("/owner_1/name_1/missing.bin", 404),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), status, "Requesting {}", uri);
+    }
+}